@@ -0,0 +1,368 @@
+use super::ppu::Region;
+use super::error::EmuError;
+use super::snapshot::{StateReader, StateWriter};
+
+// There's no sound (yet!), but plenty of games wait on the APU regardless - either polling 0x4015 until a
+// channel's length counter runs out, or sitting in a loop until the frame counter's IRQ fires - so this keeps
+// just enough of it going for them to boot: the frame counter, the length counters and the frame IRQ.
+
+// What a write to the top five bits of a channel's fourth register loads into its length counter
+const LENGTH_TABLE: [u8; 32] =
+[
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+// Pulse 1, pulse 2, triangle and noise (the DMC doesn't have a length counter)
+const CHANNELS: usize = 4;
+
+// What the output's resampled to unless the audio device wants something else
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FrameCounterMode
+{
+    FourStep,
+    FiveStep
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterKind
+{
+    HighPass,
+    LowPass
+}
+
+// The NES doesn't output the mixer's signal as-is: it goes through a couple of RC high-pass filters (which take
+// out DC offset and some of the bass) and an RC low-pass (which takes the edge off the square waves) on its way
+// to the TV. Each is about as simple as a filter gets - one pole, run once per CPU cycle - and the coefficients
+// depend on the clock rate, so they're worked out (again) whenever that changes.
+#[derive(Copy, Clone)]
+pub struct OnePoleFilter
+{
+    kind: FilterKind,
+    cutoff: f64, // In Hz
+    clock_rate: f64,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32
+}
+
+impl OnePoleFilter
+{
+    pub fn from_cutoff(kind: FilterKind, cutoff: f64) -> Self
+    {
+        OnePoleFilter { kind, cutoff, clock_rate: 0.0, alpha: 0.0, previous_input: 0.0, previous_output: 0.0 }
+    }
+
+    // Takes one sample at "clock_rate" samples per second, and gives back the filtered one
+    pub fn process(&mut self, input: f32, clock_rate: f64) -> f32
+    {
+        if clock_rate != self.clock_rate
+        {
+            let rc = 1.0 / (2.0 * std::f64::consts::PI * self.cutoff);
+            let dt = 1.0 / clock_rate;
+            self.alpha = match self.kind
+            {
+                FilterKind::HighPass => rc / (rc + dt),
+                FilterKind::LowPass => dt / (rc + dt)
+            } as f32;
+            self.clock_rate = clock_rate;
+        }
+
+        let output = match self.kind
+        {
+            FilterKind::HighPass => self.alpha * (self.previous_output + input - self.previous_input),
+            FilterKind::LowPass => self.previous_output + self.alpha * (input - self.previous_output)
+        };
+
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+// The APU's output changes every CPU cycle (~1.79 MHz), which is far more often than anything can play, so each
+// output sample's the average of however many cycles it covers - a box filter, which keeps the worst of the
+// aliasing out, and is about as cheap a low-pass as there is. The number of cycles per sample won't be whole, so
+// the remainder's carried over, which keeps the rate right on average.
+#[derive(Copy, Clone)]
+pub struct Resampler
+{
+    sample_rate: u32,
+    cycles_until_sample: f64,
+    sum: f32,
+    count: u32
+}
+
+impl Resampler
+{
+    pub fn from_sample_rate(sample_rate: u32) -> Self
+    {
+        Resampler { sample_rate, cycles_until_sample: 0.0, sum: 0.0, count: 0 }
+    }
+
+    pub fn get_sample_rate(&self) -> u32
+    {
+        self.sample_rate
+    }
+
+    // Takes one CPU cycle's worth of output, and gives back a sample whenever enough have built up
+    pub fn push(&mut self, input: f32, region: Region) -> Option<f32>
+    {
+        self.sum += input;
+        self.count += 1;
+        self.cycles_until_sample -= 1.0;
+        if self.cycles_until_sample > 0.0 { return None }
+
+        let sample = self.sum / self.count as f32;
+        self.cycles_until_sample += region.cpu_clock_rate() / self.sample_rate as f64;
+        self.sum = 0.0;
+        self.count = 0;
+        Some(sample)
+    }
+}
+
+#[derive(Clone)]
+pub struct Apu
+{
+    pub frame_counter_mode: FrameCounterMode,
+    pub frame_irq_inhibit: bool,
+    pub frame_irq: bool,       // Raised at the end of a 4-step sequence, and held until 0x4015's read
+    pub frame_counter_cycles: u32, // CPU cycles into the current sequence
+    pub length_counters: [u8; CHANNELS],
+    pub length_halted: [bool; CHANNELS], // Set by the same bit that makes the envelope loop
+    pub channels_enabled: [bool; CHANNELS],
+    pub envelope_restarts: [bool; CHANNELS], // Set by length loads; for the triangle, it's the linear counter's reload flag instead
+    pub pulse_sequence_steps: [u8; 2], // How far through its duty cycle each pulse channel is
+    pub filters: [OnePoleFilter; 3],
+    pub filters_enabled: bool, // Off for the mixer's output as it is, harshness and all
+    pub resampler: Resampler,
+    pub samples: Vec<f32> // Output since the frontend last took it, at the resampler's rate
+}
+
+impl Default for Apu
+{
+    fn default() -> Self
+    {
+        Apu
+        {
+            frame_counter_mode: FrameCounterMode::FourStep,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_counter_cycles: 0,
+            length_counters: [0; CHANNELS],
+            length_halted: [false; CHANNELS],
+            channels_enabled: [false; CHANNELS],
+            envelope_restarts: [false; CHANNELS],
+            pulse_sequence_steps: [0; 2],
+            filters:
+            [
+                OnePoleFilter::from_cutoff(FilterKind::HighPass, 90.0),
+                OnePoleFilter::from_cutoff(FilterKind::HighPass, 440.0),
+                OnePoleFilter::from_cutoff(FilterKind::LowPass, 14000.0)
+            ],
+            filters_enabled: true,
+            resampler: Resampler::from_sample_rate(DEFAULT_SAMPLE_RATE),
+            samples: Vec::new()
+        }
+    }
+}
+
+impl Apu
+{
+    // The filters and resampler only shape what's heard, so they're left as they are (they'd settle again within
+    // a few samples anyway)
+    pub fn save_state(&self, state: &mut StateWriter)
+    {
+        state.write_bool(self.frame_counter_mode == FrameCounterMode::FiveStep);
+        state.write_bool(self.frame_irq_inhibit);
+        state.write_bool(self.frame_irq);
+        state.write_u32(self.frame_counter_cycles);
+        state.write_bytes(&self.length_counters);
+        for i in 0..CHANNELS
+        {
+            state.write_bool(self.length_halted[i]);
+            state.write_bool(self.channels_enabled[i]);
+            state.write_bool(self.envelope_restarts[i]);
+        }
+        state.write_bytes(&self.pulse_sequence_steps);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>
+    {
+        self.frame_counter_mode = if state.read_bool()? { FrameCounterMode::FiveStep } else { FrameCounterMode::FourStep };
+        self.frame_irq_inhibit = state.read_bool()?;
+        self.frame_irq = state.read_bool()?;
+        self.frame_counter_cycles = state.read_u32()?;
+        state.read_into(&mut self.length_counters)?;
+        for i in 0..CHANNELS
+        {
+            self.length_halted[i] = state.read_bool()?;
+            self.channels_enabled[i] = state.read_bool()?;
+            self.envelope_restarts[i] = state.read_bool()?;
+        }
+        state.read_into(&mut self.pulse_sequence_steps)?;
+        Ok(())
+    }
+
+    // Runs once per CPU cycle, so everything's timed in those (see "get_frame_counter_steps")
+    pub fn execute(&mut self, region: Region)
+    {
+        let mut output = self.mix();
+        if self.filters_enabled
+        {
+            for filter in self.filters.iter_mut() {
+                output = filter.process(output, region.cpu_clock_rate());
+            }
+        }
+
+        // If more than a second's worth builds up, nothing's listening (as when running headlessly), so the
+        // rest go nowhere rather than piling up forever
+        if let Some(sample) = self.resampler.push(output, region)
+        {
+            if self.samples.len() < self.resampler.get_sample_rate() as usize { self.samples.push(sample); }
+        }
+
+        self.frame_counter_cycles += 1;
+
+        let steps = Self::get_frame_counter_steps(region);
+        let cycle = self.frame_counter_cycles;
+
+        match self.frame_counter_mode
+        {
+            FrameCounterMode::FourStep =>
+            {
+                // The last step's the only one with an IRQ, and as the sequence starts again straight after it,
+                // it's where the cycle count wraps
+                if cycle == steps[1] { self.clock_half_frame(); }
+                if cycle == steps[3]
+                {
+                    self.clock_half_frame();
+                    if !self.frame_irq_inhibit { self.frame_irq = true; }
+                    self.frame_counter_cycles = 0;
+                }
+            }
+
+            FrameCounterMode::FiveStep =>
+            {
+                // Same again, but with a step that does nothing in place of the IRQ, and one more after it
+                if cycle == steps[1] { self.clock_half_frame(); }
+                if cycle == steps[4]
+                {
+                    self.clock_half_frame();
+                    self.frame_counter_cycles = 0;
+                }
+            }
+        }
+    }
+
+    // Picks how many samples a second end up in "samples" (whatever's already there is left as it is)
+    pub fn set_sample_rate(&mut self, sample_rate: u32)
+    {
+        self.resampler = Resampler::from_sample_rate(sample_rate);
+    }
+
+    // The channels' combined output, from 0 to 1. None of them make any sound yet, so that's silence for now,
+    // but it's still sent out at the right rate, so that everything downstream's ready for when they do.
+    fn mix(&self) -> f32
+    {
+        0.0
+    }
+
+    // Whether the CPU's IRQ line is being held low (the DMC will also pull it eventually)
+    pub fn get_irq(&self) -> bool
+    {
+        self.frame_irq
+    }
+
+    pub fn read_status(&mut self, last_bus_value: u8, debugger: bool) -> u8
+    {
+        let mut status = last_bus_value & 0x20; // Not driven
+        for (channel, length) in self.length_counters.iter().enumerate()
+        {
+            if *length > 0 { status |= 1 << channel; }
+        }
+        if self.frame_irq { status |= 0x40; }
+
+        // Reading acknowledges the IRQ
+        if !debugger { self.frame_irq = false; }
+        status
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8)
+    {
+        match address
+        {
+            // Halt flags (the triangle's is in a different place to everyone else's)
+            0x4000 | 0x4004 | 0x400c => self.length_halted[Self::channel_from_address(address)] = value & 0x20 != 0,
+            0x4008 => self.length_halted[2] = value & 0x80 != 0,
+
+            // Length counter loads, which are ignored if the channel's turned off. Either way, the write also restarts
+            // the envelope (or the triangle's linear counter), and puts the pulse channels back at the start of their
+            // duty cycles - which is why some games' notes click when they're retriggered.
+            0x4003 | 0x4007 | 0x400b | 0x400f =>
+            {
+                let channel = Self::channel_from_address(address);
+                if self.channels_enabled[channel] {
+                    self.length_counters[channel] = LENGTH_TABLE[(value >> 3) as usize];
+                }
+
+                self.envelope_restarts[channel] = true;
+                if let Some(step) = self.pulse_sequence_steps.get_mut(channel) {
+                    *step = 0;
+                }
+            }
+
+            // Turning a channel off silences it straight away
+            0x4015 =>
+            {
+                for channel in 0..CHANNELS
+                {
+                    self.channels_enabled[channel] = value & (1 << channel) != 0;
+                    if !self.channels_enabled[channel] { self.length_counters[channel] = 0; }
+                }
+            }
+
+            // Restarts the sequence, and in 5-step mode clocks everything immediately too
+            0x4017 =>
+            {
+                self.frame_counter_mode = if value & 0x80 != 0 { FrameCounterMode::FiveStep } else { FrameCounterMode::FourStep };
+                self.frame_irq_inhibit = value & 0x40 != 0;
+                if self.frame_irq_inhibit { self.frame_irq = false; }
+
+                self.frame_counter_cycles = 0;
+                if self.frame_counter_mode == FrameCounterMode::FiveStep { self.clock_half_frame(); }
+            }
+
+            _ => {}
+        }
+    }
+
+    // Quarter frames would clock the envelopes and the triangle's linear counter, neither of which exist here
+    fn clock_half_frame(&mut self)
+    {
+        for channel in 0..CHANNELS
+        {
+            if !self.length_halted[channel] && self.length_counters[channel] > 0 {
+                self.length_counters[channel] -= 1;
+            }
+        }
+    }
+
+    fn channel_from_address(address: u16) -> usize
+    {
+        ((address - 0x4000) / 4) as usize
+    }
+
+    // When each step of the sequence happens, in CPU cycles (as that's what "execute" is run on), not APU ones - which
+    // are twice as long, hence them usually being documented as 3728.5 and so on. A 4-step sequence is a frame long.
+    fn get_frame_counter_steps(region: Region) -> [u32; 5]
+    {
+        match region
+        {
+            Region::Ntsc => [7457, 14913, 22371, 29829, 37281],
+            Region::Pal => [8313, 16627, 24939, 33252, 41565]
+        }
+    }
+}
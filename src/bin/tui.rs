@@ -0,0 +1,168 @@
+use nes_emulator_rust::on_emulation_cycle;
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::memory::{Buttons, Memory};
+use nes_emulator_rust::ppu::{Ppu, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+use crossterm::{cursor, event, queue, execute, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, SetBackgroundColor, SetForegroundColor, ResetColor};
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// A frontend that draws to the terminal instead of a window, for running over SSH and the like. Each character
+// is a "▀", with its foreground being the top pixel and its background the bottom, so as terminal cells are about
+// twice as tall as they are wide, 128x60 of them give 128x120 squarish pixels - every other one of the real thing.
+
+const COLUMNS: usize = 128;
+const ROWS: usize = 60;
+
+// Only every so many frames are drawn, as even with the colours only being sent when they change, a whole screen
+// is a lot to push through a terminal (especially a remote one)
+const DRAW_EVERY: u32 = 2;
+
+// Terminals only say when a key's pressed (and then repeat it), not when it's released, so a button's held for
+// this many frames after its key was last seen, which is enough to bridge the gap before the key starts repeating
+const HOLD_FRAMES: u32 = 15;
+
+// As with the SDL frontend
+const KEY_BINDINGS: [(char, Buttons); 4] =
+[
+    ('x', Buttons::A),
+    ('z', Buttons::B),
+    ('a', Buttons::SELECT),
+    ('s', Buttons::START),
+];
+
+const ARROW_BINDINGS: [(KeyCode, Buttons); 4] =
+[
+    (KeyCode::Up,    Buttons::UP),
+    (KeyCode::Down,  Buttons::DOWN),
+    (KeyCode::Left,  Buttons::LEFT),
+    (KeyCode::Right, Buttons::RIGHT),
+];
+
+fn main()
+{
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2
+    {
+        println!("Invalid format - must run like so:");
+        println!("./nes-tui [filename.nes]");
+        std::process::exit(1);
+    }
+
+    let mut memory = match Memory::from_file(&args[1])
+    {
+        Ok(memory) => memory,
+        Err(error) => { println!("{}", error); std::process::exit(1); }
+    };
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = match Cpu::from_memory(&mut ppu, &mut memory)
+    {
+        Ok(cpu) => cpu,
+        Err(error) => { println!("{}", error); std::process::exit(1); }
+    };
+
+    let mut stdout = std::io::stdout();
+    terminal::enable_raw_mode().expect("could not put the terminal into raw mode");
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All)).unwrap();
+
+    let result = run(&mut cpu, &mut ppu, &mut memory, &mut stdout);
+
+    // Put the terminal back how it was, even if emulation went wrong
+    execute!(stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen).unwrap();
+    terminal::disable_raw_mode().unwrap();
+
+    if let Err(error) = result {
+        println!("{}", error);
+    }
+}
+
+fn run(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory, stdout: &mut std::io::Stdout) -> Result<(), Box<dyn std::error::Error>>
+{
+    let frame_duration = Duration::from_secs_f64(1.0 / ppu.region.frames_per_second());
+    let mut held_frames = [0u32; 8]; // How much longer each button's held for, indexed by bit
+    let mut frame: u32 = 0;
+
+    loop
+    {
+        let frame_start = Instant::now();
+
+        // Take in whatever keys have been pressed since last time
+        while event::poll(Duration::ZERO)?
+        {
+            if let Event::Key(key) = event::read()?
+            {
+                if key.kind == KeyEventKind::Release { continue }
+
+                // Escape, q or ctrl-c quit (raw mode means the latter doesn't do so by itself)
+                if key.code == KeyCode::Esc || key.code == KeyCode::Char('q') ||
+                    (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+                {
+                    return Ok(())
+                }
+
+                let bit = KEY_BINDINGS.iter().find(|(c, _)| key.code == KeyCode::Char(*c)).map(|(_, bit)| *bit)
+                    .or_else(|| ARROW_BINDINGS.iter().find(|(code, _)| key.code == *code).map(|(_, bit)| *bit));
+
+                if let Some(bit) = bit {
+                    held_frames[bit.bits().trailing_zeros() as usize] = HOLD_FRAMES;
+                }
+            }
+        }
+
+        let mut buttons = Buttons::empty();
+        for (i, frames) in held_frames.iter_mut().enumerate()
+        {
+            if *frames > 0
+            {
+                buttons |= Buttons::from_bits_truncate(1 << i);
+                *frames -= 1;
+            }
+        }
+        memory.set_controller(0, buttons);
+
+        on_emulation_cycle(cpu, ppu, memory)?;
+
+        frame = frame.wrapping_add(1);
+        if frame.is_multiple_of(DRAW_EVERY) {
+            draw(ppu, stdout)?;
+        }
+
+        if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+}
+
+fn draw(ppu: &Ppu, stdout: &mut std::io::Stdout) -> std::io::Result<()>
+{
+    let pixel = |x: usize, y: usize|
+    {
+        let i = (y * SCREEN_WIDTH + x) * 3;
+        Color::Rgb { r: ppu.output[i], g: ppu.output[i + 1], b: ppu.output[i + 2] }
+    };
+
+    // Colours are only sent when they change, which for most games is not that often along a row
+    let mut last_colours = None;
+    for row in 0..ROWS
+    {
+        queue!(stdout, cursor::MoveTo(0, row as u16))?;
+        for column in 0..COLUMNS
+        {
+            let x = column * SCREEN_WIDTH / COLUMNS;
+            let top = pixel(x, row * SCREEN_HEIGHT / ROWS);
+            let bottom = pixel(x, row * SCREEN_HEIGHT / ROWS + SCREEN_HEIGHT / ROWS / 2);
+
+            if last_colours != Some((top, bottom))
+            {
+                queue!(stdout, SetForegroundColor(top), SetBackgroundColor(bottom))?;
+                last_colours = Some((top, bottom));
+            }
+            queue!(stdout, Print('▀'))?;
+        }
+    }
+
+    stdout.flush()
+}
@@ -0,0 +1,107 @@
+use super::error::EmuError;
+
+// A cheat forces a given address to always read as some value, like the Game Genie did by sitting between the
+// cartridge and the console. An optional compare value means it only replaces the byte if that's what's actually
+// there, which stops it clobbering a different bank (or just unrelated data) that happens to share the address.
+//
+// Codes can either be entered raw, as "AAAA:VV" or "AAAA:VV:CC" (all hex), or as 6 or 8 letter Game Genie codes.
+
+// Each letter stands for a nibble, in this order
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat
+{
+    pub code: String, // As entered, for showing back to the user
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    pub enabled: bool
+}
+
+impl Cheat
+{
+    pub fn from_code(code: &str) -> Result<Self, EmuError>
+    {
+        let code = code.trim().to_uppercase();
+        let (address, value, compare) = if code.contains(':') {
+            Self::decode_raw(&code)?
+        } else {
+            Self::decode_game_genie(&code)?
+        };
+
+        Ok(Cheat { code, address, value, compare, enabled: true })
+    }
+
+    // Gives what should be read instead, if anything
+    pub fn apply(&self, address: u16, value: u8) -> Option<u8>
+    {
+        if !self.enabled || address != self.address {
+            return None;
+        }
+
+        match self.compare
+        {
+            Some(compare) if compare != value => None,
+            _ => Some(self.value)
+        }
+    }
+
+    fn decode_raw(code: &str) -> Result<(u16, u8, Option<u8>), EmuError>
+    {
+        let bad_code = || EmuError::BadCheat(format!("\"{}\" is not a valid cheat (expected AAAA:VV or AAAA:VV:CC)", code));
+        let parts: Vec<&str> = code.split(':').collect();
+        if parts.len() != 2 && parts.len() != 3 {
+            return Err(bad_code());
+        }
+
+        let address = u16::from_str_radix(parts[0], 16).map_err(|_| bad_code())?;
+        let value = u8::from_str_radix(parts[1], 16).map_err(|_| bad_code())?;
+        let compare = match parts.get(2) {
+            Some(compare) => Some(u8::from_str_radix(compare, 16).map_err(|_| bad_code())?),
+            None => None
+        };
+
+        Ok((address, value, compare))
+    }
+
+    fn decode_game_genie(code: &str) -> Result<(u16, u8, Option<u8>), EmuError>
+    {
+        if code.len() != 6 && code.len() != 8 {
+            return Err(EmuError::BadCheat(format!("\"{}\" is not a valid Game Genie code (they're 6 or 8 letters long)", code)));
+        }
+
+        let mut n = [0u16; 8];
+        for (i, letter) in code.chars().enumerate()
+        {
+            n[i] = GAME_GENIE_LETTERS.find(letter).ok_or_else(||
+                EmuError::BadCheat(format!("\"{}\" is not a valid Game Genie code ('{}' isn't one of {})", code, letter, GAME_GENIE_LETTERS))
+            )? as u16;
+        }
+
+        /*
+            The bits are scrambled across the letters as so, where the value is 12345678, the address
+            is ABCDEFGHIJKLMNO (always in ROM, so the top bit's implied) and the compare is !@#$%^&*:
+
+                6 letters: 1678 H234 -IJK LABC DMNO 5EFG
+                8 letters: 1678 H234 -IJK LABC DMNO %EFG !^&* 5@#$
+         */
+        let address = 0x8000 |
+            ((n[3] & 7) << 12) |
+            ((n[4] & 8) << 8) | ((n[5] & 7) << 8) |
+            ((n[1] & 8) << 4) | ((n[2] & 7) << 4) |
+            (n[3] & 8) | (n[4] & 7);
+
+        let value = ((n[0] & 8) << 4) | ((n[1] & 7) << 4) | (n[0] & 7);
+
+        if code.len() == 6
+        {
+            Ok((address, (value | (n[5] & 8)) as u8, None))
+        }
+        else
+        {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            Ok((address, (value | (n[7] & 8)) as u8, Some(compare as u8)))
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use super::memory::Memory;
+use super::error::EmuError;
 use super::ppu::Ppu;
+use super::snapshot::{StateReader, StateWriter};
 use super::opcodes::INSTRUCTIONS;
 use super::opcodes::AddressingMode;
 use super::opcodes::Operation;
@@ -23,6 +25,82 @@ bitflags!
     }
 }
 
+// A handful of unofficial opcodes (0x02, 0x12 and so on) lock the real CPU up until it's reset, which some games
+// hit by accident, so these can either do just that or be skipped over as if they were NOPs instead
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum IllegalOpcodePolicy
+{
+    #[default]
+    Jam,
+    Nop
+}
+
+impl IllegalOpcodePolicy
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "jam" => Some(IllegalOpcodePolicy::Jam),
+            "nop" => Some(IllegalOpcodePolicy::Nop),
+            _ => None
+        }
+    }
+}
+
+// How many of the most recent instructions are kept in "Cpu::pc_history"
+pub const PC_HISTORY_SIZE: usize = 64;
+
+// Where the CPU's been lately (and what it ran there), which is always kept as it's cheap - unlike a full trace -
+// and is usually enough to see how a game ended up jumping off into nowhere or stuck in a loop
+#[derive(Copy, Clone)]
+pub struct PcHistory
+{
+    entries: [(u16, u8); PC_HISTORY_SIZE], // Addresses and opcodes, as a ring buffer
+    next: usize,                           // Where the next one goes
+    length: usize
+}
+
+impl Default for PcHistory
+{
+    fn default() -> Self
+    {
+        PcHistory { entries: [(0, 0); PC_HISTORY_SIZE], next: 0, length: 0 }
+    }
+}
+
+impl PcHistory
+{
+    pub fn push(&mut self, pc: u16, opcode: u8)
+    {
+        self.entries[self.next] = (pc, opcode);
+        self.next = (self.next + 1) % PC_HISTORY_SIZE;
+        self.length = (self.length + 1).min(PC_HISTORY_SIZE);
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.length = 0;
+    }
+
+    // Newest first
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_
+    {
+        (1..=self.length).map(move |i| self.entries[(self.next + PC_HISTORY_SIZE - i) % PC_HISTORY_SIZE])
+    }
+
+    // One instruction per line, newest first, for printing out after a crash and the like
+    pub fn dump(&self) -> String
+    {
+        let lines: Vec<String> = self.iter().map(|(pc, opcode)|
+        {
+            let Instruction(name, ..) = &INSTRUCTIONS[opcode as usize];
+            format!("{:#06x} {} ({:#04x})", pc, name, opcode)
+        }).collect();
+        lines.join("\n")
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Cpu
 {
@@ -32,7 +110,13 @@ pub struct Cpu
     pub x: u8,                 // Index register X
     pub y: u8,                 // Index register Y
     pub flags: ProcessorState, // Processor status (flags)
-    pub cycles: u32
+    pub cycles: u32,           // Cycles left until the current instruction's "finished" (they're executed all at once)
+    pub total_cycles: u64,     // Cycles elapsed since power on, which never goes backwards
+    pub execution_guard: bool, // Stop with an error if the PC ends up anywhere other than RAM or ROM (see "execute")
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+    pub halted: bool,          // Jammed, and so doing nothing at all (interrupts included) until reset
+    pub nmi_pending: bool,     // An NMI noticed in time to be taken once the current instruction's done (see "lib.rs")
+    pub pc_history: PcHistory
 }
 
 pub struct Operand
@@ -43,7 +127,12 @@ pub struct Operand
 
 impl Cpu
 {
-    pub fn from_memory(ppu: &mut Ppu, memory: &mut Memory) -> Self
+    pub fn from_memory(ppu: &mut Ppu, memory: &mut Memory) -> Result<Self, EmuError>
+    {
+        Self::from_memory_with_policy(ppu, memory, IllegalOpcodePolicy::default())
+    }
+
+    pub fn from_memory_with_policy(ppu: &mut Ppu, memory: &mut Memory, illegal_opcode_policy: IllegalOpcodePolicy) -> Result<Self, EmuError>
     {
         // Flags start at 0x34 - IRQs disabled
         let mut flags = ProcessorState::default();
@@ -52,16 +141,76 @@ impl Cpu
         flags.set(ProcessorState::U_FLAG, true);
         assert_eq!(flags.bits, 0x34);
 
-        Cpu
+        Ok(Cpu
         {
-            pc: memory.read_word(ppu, 0xfffc, false), // Program counter depends on reset vector (see memory mapping)
+            // Program counter depends on reset vector (see memory mapping); nothing's running yet, so this is read
+            // as the debugger would, to be sure it can't disturb anything
+            pc: memory.read_word(ppu, 0xfffc, true)?,
             flags,
             sp: 0xfd,
             a: 0,
             x: 0,
             y: 0,
-            cycles: 7
-        }
+            cycles: 7,
+            total_cycles: 7,
+            execution_guard: false,
+            illegal_opcode_policy,
+            halted: false,
+            nmi_pending: false,
+            pc_history: PcHistory::default()
+        })
+    }
+
+    // What the reset button does, as opposed to powering on: registers are left alone, except that interrupts are
+    // disabled, and the stack pointer's moved down by 3 (it goes through the motions of an interrupt, but with
+    // writes turned into reads), before jumping to the reset vector. Un-jams the CPU, too.
+    pub fn reset(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+    {
+        self.pc = memory.read_word(ppu, 0xfffc, true)?;
+        self.sp = self.sp.wrapping_sub(3);
+        self.flags.set(ProcessorState::DISABLE_INTERRUPTS, true);
+        self.cycles = 7;
+        self.halted = false;
+        self.nmi_pending = false;
+        Ok(())
+    }
+
+    // Only what the game can tell the difference of; the execution guard, policy and history are the user's
+    pub fn save_state(&self, state: &mut StateWriter)
+    {
+        state.write_u16(self.pc);
+        state.write_u8(self.sp);
+        state.write_u8(self.a);
+        state.write_u8(self.x);
+        state.write_u8(self.y);
+        state.write_u8(self.flags.bits);
+        state.write_u32(self.cycles);
+        state.write_u64(self.total_cycles);
+        state.write_bool(self.halted);
+        state.write_bool(self.nmi_pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>
+    {
+        self.pc = state.read_u16()?;
+        self.sp = state.read_u8()?;
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.flags = ProcessorState::from_bits_truncate(state.read_u8()?);
+        self.cycles = state.read_u32()?;
+        self.total_cycles = state.read_u64()?;
+        self.halted = state.read_bool()?;
+        self.nmi_pending = state.read_bool()?;
+        Ok(())
+    }
+
+    // Some test ROMs (nestest, for one) have an automated mode that's entered by starting somewhere other than
+    // the reset vector, so this is for moving the PC there once the CPU's been made (the reset's cycles still
+    // stand, as that's what their logs expect)
+    pub fn set_pc(&mut self, pc: u16)
+    {
+        self.pc = pc;
     }
 
     // Non-maskable interrupts cannot be masked (by definition of course), and store the program
@@ -69,11 +218,11 @@ impl Cpu
     // the "RTI" instruction that will therefore return us from the interrupt. I don't know what
     // the NES calls it, but what I'd call the "interrupt vector" is stored at 0xfffa.
 
-    pub fn on_non_maskable_interrupt(&mut self, ppu: &mut Ppu, memory: &mut Memory)
+    pub fn on_non_maskable_interrupt(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
     {
         // Push program counter
-        self.push(ppu, memory, (self.pc >> 8) as u8); // higher byte
-        self.push(ppu, memory, (self.pc >> 0) as u8); // lower byte
+        self.push(ppu, memory, (self.pc >> 8) as u8)?; // higher byte
+        self.push(ppu, memory, (self.pc >> 0) as u8)?; // lower byte
 
         // Set the "B flag" to 01
         self.flags.set(ProcessorState::B_FLAG, false);
@@ -83,28 +232,52 @@ impl Cpu
         self.flags.set(ProcessorState::DISABLE_INTERRUPTS, true);
 
         // Push modified flags
-        self.push(ppu, memory, self.flags.bits);
+        self.push(ppu, memory, self.flags.bits)?;
 
         // Read "interrupt vector" (or whatever it's called) from 0xfffa
-        self.pc = memory.read_word(ppu, 0xfffa, false);
-        self.cycles = 8;
+        self.pc = memory.read_word(ppu, 0xfffa, false)?;
+        self.cycles = 7;
+        self.total_cycles += 7;
+        Ok(())
+    }
+
+    // Maskable interrupts (from the APU's frame counter, or a mapper) are much the same, except that they're
+    // ignored whilst the "disable interrupts" flag is set, which is for whoever's raising them to check. They
+    // have their own vector at 0xfffe (shared with BRK), and the line stays held until it's acknowledged, so
+    // it's up to the handler to do so before clearing the flag again (usually via RTI).
+
+    pub fn on_interrupt_request(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+    {
+        self.push(ppu, memory, (self.pc >> 8) as u8)?;
+        self.push(ppu, memory, self.pc as u8)?;
+
+        // "B flag" is 01, as with NMIs (BRK being the odd one out)
+        self.flags.set(ProcessorState::B_FLAG, false);
+        self.flags.set(ProcessorState::U_FLAG, true);
+        self.push(ppu, memory, self.flags.bits)?;
+        self.flags.set(ProcessorState::DISABLE_INTERRUPTS, true);
+
+        self.pc = memory.read_word(ppu, 0xfffe, false)?;
+        self.cycles = 7;
+        self.total_cycles += 7;
+        Ok(())
     }
 
 
-    fn read_byte_for_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, debugger: bool) -> u8
+    fn read_byte_for_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, debugger: bool) -> Result<u8, EmuError>
     {
         // Read from program counter than advance it (even in debug mode)
-        let data = memory.read_byte(ppu, self.pc, debugger);
-        self.pc += 1;
-        data
+        let data = memory.read_byte(ppu, self.pc, debugger)?;
+        self.pc = self.pc.wrapping_add(1);
+        Ok(data)
     }
 
-    fn read_word_for_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, debugger: bool) -> u16
+    fn read_word_for_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, debugger: bool) -> Result<u16, EmuError>
     {
         // As above, but combine into word
-        let low = self.read_byte_for_operand(ppu, memory, debugger) as u16;
-        let high = self.read_byte_for_operand(ppu, memory, debugger) as u16;
-        (high << 8) | low
+        let low = self.read_byte_for_operand(ppu, memory, debugger)? as u16;
+        let high = self.read_byte_for_operand(ppu, memory, debugger)? as u16;
+        Ok((high << 8) | low)
     }
 
     // The 6502 has a number of different "addressing modes", so that each opcode may have multiple versions
@@ -119,9 +292,9 @@ impl Cpu
     // "argument" at that address (if the above data was indeed a valid address), but only when explicitly
     // called for!
 
-    pub fn fetch_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, addressing_mode: &AddressingMode, debugger: bool) -> Operand
+    pub fn fetch_operand(&mut self, ppu: &mut Ppu, memory: &mut Memory, addressing_mode: &AddressingMode, debugger: bool) -> Result<Operand, EmuError>
     {
-        match addressing_mode
+        let operand = match addressing_mode
         {
             AddressingMode::Implied => Operand { data: 0, additional_cycle: false },
 
@@ -129,19 +302,19 @@ impl Cpu
 
             // Fetches from the next byte after the opcode
             AddressingMode::Immediate => {
-                Operand { data: self.read_byte_for_operand(ppu, memory, debugger) as u16, additional_cycle: false }
+                Operand { data: self.read_byte_for_operand(ppu, memory, debugger)? as u16, additional_cycle: false }
             },
 
             // Fetches the following 16-bit address
             AddressingMode::Absolute => {
-                let address = self.read_word_for_operand(ppu, memory, debugger);
+                let address = self.read_word_for_operand(ppu, memory, debugger)?;
                 Operand { data: address, additional_cycle: false }
             }
 
             // As above, but either X or Y is added to the address
             AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
                 let register = if addressing_mode == &AddressingMode::AbsoluteX { self.x } else { self.y };
-                let base_address = self.read_word_for_operand(ppu, memory, debugger);
+                let base_address = self.read_word_for_operand(ppu, memory, debugger)?;
                 let address = base_address.wrapping_add(register as u16);
 
                 // If a page boundary has been crossed, an additional clock cycle is required
@@ -150,21 +323,21 @@ impl Cpu
 
             // Fetches byte in first page from following address
             AddressingMode::ZeroPage => {
-                let address = self.read_byte_for_operand(ppu, memory, debugger);
+                let address = self.read_byte_for_operand(ppu, memory, debugger)?;
                 Operand { data: address as u16, additional_cycle: false }
             }
 
             // As above, but with either X or Y used as an offset
             AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
                 let register = if addressing_mode == &AddressingMode::ZeroPageX { self.x } else { self.y };
-                let address = self.read_byte_for_operand(ppu, memory, debugger).wrapping_add(register);
+                let address = self.read_byte_for_operand(ppu, memory, debugger)?.wrapping_add(register);
                 Operand { data: address as u16, additional_cycle: false }
             }
 
             // Fetches from address from -128 to +127 bytes from opcode - used only in branching;
             // relative in terms of relative to the program counter *after* the offset has been fetched
             AddressingMode::Relative => {
-                let opcode_offset = self.read_byte_for_operand(ppu, memory, debugger) as i8;
+                let opcode_offset = self.read_byte_for_operand(ppu, memory, debugger)? as i8;
                 let opcode_address = self.pc;
                 let address = opcode_address.wrapping_add(opcode_offset as u16);
                 Operand { data: address as u16, additional_cycle: false }
@@ -176,17 +349,17 @@ impl Cpu
             // cross a page. Yet in real hardware, this doesn't happen - instead we must wrap
             // back round to the same page.
             AddressingMode::Indirect => {
-                let original_address = self.read_word_for_operand(ppu, memory, debugger);
+                let original_address = self.read_word_for_operand(ppu, memory, debugger)?;
                 let actual_address: u16;
 
                 // Emulate bug
-                let lower_byte = memory.read_byte(ppu, original_address, debugger) as u16;
+                let lower_byte = memory.read_byte(ppu, original_address, debugger)? as u16;
 
                 if original_address & 0xff == 0xff {
-                    actual_address = ((memory.read_byte(ppu, original_address & 0xff00, debugger) as u16) << 8) | lower_byte;
+                    actual_address = ((memory.read_byte(ppu, original_address & 0xff00, debugger)? as u16) << 8) | lower_byte;
                 }
                 else {
-                    actual_address = ((memory.read_byte(ppu, original_address + 1, debugger) as u16) << 8) | lower_byte;
+                    actual_address = ((memory.read_byte(ppu, original_address.wrapping_add(1), debugger)? as u16) << 8) | lower_byte;
                 }
 
                 Operand { data: actual_address, additional_cycle: false }
@@ -195,51 +368,70 @@ impl Cpu
             AddressingMode::IndirectX => {
                 // The following 8-bit address is added to register X, and this is then used to
                 // find an address in the first page, which contains the actual address, spanning 16 bits.
-                let address = self.read_byte_for_operand(ppu, memory, debugger).wrapping_add(self.x);
-                let value = memory.read_word_from_first_page(ppu, address, debugger);
+                let address = self.read_byte_for_operand(ppu, memory, debugger)?.wrapping_add(self.x);
+                let value = memory.read_word_from_first_page(ppu, address, debugger)?;
                 Operand { data: value, additional_cycle: false }
             }
 
             AddressingMode::IndirectY => {
                 // Like above, but with the offset being from register Y, and only added after the
                 // sought-after 16-bit address afterwards.
-                let address = self.read_byte_for_operand(ppu, memory, debugger);
-                let value = memory.read_word_from_first_page(ppu, address, debugger);
+                let address = self.read_byte_for_operand(ppu, memory, debugger)?;
+                let value = memory.read_word_from_first_page(ppu, address, debugger)?;
 
                 // Where this offset causes a change in page, an additional cycle is needed.
                 let page_crossed = memory.pages_differ(value, value.wrapping_add(self.y as u16));
                 Operand { data: value.wrapping_add(self.y as u16), additional_cycle: page_crossed }
             }
-        }
+        };
+
+        Ok(operand)
     }
 
-    fn fetch_args(&mut self, ppu: &mut Ppu, memory: &mut Memory, addressing_mode: &AddressingMode, operand_data: u16) -> u8
+    fn fetch_args(&mut self, ppu: &mut Ppu, memory: &mut Memory, addressing_mode: &AddressingMode, operand_data: u16) -> Result<u8, EmuError>
     {
         match addressing_mode
         {
             // First, the addressing modes where this doesn't count...
-            AddressingMode::Implied => 0,
-            AddressingMode::Accumulator | AddressingMode::Immediate => { operand_data as u8 }
+            AddressingMode::Implied => Ok(0),
+            AddressingMode::Accumulator | AddressingMode::Immediate => { Ok(operand_data as u8) }
 
             // and then the rest...
             _ => { memory.read_byte(ppu, operand_data, false) }
         }
     }
 
-    pub fn execute(&mut self, ppu: &mut Ppu, memory: &mut Memory)
+    // Executes a single instruction, returning how many cycles it took - handy for testing
+    pub fn step(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<u32, EmuError>
     {
-        // Fetch opcode
-        let opcode = memory.read_byte(ppu, self.pc, false);
+        let cycles_before = self.cycles;
+        self.execute(ppu, memory)?;
+        Ok(self.cycles - cycles_before)
+    }
+
+    pub fn execute(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+    {
+        let cycles_before = self.cycles;
+
+        // Homebrew that's gone wrong tends to end up running whatever's in the I/O registers (or nothing at all),
+        // which is easier to debug if it stops right there instead of carrying on into who knows where
+        if self.execution_guard && !memory.is_executable(self.pc) {
+            return Err(EmuError::InvalidExecution(self.pc));
+        }
+
+        // Fetch opcode (noting it down first thing, so that even one that can't be decoded ends up in the history)
+        let opcode = memory.read_byte(ppu, self.pc, false)?;
+        self.pc_history.push(self.pc, opcode);
 
         // Decode opcode into more abstract form (because there may be multiple forms of an opcode for each addressing mode)
-        let Instruction(name, operation, addressing_mode, cycles) = &INSTRUCTIONS[opcode as usize];
-        self.pc += 1;
+        let Instruction(_, operation, addressing_mode, cycles) = &INSTRUCTIONS[opcode as usize];
+        self.pc = self.pc.wrapping_add(1);
 
         // Fetch operand, advancing the program counter too if need be
-        let operand = self.fetch_operand(ppu, memory, addressing_mode, false);
+        let operand = self.fetch_operand(ppu, memory, addressing_mode, false)?;
 
         // Fetch argument, but only if the operation calls for it (see long paragraph attached to "fetch_operand")
-        let argument = if operation_requires_fetched_argument(operation) { self.fetch_args(ppu, memory, addressing_mode, operand.data) } else { 0 };
+        let argument = if operation_requires_fetched_argument(operation) { self.fetch_args(ppu, memory, addressing_mode, operand.data)? } else { 0 };
 
         // Execute opcode
         let has_extra_cycles = match operation
@@ -294,7 +486,7 @@ impl Cpu
                 // Result is written either back to byte (in addressing modes absolute, absolute x,
                 // zero page, and zero page x), or is stored in the accumulator
                 if addressing_mode == &AddressingMode::Accumulator { self.a = result; }
-                else { memory.write_byte(ppu, operand.data, result); }
+                else { memory.write_byte(ppu, operand.data, result)?; }
 
                 false
             }
@@ -308,7 +500,7 @@ impl Cpu
 
                 // See above
                 if addressing_mode == &AddressingMode::Accumulator { self.a = result; }
-                else { memory.write_byte(ppu, operand.data, result); }
+                else { memory.write_byte(ppu, operand.data, result)?; }
 
                 false
             }
@@ -324,7 +516,7 @@ impl Cpu
 
                 // As above
                 if addressing_mode == &AddressingMode::Accumulator { self.a = result; }
-                else { memory.write_byte(ppu, operand.data, result); }
+                else { memory.write_byte(ppu, operand.data, result)?; }
 
                 false
             }
@@ -333,13 +525,15 @@ impl Cpu
                 // Similar to above but we shift right and the carry flag becomes the left-most bit instead
                 let result = argument.wrapping_shr(1) | (if self.flags.contains(ProcessorState::CARRY) { 0x80 } else { 0 });
 
+                // The old carry ends up as bit 7, so the negative flag follows it, but set it from the result
+                // like everything else so that it's obviously right
                 self.set_zero_flag(result);
-                self.flags.set(ProcessorState::NEGATIVE, self.flags.contains(ProcessorState::CARRY));
+                self.set_negative_flag(result);
                 self.set_carry_flag((argument & 0b1) != 0);
 
                 // As above
                 if addressing_mode == &AddressingMode::Accumulator { self.a = result; }
-                else { memory.write_byte(ppu, operand.data, result); }
+                else { memory.write_byte(ppu, operand.data, result)?; }
 
                 false
             }
@@ -347,8 +541,8 @@ impl Cpu
 
             // ----------------------- Incrementing and decrementing -----------------------
 
-            Operation::INC => { let result = argument.wrapping_add(1); self.set_zero_flag(result); self.set_negative_flag(result); memory.write_byte(ppu, operand.data, result); false }
-            Operation::DEC => { let result = argument.wrapping_sub(1); self.set_zero_flag(result); self.set_negative_flag(result); memory.write_byte(ppu, operand.data, result); false }
+            Operation::INC => { let result = argument.wrapping_add(1); self.set_zero_flag(result); self.set_negative_flag(result); memory.write_byte(ppu, operand.data, result)?; false }
+            Operation::DEC => { let result = argument.wrapping_sub(1); self.set_zero_flag(result); self.set_negative_flag(result); memory.write_byte(ppu, operand.data, result)?; false }
 
             Operation::INX => { let result = self.x.wrapping_add(1);   self.set_zero_flag(result); self.set_negative_flag(result); self.x = result; false }
             Operation::INY => { let result = self.y.wrapping_add(1);   self.set_zero_flag(result); self.set_negative_flag(result); self.y = result; false }
@@ -363,9 +557,9 @@ impl Cpu
             Operation::LDX => { self.x = argument as u8; self.set_negative_flag(self.x); self.set_zero_flag(self.x); true },
             Operation::LDY => { self.y = argument as u8; self.set_negative_flag(self.y); self.set_zero_flag(self.y); true },
 
-            Operation::STA => { memory.write_byte(ppu, operand.data, self.a); false }
-            Operation::STX => { memory.write_byte(ppu, operand.data, self.x); false }
-            Operation::STY => { memory.write_byte(ppu, operand.data, self.y); false }
+            Operation::STA => { memory.write_byte(ppu, operand.data, self.a)?; false }
+            Operation::STX => { memory.write_byte(ppu, operand.data, self.x)?; false }
+            Operation::STY => { memory.write_byte(ppu, operand.data, self.y)?; false }
 
 
             // ----------------------- Setting and clearing flags -----------------------
@@ -398,9 +592,9 @@ impl Cpu
             Operation::JSR => {
                 // Push onto the stack the *current* program counter, because it's actually "RTS"
                 // that has the burden of adding one to skip past this instruction when returning
-                self.pc -= 1;
-                self.push(ppu, memory, (self.pc >> 8) as u8);
-                self.push(ppu, memory, (self.pc & 0xff) as u8);
+                self.pc = self.pc.wrapping_sub(1);
+                self.push(ppu, memory, (self.pc >> 8) as u8)?;
+                self.push(ppu, memory, (self.pc & 0xff) as u8)?;
 
                 // Jump to subroutine
                 self.pc = operand.data;
@@ -410,16 +604,16 @@ impl Cpu
             Operation::RTI => {
                 // Pops the topmost byte from the stack and uses it to update the processor status, then pops
                 // the next two bytes from the stack so as to update the program counter
-                self.flags.bits = self.pop(ppu, memory);
-                self.pc = self.pop(ppu, memory) as u16 | ((self.pop(ppu, memory) as u16) << 8);
+                self.flags.bits = self.pop(ppu, memory)?;
+                self.pc = self.pop(ppu, memory)? as u16 | ((self.pop(ppu, memory)? as u16) << 8);
                 false
             }
 
             Operation::RTS => {
                 // Pop the top two bytes off the stack so as to update the program counter, then add one
                 // to get past the pushed "JSR" opcode (see above)
-                self.pc = self.pop(ppu, memory) as u16 | ((self.pop(ppu, memory) as u16) << 8);
-                self.pc += 1;
+                self.pc = self.pop(ppu, memory)? as u16 | ((self.pop(ppu, memory)? as u16) << 8);
+                self.pc = self.pc.wrapping_add(1);
                 false
             }
 
@@ -438,22 +632,22 @@ impl Cpu
 
             // ----------------------- Pushes and pops -----------------------
 
-            Operation::PHA => { self.push(ppu, memory, self.a); false }
+            Operation::PHA => { self.push(ppu, memory, self.a)?; false }
 
             Operation::PHP => {
                 // The "B" flag must be set in the pushed flags, but not in our actual flags
-                self.push(ppu, memory, self.flags.bits | ProcessorState::B_FLAG.bits | ProcessorState::U_FLAG.bits);
+                self.push(ppu, memory, self.flags.bits | ProcessorState::B_FLAG.bits | ProcessorState::U_FLAG.bits)?;
                 false
             }
 
             Operation::PLA => {
-                self.a = self.pop(ppu, memory);
+                self.a = self.pop(ppu, memory)?;
                 self.set_zero_flag(self.a);
                 self.set_negative_flag(self.a);
                 false
             }
 
-            Operation::PLP => { self.flags.bits = self.pop(ppu, memory); false }
+            Operation::PLP => { self.flags.bits = self.pop(ppu, memory)?; false }
 
 
             // ----------------------- Transfers -----------------------
@@ -498,7 +692,7 @@ impl Cpu
 
             Operation::SAX => {
                 // Stores the AND of A and X, affecting no flags
-                memory.write_byte(ppu, operand.data, self.a & self.x);
+                memory.write_byte(ppu, operand.data, self.a & self.x)?;
                 false
             }
 
@@ -515,7 +709,7 @@ impl Cpu
             Operation::DCP => {
                 // Equivalent to a DEC followed by a CMP, except that it supports more address modes
                 let dec_value = argument.wrapping_sub(1);
-                memory.write_byte(ppu, operand.data, dec_value);
+                memory.write_byte(ppu, operand.data, dec_value)?;
 
                 let cmp_value = self.a.wrapping_sub(dec_value);
                 self.set_carry_flag(self.a >= dec_value);
@@ -528,7 +722,7 @@ impl Cpu
             Operation::ISC => {
                 // Equivalent to a INC followed by an SBC, but again supporting more address modes
                 let inc_value = argument.wrapping_add(1);
-                memory.write_byte(ppu, operand.data, inc_value);
+                memory.write_byte(ppu, operand.data, inc_value)?;
 
                 let (sbc_value_one, sbc_carry_one) = self.a.overflowing_sub(inc_value);
                 let (sbc_value_two, sbc_carry_two) = sbc_value_one.overflowing_sub(if self.flags.contains(ProcessorState::CARRY) { 0 } else { 1 });
@@ -546,7 +740,7 @@ impl Cpu
                 // Equivalent to an ROL followed by an AND, but again supporting more address modes
                 let rol_value = argument.wrapping_shl(1) | (if self.flags.contains(ProcessorState::CARRY) { 1 } else { 0 });
                 self.set_carry_flag(argument & 0x80 != 0);
-                memory.write_byte(ppu, operand.data, rol_value);
+                memory.write_byte(ppu, operand.data, rol_value)?;
 
                 let and_value = self.a & rol_value;
                 self.set_zero_flag(and_value);
@@ -560,7 +754,7 @@ impl Cpu
                 // Equivalent to an ROR followed by an ADC, but again supporting more address modes
                 let ror_value = argument.wrapping_shr(1) | (if self.flags.contains(ProcessorState::CARRY) { 0x80 } else { 0x00 });
                 self.set_carry_flag((argument & 1) == 1);
-                memory.write_byte(ppu, operand.data, ror_value);
+                memory.write_byte(ppu, operand.data, ror_value)?;
 
                 let adc_value = self.a as u16 + ror_value as u16 + (if self.flags.contains(ProcessorState::CARRY) { 1 } else { 0 });
 
@@ -578,7 +772,7 @@ impl Cpu
                 // Equivalent to an ASL followed by an ORA, but again supporting more address modes
                 let asl_value = argument.wrapping_shl(1);
                 self.set_carry_flag(argument & 0x80 != 0);
-                memory.write_byte(ppu, operand.data, asl_value);
+                memory.write_byte(ppu, operand.data, asl_value)?;
 
                 let ora_value = self.a | asl_value;
                 self.set_zero_flag(ora_value);
@@ -593,7 +787,7 @@ impl Cpu
                 // Equivalent to an LSR followed by an EOR, but again supporting more address modes
                 let lsr_value = argument.wrapping_shr(1);
                 self.set_carry_flag((argument & 1) == 1);
-                memory.write_byte(ppu, operand.data, lsr_value);
+                memory.write_byte(ppu, operand.data, lsr_value)?;
 
                 let eor_value = self.a ^ lsr_value;
                 self.set_zero_flag(eor_value);
@@ -603,15 +797,124 @@ impl Cpu
                 false
             }
 
+            // The immediate-only ones, which are all an AND with something else tacked on
+
+            Operation::ANC => {
+                // AND, then bit 7 goes into the carry as well as the negative flag
+                self.a &= argument;
+                self.set_carry_flag(self.a & 0x80 != 0);
+                self.set_zero_flag(self.a);
+                self.set_negative_flag(self.a);
+                false
+            }
+
+            Operation::ALR => {
+                // AND, then LSR the accumulator
+                let and_value = self.a & argument;
+                self.set_carry_flag((and_value & 1) == 1);
+                self.a = and_value >> 1;
+                self.set_zero_flag(self.a);
+                self.set_negative_flag(self.a);
+                false
+            }
+
+            Operation::ARR => {
+                // AND, then ROR the accumulator, except that carry and overflow come out of the adder rather
+                // than the shift: carry is bit 6 of the result, and overflow is bit 6 XORed with bit 5
+                let and_value = self.a & argument;
+                self.a = (and_value >> 1) | (if self.flags.contains(ProcessorState::CARRY) { 0x80 } else { 0x00 });
+                self.set_carry_flag(self.a & 0x40 != 0);
+                self.set_overflow_flag(((self.a >> 6) ^ (self.a >> 5)) & 1 == 1);
+                self.set_zero_flag(self.a);
+                self.set_negative_flag(self.a);
+                false
+            }
+
+            Operation::AXS => {
+                // (A AND X) minus the argument into X, setting the flags like CMP does (so without borrowing)
+                let and_value = self.a & self.x;
+                self.x = and_value.wrapping_sub(argument);
+                self.set_carry_flag(and_value >= argument);
+                self.set_zero_flag(self.x);
+                self.set_negative_flag(self.x);
+                false
+            }
+
             Operation::BRK => {
-                println!("\n\nDone!\n");
-                println!("0x2: {:#02x}", memory.read_byte(ppu, 0x2, false));
-                println!("0x3: {:#02x}", memory.read_byte(ppu, 0x3, false));
-                println!();
-                panic!();
+                // A software interrupt, which works much like an NMI (see above) except that it goes through
+                // the IRQ vector, and the pushed flags have the "B flag" set so the handler can tell them apart.
+                // The byte after BRK is padding, which the immediate addressing mode has already skipped over.
+                self.push(ppu, memory, (self.pc >> 8) as u8)?;
+                self.push(ppu, memory, (self.pc & 0xff) as u8)?;
+                self.push(ppu, memory, self.flags.bits | ProcessorState::B_FLAG.bits | ProcessorState::U_FLAG.bits)?;
+                self.flags.set(ProcessorState::DISABLE_INTERRUPTS, true);
+                self.pc = memory.read_word(ppu, 0xfffe, false)?;
+                false
             }
 
-            _ => panic!("Could not decode opcode {} - {:#04x}", name, opcode as u8)
+            // ---------------------- Unstable unofficial ops ----------------------
+
+            // All but LAS depend on how the chip behaves electrically, so differ between (and even within)
+            // consoles, but games (and demos) that use them only rely on what's consistent, which is what's
+            // done here. XAA ANDs the accumulator with a "magic" constant first, taken as 0xee (the most
+            // commonly seen value), and the stores AND what they write with one more than the high byte of the
+            // address before indexing - which, if indexing crossed a page, also ends up as the high byte of
+            // the address that's actually written to.
+
+            Operation::XAA => {
+                self.a = (self.a | 0xee) & self.x & argument;
+                self.set_zero_flag(self.a);
+                self.set_negative_flag(self.a);
+                false
+            }
+
+            Operation::AHX => {
+                let index = self.y;
+                self.unstable_store(ppu, memory, operand.data, index, self.a & self.x)?;
+                false
+            }
+
+            Operation::TAS => {
+                self.sp = self.a & self.x;
+                let index = self.y;
+                self.unstable_store(ppu, memory, operand.data, index, self.sp)?;
+                false
+            }
+
+            Operation::SHY => {
+                let index = self.x;
+                self.unstable_store(ppu, memory, operand.data, index, self.y)?;
+                false
+            }
+
+            Operation::SHX => {
+                let index = self.y;
+                self.unstable_store(ppu, memory, operand.data, index, self.x)?;
+                false
+            }
+
+            Operation::LAS => {
+                // Reliable, unlike the rest, but just as rarely used
+                let value = argument & self.sp;
+                self.a = value;
+                self.x = value;
+                self.sp = value;
+                self.set_zero_flag(value);
+                self.set_negative_flag(value);
+                true
+            }
+
+            Operation::JAM => {
+                // Left pointing at the opcode, so the debugger shows what did it
+                if self.illegal_opcode_policy == IllegalOpcodePolicy::Jam
+                {
+                    self.halted = true;
+                    self.pc = self.pc.wrapping_sub(1);
+                }
+                false
+            }
+
+            _ => return Err(EmuError::UnknownOpcode { opcode, address: self.pc.wrapping_sub(1) })
         };
 
         // Some opcodes take longer depending on the addressing mode, and some don't, but it's almost always
@@ -622,6 +925,8 @@ impl Cpu
 
         // Of course we should also take into account the regular old number of cycles too
         self.cycles += *cycles as u32;
+        self.total_cycles += (self.cycles - cycles_before) as u64;
+        Ok(())
     }
 
     // Below are helper functions for the above opcodes, just to make things tidier and more compact
@@ -639,7 +944,8 @@ impl Cpu
     {
         if condition
         {
-            // Branching to the same page adds one cycle, whilst a different page incurs two extra cycles
+            // Branching to the same page adds one cycle, whilst a different page incurs two extra cycles - "same"
+            // being relative to the instruction after the branch (where the PC is now), not the branch itself
             if memory.pages_differ(self.pc, location) { self.cycles += 2 } else { self.cycles += 1 }
             self.pc = location;
         }
@@ -681,16 +987,28 @@ impl Cpu
         self.flags.set(ProcessorState::NEGATIVE, (value & 0b10000000) != 0);
     }
 
-    pub fn push(&mut self, ppu: &mut Ppu, memory: &mut Memory, value: u8)
+    // See the unstable opcodes in "execute"; "address" has already had "index" added to it
+    fn unstable_store(&mut self, ppu: &mut Ppu, memory: &mut Memory, address: u16, index: u8, value: u8) -> Result<(), EmuError>
+    {
+        let base = address.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+
+        let address = if memory.pages_differ(base, address) { ((value as u16) << 8) | (address & 0xff) } else { address };
+        memory.write_byte(ppu, address, value)
+    }
+
+    pub fn push(&mut self, ppu: &mut Ppu, memory: &mut Memory, value: u8) -> Result<(), EmuError>
     {
-        // Stack pointer is just the low byte of the actual stack, which resides from 0x100-0x1ff
-        memory.write_byte(ppu, 0x100 + self.sp as u16, value);
-        self.sp -= 1;
+        // Stack pointer is just the low byte of the actual stack, which resides from 0x100-0x1ff (and so wraps
+        // round within it, rather than ever spilling out)
+        memory.write_byte(ppu, 0x100 + self.sp as u16, value)?;
+        self.sp = self.sp.wrapping_sub(1);
+        Ok(())
     }
 
-    pub fn pop(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> u8
+    pub fn pop(&mut self, ppu: &mut Ppu, memory: &mut Memory) -> Result<u8, EmuError>
     {
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         memory.read_byte(ppu, 0x100 + self.sp as u16, false) // See above for "0x100 + self.sp"
     }
 }
@@ -0,0 +1,46 @@
+use std::fmt;
+use super::mapper::SUPPORTED_MAPPERS;
+
+// Anything that can go wrong whilst loading or running a ROM. These used to just panic, which is fine for
+// a debugger, but not so much when the emulator's being embedded or wants to let the user try another ROM.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError
+{
+    UnmappedRead(u16),
+    UnmappedWrite(u16),
+    UnknownOpcode { opcode: u8, address: u16 },
+    InvalidExecution(u16),
+    UnsupportedMapper(u8),
+    FdsNotSupported,
+    BadRom(String),
+    BadMovie(String),
+    BadState(String),
+    BadCheat(String),
+}
+
+impl fmt::Display for EmuError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            EmuError::UnmappedRead(address) => write!(f, "Could not map memory read for address {:#06x}", address),
+            EmuError::UnmappedWrite(address) => write!(f, "Could not map memory write for address {:#06x}", address),
+            EmuError::UnknownOpcode { opcode, address } => write!(f, "Could not decode opcode {:#04x} at {:#06x}", opcode, address),
+            EmuError::InvalidExecution(address) => write!(f, "Tried to execute code at {:#06x}, which is neither RAM nor ROM", address),
+            EmuError::UnsupportedMapper(mapper) =>
+            {
+                let supported: Vec<String> = SUPPORTED_MAPPERS.iter().map(|mapper| mapper.to_string()).collect();
+                write!(f, "Mapper {} is not supported (supported mappers: {})", mapper, supported.join(", "))
+            }
+            EmuError::FdsNotSupported => write!(f, "FDS (Famicom Disk System) images are not supported - only iNES ROMs (\".nes\" files) are"),
+            EmuError::BadRom(reason) => write!(f, "{}", reason),
+            EmuError::BadMovie(reason) => write!(f, "{}", reason),
+            EmuError::BadState(reason) => write!(f, "{}", reason),
+            EmuError::BadCheat(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
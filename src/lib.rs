@@ -0,0 +1,190 @@
+pub mod apu;
+pub mod cheat;
+pub mod cpu;
+pub mod error;
+pub mod mapper;
+pub mod memory;
+pub mod movie;
+pub mod opcodes;
+pub mod palette_table;
+pub mod ppu;
+pub mod snapshot;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use cpu::{Cpu, ProcessorState};
+use error::EmuError;
+use memory::Memory;
+use ppu::Ppu;
+
+// Runs the emulation for one "frame", independently of any window or input, so that it may
+// be driven by the frontend in main.rs or headlessly (for tests and the like)
+
+pub fn on_emulation_cycle(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    for _ in 0..ppu.region.cycles_per_frame()
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    Ok(())
+}
+
+// Keeps going until the PPU reaches the start of the next v-blank (see "Ppu::at_vblank_start"), which
+// is handy for taking screenshots or traces at the same point every frame. The CPU's instructions are
+// atomic anyway, and the timing lives in the PPU's cycle count, so stopping here never upsets it.
+
+pub fn run_until_vblank(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    // Always move on at least once, so that calling this repeatedly gives one frame each time
+    on_emulation_tick(cpu, ppu, memory)?;
+    while !ppu.at_vblank_start()
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    Ok(())
+}
+
+// As above, but only until the PPU gets to the start of the next scanline, for watching a frame being drawn (and
+// mid-frame effects happening) a line at a time
+
+pub fn run_scanline(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    let scanline = ppu.get_scanline();
+    while ppu.get_scanline() == scanline
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    Ok(())
+}
+
+// Runs until the CPU's finished its next instruction, for stepping through code in the debugger. As instructions
+// are executed all at once on their first cycle, the rest of their cycles are run too, so that the PPU's caught
+// up by the time this returns (and so the next instruction starts from where it would've anyway).
+
+pub fn run_instruction(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    // A jammed CPU's never going to get anywhere
+    if cpu.halted { return Ok(()) }
+
+    // Whatever's in progress has already happened as far as anyone can tell, so that's finished off first...
+    while cpu.cycles > 0 && !cpu.halted
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    // ...then DMA (or just the PPU being between CPU cycles) may hold up the start of the next one for a while...
+    while cpu.cycles == 0 && !cpu.halted
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    // ...after which it's a case of waiting for it to "finish"
+    while cpu.cycles > 0 && !cpu.halted
+    {
+        on_emulation_tick(cpu, ppu, memory)?;
+    }
+
+    Ok(())
+}
+
+// Presses the reset button, which resets the CPU and PPU (see their own "reset"s for what that means), silences the
+// APU and stops any DMA that's in the middle of happening, whilst RAM, VRAM and the cartridge are left as they were
+
+pub fn reset(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    ppu.reset();
+    memory.apu.write_register(0x4015, 0);
+    memory.dma_happening = false;
+    memory.dma_cycles_left = 0;
+    cpu.reset(ppu, memory)
+}
+
+// A single PPU cycle, along with the CPU (or DMA) if it's due to run on it
+
+pub fn on_emulation_tick(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+{
+    // The PPU's about to advance, so remember which cycle this is
+    let cycle = ppu.total_cycles;
+
+    // PPU runs at, well... "PPU speed"
+    ppu.execute(memory);
+
+    // CPU runs at one third of the speed (or a little slower for PAL)
+    if ppu.region.cpu_runs_on_cycle(cycle)
+    {
+        // The APU's frame counter keeps time in CPU cycles, DMA or not
+        memory.apu.execute(ppu.region);
+
+        // If DMA is happening, execution is halted for 513 cycles (or 514), though the clock keeps going. It only
+        // begins once the instruction that wrote to 0x4014 has finished, with a cycle spent halting the CPU, then
+        // another if that was an odd cycle (as reads can only happen on every other one), then 256 pairs of reads
+        // and writes.
+        if memory.dma_happening && cpu.cycles == 0
+        {
+            if memory.dma_cycles_left == 0 {
+                memory.dma_cycles_left = if cpu.total_cycles % 2 == 1 { 514 } else { 513 };
+            }
+
+            cpu.total_cycles += 1;
+            memory.dma_cycles_left -= 1;
+
+            // Counting down, so the last 512 cycles alternate between reading and writing (the ones before
+            // that being the halt and alignment cycles, which do nothing)
+            if memory.dma_cycles_left < 512
+            {
+                if memory.dma_cycles_left % 2 == 1
+                {
+                    memory.dma_data = memory.read_byte(ppu, (memory.dma_page as u16) << 8 | memory.dma_address as u16, false)?;
+                }
+                else
+                {
+                    ppu.object_attribute_memory[memory.dma_address as usize] = memory.dma_data;
+                    memory.dma_address = memory.dma_address.wrapping_add(1);
+                }
+            }
+
+            if memory.dma_cycles_left == 0 {
+                memory.dma_happening = false;
+            }
+        }
+        // A jammed CPU does nothing at all, though the clock (and so everything else) keeps going
+        else if cpu.halted
+        {
+            cpu.total_cycles += 1;
+            ppu.due_non_maskable_interrupt = false;
+        }
+        else
+        {
+            // Interrupts are only taken between instructions: NMIs first, then IRQs if they're not masked
+            if cpu.cycles == 0
+            {
+                if cpu.nmi_pending
+                {
+                    cpu.nmi_pending = false;
+                    cpu.on_non_maskable_interrupt(ppu, memory)?;
+                }
+                else if memory.apu.get_irq() && !cpu.flags.contains(ProcessorState::DISABLE_INTERRUPTS) {
+                    cpu.on_interrupt_request(ppu, memory)?;
+                } else {
+                    cpu.step(ppu, memory)?;
+                }
+            }
+            cpu.cycles -= 1;
+        }
+
+        // The 6502 looks for NMIs at the end of each instruction's second-to-last cycle, so one that comes along
+        // on the last cycle has to wait for the *next* instruction to finish too (which a few games' timing, and
+        // the vbl_nmi test ROMs, rely on). Whilst DMA has the CPU sat between instructions, it's noticed straight away.
+        if ppu.due_non_maskable_interrupt && (cpu.cycles == 1 || memory.dma_happening)
+        {
+            ppu.due_non_maskable_interrupt = false;
+            cpu.nmi_pending = true;
+        }
+    }
+
+    Ok(())
+}
@@ -1,43 +1,426 @@
-mod cpu;
-mod memory;
-mod opcodes;
-mod palette_table;
-mod ppu;
-
-use cpu::Cpu;
-use memory::Memory;
-use ppu::Ppu;
-use ppu::SCREEN_WIDTH;
-use ppu::SCREEN_HEIGHT;
-use ppu::CYCLES_PER_FRAME;
-use ppu::PATTERN_TABLE_SIZE;
-use opcodes::INSTRUCTIONS;
-use opcodes::Instruction;
-
-use imgui::{Condition, im_str, Image, StyleVar, TextureId, Window, Context};
+use nes_emulator_rust::on_emulation_cycle;
+use nes_emulator_rust::run_instruction;
+use nes_emulator_rust::run_scanline;
+use nes_emulator_rust::reset;
+use nes_emulator_rust::apu::DEFAULT_SAMPLE_RATE;
+use nes_emulator_rust::cheat::Cheat;
+use nes_emulator_rust::cpu::{Cpu, IllegalOpcodePolicy};
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::{Buttons, Memory, Mirroring};
+use nes_emulator_rust::movie::Movie;
+use nes_emulator_rust::ppu::{Ppu, PpuControl, PpuMask};
+use nes_emulator_rust::ppu::Region;
+use nes_emulator_rust::ppu::SCREEN_WIDTH;
+use nes_emulator_rust::ppu::SCREEN_HEIGHT;
+use nes_emulator_rust::ppu::PATTERN_TABLE_SIZE;
+use nes_emulator_rust::snapshot::Snapshot;
+use nes_emulator_rust::opcodes::INSTRUCTIONS;
+use nes_emulator_rust::opcodes::Instruction;
+
+use arboard::{Clipboard, ImageData};
+
+use imgui::{Condition, im_str, Image, ImStr, ImString, StyleVar, TextureId, Window, Context};
 use imgui_opengl_renderer::Renderer;
 use imgui_sdl2::ImguiSdl2;
 
-use sdl2::controller::{GameController, Button};
+use sdl2::audio::AudioSpecDesired;
+use sdl2::controller::{Axis, GameController, Button};
 use sdl2::keyboard::{Keycode, Scancode};
 use sdl2::event::Event;
+use sdl2::video::FullscreenType;
+use sdl2::surface::Surface;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::EventPump;
 
+use std::collections::VecDeque;
 use std::ops::RangeInclusive;
 use std::os::raw::c_void;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 const WINDOW_WIDTH: u32 = 961;
 const WINDOW_HEIGHT: u32 = 684;
 const SCREEN_SCALE: usize = 2;
 
+// NES pixels aren't quite square, but rather slightly wider than they are tall
+const PIXEL_ASPECT_RATIO: f32 = 8.0 / 7.0;
+
+// Save states are kept in slots, selectable with the number keys
+const SAVE_SLOTS: usize = 10;
+
+// In samples; see "--audio-buffer"
+const DEFAULT_AUDIO_BUFFER_SIZE: u16 = 1024;
+
+// Anything queued beyond this many buffers' worth is dropped, so that lag can't build up if emulation runs a little fast
+const MAX_QUEUED_AUDIO_BUFFERS: u32 = 4;
+
+// How often the ROM's checked for changes with "--watch"
+const ROM_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Emulation can be slowed down or sped up (with the minus and equals keys) to get a better look at things
+const SPEED_MULTIPLIERS: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const DEFAULT_SPEED_INDEX: usize = 2;
+
+// However long the last frame took (the window being dragged, the machine being busy...), at most this many frames
+// (times the speed multiplier) are run to catch up, and the rest are dropped. Catching up on everything would freeze
+// the window whilst it did, then have the game sprint, and if running that many frames took longer still, it'd never
+// catch up at all. Dropping them means the game just carries on from where it was, at the cost of a gap in the audio.
+const MAX_CATCH_UP_FRAMES: f64 = 4.0;
+
+// How many frames turbo buttons stay pressed (and then released) for
+const TURBO_RATE: u32 = 2;
+
+// Controller one's bindings, as (key or button, bit in the controller byte, name); the help window's built from these too
+const KEY_BINDINGS: [(Scancode, Buttons, &str); 8] =
+[
+    (Scancode::X,     Buttons::A,      "A"),
+    (Scancode::Z,     Buttons::B,      "B"),
+    (Scancode::A,     Buttons::SELECT, "Select"),
+    (Scancode::S,     Buttons::START,  "Start"),
+    (Scancode::Up,    Buttons::UP,     "Up"),
+    (Scancode::Down,  Buttons::DOWN,   "Down"),
+    (Scancode::Left,  Buttons::LEFT,   "Left"),
+    (Scancode::Right, Buttons::RIGHT,  "Right"),
+];
+
+const TURBO_KEY_BINDINGS: [(Scancode, Buttons, &str); 2] =
+[
+    (Scancode::C, Buttons::A, "Turbo A"),
+    (Scancode::V, Buttons::B, "Turbo B"),
+];
+
+const BUTTON_BINDINGS: [(Button, Buttons, &str); 10] =
+[
+    (Button::A,         Buttons::A,      "A"),
+    (Button::B,         Buttons::A,      "A"),
+    (Button::X,         Buttons::B,      "B"),
+    (Button::Y,         Buttons::B,      "B"),
+    (Button::Back,      Buttons::SELECT, "Select"),
+    (Button::Start,     Buttons::START,  "Start"),
+    (Button::DPadUp,    Buttons::UP,     "Up"),
+    (Button::DPadDown,  Buttons::DOWN,   "Down"),
+    (Button::DPadLeft,  Buttons::LEFT,   "Left"),
+    (Button::DPadRight, Buttons::RIGHT,  "Right"),
+];
+
+// How far the left stick has to be pushed along either axis (out of 32767) before it counts as that direction; loose
+// sticks sit a fair way off centre, and without this would hold directions down all by themselves. Can be changed
+// with "--deadzone=N", as a percentage.
+const DEFAULT_STICK_DEADZONE: i16 = 8000;
+
+// Everything else is handled as it comes in, in the main loop, so do keep this in step with that
+const HOTKEYS: [(&str, &str); 10] =
+[
+    ("F1", "Show or hide this help"),
+    ("F2", "Show or hide the debugger"),
+    ("F3", "Reset"),
+    ("F11", "Toggle fullscreen"),
+    ("- / =", "Slow down or speed up"),
+    ("0-9", "Pick a save slot"),
+    ("F5", "Start or stop recording a movie"),
+    ("F6", "Start or stop playing a movie"),
+    ("F8", "Copy the screen to the clipboard"),
+    ("Escape", "Quit"),
+];
+
+// How the output's shown, as picked in the GUI
+#[derive(Default)]
+struct DisplaySettings
+{
+    correct_aspect_ratio: bool, // Stretch pixels to be 8:7 rather than square
+    fit_to_window: bool,        // Let the output take up the whole window, rather than its usual spot
+    smooth_output: bool,        // Linear rather than nearest filtering
+    hide_debugger: bool,        // Show nothing but the output, filling the window, for just playing
+    frame_skip: u32,            // Frames left undrawn between each one that's shown (see "Ppu::frame_skip")
+    sprite_limit: bool,         // Only the first 8 sprites on each scanline, as on hardware (see "Ppu::sprite_limit")
+    overscan: Overscan,
+}
+
+// TVs hid a few pixels around the edges, which games took advantage of by leaving garbage there (mostly at the top
+// and bottom), so this many pixels are cropped from each edge when showing the output - though it's all still emulated
+struct Overscan
+{
+    top: u32,
+    bottom: u32,
+    left: u32,
+    right: u32
+}
+
+// What the cartridge's mirroring can be overridden with from the GUI, for debugging
+const MIRRORING_OVERRIDES: [(&str, Option<Mirroring>); 6] =
+[
+    ("Cartridge", None),
+    ("Horizontal", Some(Mirroring::Horizontal)),
+    ("Vertical", Some(Mirroring::Vertical)),
+    ("Single screen (lower)", Some(Mirroring::SingleScreenLower)),
+    ("Single screen (upper)", Some(Mirroring::SingleScreenUpper)),
+    ("Four screen", Some(Mirroring::FourScreen)),
+];
+
+// The most frames that can be skipped between each one drawn
+const MAX_FRAME_SKIP: u32 = 5;
+
+// The most that can be cropped from any one edge
+const MAX_OVERSCAN: u32 = 32;
+
+impl Default for Overscan
+{
+    fn default() -> Self
+    {
+        Overscan { top: 8, bottom: 8, left: 0, right: 0 }
+    }
+}
+
+// What to do when opposite directions are held at once, which a real D-pad can't do (and so some games break on)
+#[derive(Copy, Clone, PartialEq, Default)]
+enum SocdMode
+{
+    #[default]
+    Neutral,    // Neither direction
+    LastWins,   // Whichever was pressed most recently
+    Allow,      // Both, as it comes
+}
+
+impl SocdMode
+{
+    fn from_name(name: &str) -> Option<Self>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "neutral" => Some(SocdMode::Neutral),
+            "last" => Some(SocdMode::LastWins),
+            "allow" => Some(SocdMode::Allow),
+            _ => None
+        }
+    }
+
+    // "previous" is what was resolved last frame, which is how we know which direction's the newer one
+    fn resolve(&self, buttons: Buttons, previous: Buttons) -> Buttons
+    {
+        let mut buttons = buttons;
+        for (first, second) in [(Buttons::UP, Buttons::DOWN), (Buttons::LEFT, Buttons::RIGHT)]
+        {
+            if !buttons.contains(first | second) { continue }
+
+            let keep = match self
+            {
+                SocdMode::Allow => first | second,
+                SocdMode::LastWins if previous & (first | second) == first => second,
+                SocdMode::LastWins if previous & (first | second) == second => first,
+                _ => Buttons::empty()
+            };
+
+            buttons = (buttons - (first | second)) | keep;
+        }
+        buttons
+    }
+}
+
+// Which of a gamepad's D-pad and left stick press the directions, as per "--directions=dpad|stick|both"
+#[derive(Copy, Clone, PartialEq, Default)]
+enum DirectionSource
+{
+    DPad,
+    Stick,
+    #[default]
+    Both,
+}
+
+impl DirectionSource
+{
+    fn from_name(name: &str) -> Option<Self>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "dpad" => Some(DirectionSource::DPad),
+            "stick" => Some(DirectionSource::Stick),
+            "both" => Some(DirectionSource::Both),
+            _ => None
+        }
+    }
+
+    // "pad" is everything from the buttons (D-pad included), and "stick" is only ever directions
+    fn combine(&self, pad: Buttons, stick: Buttons) -> Buttons
+    {
+        let directions = Buttons::UP | Buttons::DOWN | Buttons::LEFT | Buttons::RIGHT;
+        match self
+        {
+            DirectionSource::DPad => pad,
+            DirectionSource::Stick => (pad - directions) | stick,
+            DirectionSource::Both => pad | stick
+        }
+    }
+}
+
+// Each axis is taken on its own, so diagonals work as they would on a D-pad (SDL has down as positive Y)
+fn get_stick_directions(controller: &GameController, deadzone: i16) -> Buttons
+{
+    let (x, y) = (controller.axis(Axis::LeftX), controller.axis(Axis::LeftY));
+    let mut buttons = Buttons::empty();
+    if x < -deadzone { buttons |= Buttons::LEFT; }
+    if x > deadzone { buttons |= Buttons::RIGHT; }
+    if y < -deadzone { buttons |= Buttons::UP; }
+    if y > deadzone { buttons |= Buttons::DOWN; }
+    buttons
+}
+
+// Movies are recorded or played back from power on, a frame at a time
+enum MovieState
+{
+    Idle,
+    Recording(Movie),
+    Playing(Movie, usize)
+}
+
+// How many instructions can be stepped back over (each one's a whole snapshot, so this can't go on forever)
+const MAX_STEP_HISTORY: usize = 256;
+
+// Pausing, and stepping forwards (or backwards) an instruction at a time whilst paused
+#[derive(Default)]
+struct Stepping
+{
+    paused: bool,
+    history: VecDeque<Snapshot> // The machine from before each instruction that's been stepped, most recent last
+}
+
+impl Stepping
+{
+    fn step(&mut self, cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+    {
+        self.history.push_back(Snapshot::from_machine(cpu, ppu, memory));
+        if self.history.len() > MAX_STEP_HISTORY { self.history.pop_front(); }
+        run_instruction(cpu, ppu, memory)
+    }
+
+    // The same, but a whole scanline at a time
+    fn step_scanline(&mut self, cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> Result<(), EmuError>
+    {
+        self.history.push_back(Snapshot::from_machine(cpu, ppu, memory));
+        if self.history.len() > MAX_STEP_HISTORY { self.history.pop_front(); }
+        let result = run_scanline(cpu, ppu, memory);
+
+        // Otherwise the line that's just been drawn wouldn't be shown until the frame's finished
+        ppu.output_dirty = true;
+        result
+    }
+
+    // Returns whether there was anything to go back to
+    fn step_back(&mut self, cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory) -> bool
+    {
+        match self.history.pop_back()
+        {
+            Some(snapshot) => { snapshot.restore(cpu, ppu, memory); true }
+            None => false
+        }
+    }
+
+    // Anything run since the last step wouldn't be undone by going back, so once emulation's running freely again,
+    // the history's no use
+    fn resume(&mut self)
+    {
+        self.paused = false;
+        self.history.clear();
+    }
+}
+
+// How long notifications stay up, in seconds (the last of which is spent fading out), and how many at once
+const NOTIFICATION_DURATION: f32 = 3.0;
+const MAX_NOTIFICATIONS: usize = 5;
+
+// Short messages shown in the corner of the output, so that hotkeys (which otherwise act silently) give some sign
+// they've worked; each is printed too, as everything was before these existed
+#[derive(Default)]
+struct Notifications
+{
+    messages: VecDeque<(String, Instant)> // Along with when they were pushed
+}
+
+impl Notifications
+{
+    fn push(&mut self, message: String)
+    {
+        println!("{}", message);
+        self.messages.push_back((message, Instant::now()));
+        if self.messages.len() > MAX_NOTIFICATIONS { self.messages.pop_front(); }
+    }
+}
+
+// Writing a byte anywhere from the debugger (the "Poke" window), for forcing game state whilst experimenting. RAM
+// (and PRG RAM) is just memory, but everywhere else is a register of some sort - the PPU's, the APU's, OAM DMA, or
+// the mapper's over ROM - which'll act on the write as the game's would, so that's only allowed once asked for.
+#[derive(Default)]
+struct Poke
+{
+    address: ImString, // In hex, as with cheats
+    value: ImString,
+    allow_side_effects: bool,
+    error: Option<String>
+}
+
+impl Poke
+{
+    fn has_side_effects(address: u16) -> bool
+    {
+        !matches!(address, 0x0000..=0x1fff | 0x6000..=0x7fff)
+    }
+
+    // Returns what was written where, for a notification
+    fn write(&self, ppu: &mut Ppu, memory: &mut Memory) -> Result<(u16, u8), String>
+    {
+        let address = u16::from_str_radix(self.address.to_str().trim(), 16).map_err(|_| "Address should be 0000 to ffff".to_string())?;
+        let value = u8::from_str_radix(self.value.to_str().trim(), 16).map_err(|_| "Value should be 00 to ff".to_string())?;
+
+        if Poke::has_side_effects(address) && !self.allow_side_effects {
+            return Err(format!("{:#06x} isn't RAM, so writing to it could set something off; tick \"Side effects\" to do so anyway", address));
+        }
+
+        memory.write_byte(ppu, address, value).map_err(|error| error.to_string())?;
+        Ok((address, value))
+    }
+}
+
+// With "--watch", the ROM's reloaded whenever it changes on disk, for going back and forth with an assembler.
+// Polling the modified time every so often is plenty for that, and doesn't need anything platform-specific.
+struct RomWatcher
+{
+    modified: Option<SystemTime>,
+    last_checked: Instant
+}
+
+impl RomWatcher
+{
+    fn from_file(rom_filename: &str) -> Self
+    {
+        RomWatcher { modified: Self::get_modified(rom_filename), last_checked: Instant::now() }
+    }
+
+    fn has_changed(&mut self, rom_filename: &str) -> bool
+    {
+        if self.last_checked.elapsed() < ROM_WATCH_INTERVAL { return false }
+        self.last_checked = Instant::now();
+
+        let modified = Self::get_modified(rom_filename);
+        let changed = modified.is_some() && modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+
+    // Could be missing for a moment whilst it's being rewritten, in which case it's just checked again later on
+    fn get_modified(rom_filename: &str) -> Option<SystemTime>
+    {
+        std::fs::metadata(rom_filename).and_then(|metadata| metadata.modified()).ok()
+    }
+}
+
 fn main()
 {
-    // Get std args: filename, [speed]
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 && args.len() != 3
+    // Get std args: [filename], [speed], then any "--" options after that; without a ROM, there's just a test pattern
+    // until one's loaded from the GUI
+    let (options, args): (Vec<String>, Vec<String>) = std::env::args().partition(|arg| arg.starts_with("--"));
+    if args.len() > 3
     {
         println!("Invalid format - must run like so:");
-        println!("./nes-emulator-rust [filename.nes] [speed as int (optional)]");
+        println!("./nes-emulator-rust [filename.nes (optional)] [speed as int (optional)] [--ntsc or --pal (optional)] [--skip-frames=N (optional)] [--bench=N (optional)] [--socd=neutral|last|allow (optional)] [--illegal-opcodes=jam|nop (optional)] [--sample-rate=N (optional)] [--audio-buffer=N (optional)] [--watch (optional)] [--draw-every=N (optional)] [--mirroring=horizontal|vertical|single-lower|single-upper|four-screen (optional)] [--directions=dpad|stick|both (optional)] [--deadzone=N (optional)]");
         std::process::abort();
     }
     let speed = if args.len() == 3 {
@@ -45,10 +428,84 @@ fn main()
     } else { 1 };
     println!("Running at {}x speed", speed);
 
+    // Region can be forced, as ROM headers often don't say
+    let region_override = options.iter().find_map(|option| Region::from_name(&option[2..]));
+
+    // Mirroring can be too, but only ever to help debug things, as the cartridge is always right
+    let mirroring_override = options.iter()
+        .find_map(|option| option.strip_prefix("--mirroring="))
+        .map(|mirroring| Mirroring::from_name(mirroring).expect("mirroring should be horizontal, vertical, single-lower, single-upper or four-screen"));
+
+    // Opposite directions held together (on the keyboard, or across it and a pad) get sorted out as per "--socd=neutral|last|allow"
+    let socd_mode = options.iter()
+        .find_map(|option| option.strip_prefix("--socd="))
+        .map(|mode| SocdMode::from_name(mode).expect("SOCD mode should be neutral, last or allow"))
+        .unwrap_or_default();
+
+    // Gamepads' directions come from the D-pad, left stick or both ("--directions=dpad|stick|both"), the latter only once
+    // it's pushed past the deadzone ("--deadzone=N", as a percentage of the way to the edge)
+    let direction_source = options.iter()
+        .find_map(|option| option.strip_prefix("--directions="))
+        .map(|source| DirectionSource::from_name(source).expect("directions should come from dpad, stick or both"))
+        .unwrap_or_default();
+    let stick_deadzone = options.iter()
+        .find_map(|option| option.strip_prefix("--deadzone="))
+        .map(|percentage| percentage.parse::<u8>().ok().filter(|percentage| *percentage <= 100).expect("deadzone should be a percentage from 0 to 100"))
+        .map(|percentage| (i16::MAX as i32 * percentage as i32 / 100) as i16)
+        .unwrap_or(DEFAULT_STICK_DEADZONE);
+
+    // Opcodes that would jam the CPU can be made to do nothing instead with "--illegal-opcodes=nop"
+    let illegal_opcode_policy = options.iter()
+        .find_map(|option| option.strip_prefix("--illegal-opcodes="))
+        .map(|policy| IllegalOpcodePolicy::from_name(policy).expect("illegal opcode policy should be jam or nop"))
+        .unwrap_or_default();
+
+    // Anything not worth watching at the start (logos and the like) can be run through before the window shows anything
+    let skip_frames = options.iter()
+        .find_map(|option| option.strip_prefix("--skip-frames="))
+        .map(|frames| frames.parse::<usize>().expect("frames to skip was an invalid integer"))
+        .unwrap_or(0);
+
+    // On slow machines, "--draw-every=N" only draws every Nth frame, though the game still runs at full speed (not
+    // to be confused with "--skip-frames", above)
+    let frame_skip = options.iter()
+        .find_map(|option| option.strip_prefix("--draw-every="))
+        .map(|frames| frames.parse::<u32>().ok().filter(|frames| *frames > 0).expect("frames to draw every was an invalid integer") - 1)
+        .unwrap_or(0);
+
+    // Audio's asked for at "--sample-rate=N" (44100 by default), "--audio-buffer=N" samples at a time; bigger
+    // buffers are less likely to run dry (and crackle) on slower machines, but lag further behind
+    let sample_rate = options.iter()
+        .find_map(|option| option.strip_prefix("--sample-rate="))
+        .map(|rate| rate.parse::<u32>().expect("sample rate was an invalid integer"))
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+    let audio_buffer_size = options.iter()
+        .find_map(|option| option.strip_prefix("--audio-buffer="))
+        .map(|size| size.parse::<u16>().expect("audio buffer size was an invalid integer"))
+        .unwrap_or(DEFAULT_AUDIO_BUFFER_SIZE);
+
+    let rom_argument = args.get(1).cloned().unwrap_or_default();
+
+    // Benchmarking runs without a window at all, and then quits
+    if let Some(frames) = options.iter().find_map(|option| option.strip_prefix("--bench="))
+    {
+        bench(&rom_argument, region_override, mirroring_override, illegal_opcode_policy, frames.parse::<usize>().expect("frames to bench was an invalid integer"));
+        return;
+    }
+
     // Init SDL
     let sdl_context = sdl2::init().unwrap();
     let video = sdl_context.video().unwrap();
 
+    // The device may not give us the rate we asked for, in which case the APU makes whatever it did give instead
+    let audio_spec = AudioSpecDesired { freq: Some(sample_rate as i32), channels: Some(1), samples: Some(audio_buffer_size) };
+    let audio_queue = match sdl_context.audio().and_then(|audio| audio.open_queue::<f32, _>(None, &audio_spec))
+    {
+        Ok(queue) => { queue.resume(); Some(queue) }
+        Err(error) => { println!("Could not open audio device ({}); continuing without sound", error); None }
+    };
+    let sample_rate = audio_queue.as_ref().map(|queue| queue.spec().freq as u32).unwrap_or(sample_rate);
+
     // Find detected SDL controllers...
     let game_controller_subsystem = sdl_context.game_controller().unwrap();
     let available = game_controller_subsystem
@@ -74,16 +531,20 @@ fn main()
     gl_attr.set_context_version(3, 0);
 
     // Create window
-    let window = video.window("NES", WINDOW_WIDTH, WINDOW_HEIGHT)
+    let mut window = video.window("NES", WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
         .opengl()
         .allow_highdpi()
         .build()
         .unwrap();
 
-    // Init OpenGL
+    // Init OpenGL; v-sync keeps us from drawing far more often than need be, but the actual emulation
+    // speed is kept in check separately (as not every monitor runs at 60Hz)
     let _gl_context = window.gl_create_context().unwrap();
     gl::load_with(|s| video.gl_get_proc_address(s) as _);
+    if video.gl_set_swap_interval(sdl2::video::SwapInterval::VSync).is_err() {
+        println!("Could not enable v-sync");
+    }
 
     // Init ImGui; disable .ini config
     let mut imgui = imgui::Context::create();
@@ -93,20 +554,65 @@ fn main()
     let mut imgui_sdl2 = imgui_sdl2::ImguiSdl2::new(&mut imgui, &window);
     let renderer = imgui_opengl_renderer::Renderer::new(&mut imgui, |s| video.gl_get_proc_address(s) as _);
 
+    // Another ROM can be loaded from the GUI later on, though if that fails, the current one keeps going. If there's
+    // no ROM to begin with (or it wouldn't load), it's the test pattern that keeps going, and the filename's left
+    // empty, which is what stops anything being run.
+    let mut rom_path = ImString::new(rom_argument.as_str());
+    let mut rom_filename = rom_argument.clone();
+    let mut rom_load_error: Option<EmuError> = None;
+
     // Init emulation
-    let mut ppu = Ppu::default();
-    let mut memory = Memory::default();
-    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory);
+    let (mut cpu, mut ppu, mut memory) = match load_machine(&rom_filename, region_override, mirroring_override, illegal_opcode_policy, sample_rate)
+    {
+        Ok(machine) => machine,
+        Err(error) =>
+        {
+            if !rom_filename.is_empty()
+            {
+                println!("{}", error);
+                rom_load_error = Some(error);
+            }
+            rom_filename.clear();
+            blank_machine(region_override)
+        }
+    };
+    let mut rom_watcher = options.iter().any(|option| option == "--watch").then(|| RomWatcher::from_file(&rom_filename));
+
+    // Cheats themselves live in memory, but whatever's being typed in (and whether it made any sense) lives here
+    let mut cheat_code = ImString::with_capacity(16);
+    let mut cheat_error: Option<EmuError> = None;
+    let mut poke = Poke::default();
+
+    // F5 starts and stops recording a movie, and F6 starts and stops playing it back
+    let mut movie_state = MovieState::Idle;
+    let mut notifications = Notifications::default();
+
+    // Only made once something's copied (F8), then kept, as on some platforms what's copied goes when it does
+    let mut clipboard: Option<Clipboard> = None;
+
+    // If the emulation ever goes wrong, it stops until the user loads a state (or quits)
+    let mut emulation_error: Option<EmuError> = None;
+    if rom_filename.is_empty() {
+        ppu.draw_test_pattern();
+    }
+    else if let Err(error) = run_frames(&mut cpu, &mut ppu, &mut memory, skip_frames) {
+        emulation_error = Some(error);
+    }
 
     // Saved states
-    let mut saved_cpu = cpu;
-    let mut saved_ppu = ppu;
-    let mut saved_memory = memory.clone();
+    let mut save_slots: [Option<Snapshot>; SAVE_SLOTS] = Default::default();
+    let mut current_slot: usize = 0;
+    let mut stepping = Stepping::default();
 
     // Create OpenGL textures
     let mut output_texture: u32 = 0;
     let mut pattern_table_textures = [0u32; 2];
+    let mut heatmap_texture: u32 = 0;
+    let mut tiles_used: Option<usize> = None; // Only worked out (along with the heatmap) when asked for
     let mut palette = 0;
+    let mut chr_bank: Option<usize> = None; // For the pattern table viewer; none means whatever's switched in
+    let mut display_settings = DisplaySettings { frame_skip, sprite_limit: true, ..Default::default() };
+    let mut show_help = false;
 
     unsafe
     {
@@ -122,14 +628,35 @@ fn main()
             gl::BindTexture(gl::TEXTURE_2D, pattern_table_textures[i]);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB as i32, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, 0, gl::RGB, gl::UNSIGNED_BYTE, ppu.get_pattern_table(&mut memory, i as u8, palette).as_ptr() as *const c_void);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB as i32, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, 0, gl::RGB, gl::UNSIGNED_BYTE, ppu.get_pattern_table(&mut memory, i as u8, palette, chr_bank).as_ptr() as *const c_void);
         }
+
+        gl::GenTextures(1, &mut heatmap_texture);
+        gl::BindTexture(gl::TEXTURE_2D, heatmap_texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB as i32, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, 0, gl::RGB, gl::UNSIGNED_BYTE, std::ptr::null());
     }
 
     // Begin event loop
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut frame_count: u32 = 0;
+    let mut previous_controllers = [Buttons::empty(); 2];
+
+    // Frame limiting; we keep track of how many frames "should" have been run by now, which may be fractional
+    let mut speed_index = DEFAULT_SPEED_INDEX;
+    let mut last_frame_time = Instant::now();
+    let mut frames_due = 0.0;
+
+    // How many frames are actually being run, as opposed to should be, measured over a second at a time
+    let mut measured_fps = 0.0;
+    let mut fps_frames = 0;
+    let mut fps_start_time = Instant::now();
     'running: loop
     {
+        let mut toggle_recording = false;
+        let mut toggle_playback = false;
+
         // Poll window events
         for event in event_pump.poll_iter()
         {
@@ -140,67 +667,269 @@ fn main()
             match event
             {
                 Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+
+                // Minus and equals change the speed
+                Event::KeyDown { keycode: Some(Keycode::Minus), .. } =>
+                {
+                    speed_index = speed_index.saturating_sub(1);
+                    notifications.push(format!("Speed: {}x", SPEED_MULTIPLIERS[speed_index]));
+                }
+                Event::KeyDown { keycode: Some(Keycode::Equals), .. } =>
+                {
+                    speed_index = (speed_index + 1).min(SPEED_MULTIPLIERS.len() - 1);
+                    notifications.push(format!("Speed: {}x", SPEED_MULTIPLIERS[speed_index]));
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } => show_help = !show_help,
+
+                // F2 hides the debugger, and F11 goes fullscreen (which is best with the debugger hidden, really)
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } => display_settings.hide_debugger = !display_settings.hide_debugger,
+                Event::KeyDown { keycode: Some(Keycode::F11), .. } =>
+                {
+                    let fullscreen = if window.fullscreen_state() == FullscreenType::Off { FullscreenType::Desktop } else { FullscreenType::Off };
+                    if let Err(error) = window.set_fullscreen(fullscreen) {
+                        notifications.push(format!("Could not change fullscreen mode: {}", error));
+                    }
+                }
+
+                // F3 presses the reset button, which also gets emulation going again if it had stopped
+                Event::KeyDown { keycode: Some(Keycode::F3), .. } =>
+                {
+                    emulation_error = reset(&mut cpu, &mut ppu, &mut memory).err();
+                    notifications.push("Reset".to_string());
+                }
+
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => toggle_recording = true,
+                Event::KeyDown { keycode: Some(Keycode::F6), .. } => toggle_playback = true,
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => notifications.push(copy_frame_to_clipboard(&mut clipboard, &ppu, &rom_filename)),
+
+                // Number keys pick the save slot
+                Event::KeyDown { keycode: Some(keycode), .. }
+                    if keycode as i32 >= Keycode::Num0 as i32 && keycode as i32 <= Keycode::Num9 as i32 =>
+                {
+                    current_slot = (keycode as i32 - Keycode::Num0 as i32) as usize;
+                    notifications.push(format!("Save slot {}{}", current_slot, if save_slots[current_slot].is_none() { " (empty)" } else { "" }));
+                }
+
                 _ => {}
             }
         }
 
-        // Set (emulated) controller from keyboard
-        memory.controller[0] = 0;
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::X)     { 0x80 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::Z)     { 0x40 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::A)     { 0x20 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::S)     { 0x10 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::Up)    { 0x08 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::Down)  { 0x04 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::Left)  { 0x02 } else { 0 };
-        memory.controller[0] |= if event_pump.keyboard_state().is_scancode_pressed(Scancode::Right) { 0x01 } else { 0 };
-
-        // Apply (physical) controllers to input too
-        for i in 0..controllers.len()
-        {
-            // A button
-            memory.controller[0] |= if controllers[i].button(Button::A)         { 0x80 } else { 0 };
-            memory.controller[0] |= if controllers[i].button(Button::B)         { 0x80 } else { 0 };
-
-            // B button
-            memory.controller[0] |= if controllers[i].button(Button::X)         { 0x40 } else { 0 };
-            memory.controller[0] |= if controllers[i].button(Button::Y)         { 0x40 } else { 0 };
-
-            // Select
-            memory.controller[0] |= if controllers[i].button(Button::Back)      { 0x20 } else { 0 };
-
-            // Start
-            memory.controller[0] |= if controllers[i].button(Button::Start)     { 0x10 } else { 0 };
-
-            // Directions
-            memory.controller[0] |= if controllers[i].button(Button::DPadUp)    { 0x08 } else { 0 };
-            memory.controller[0] |= if controllers[i].button(Button::DPadDown)  { 0x04 } else { 0 };
-            memory.controller[0] |= if controllers[i].button(Button::DPadLeft)  { 0x02 } else { 0 };
-            memory.controller[0] |= if controllers[i].button(Button::DPadRight) { 0x01 } else { 0 };
+        // Set (emulated) controllers from the keyboard and any physical ones, all at once, so that anything can press
+        // any button without one overriding the other; the keyboard's always player one
+        let keyboard = event_pump.keyboard_state();
+        let mut buttons = [Buttons::empty(); 2];
+        for (scancode, bit, _) in KEY_BINDINGS.iter()
+        {
+            if keyboard.is_scancode_pressed(*scancode) { buttons[0] |= *bit; }
+        }
+
+        // Turbo A and B alternate between pressed and released every few frames
+        frame_count = frame_count.wrapping_add(1);
+        if (frame_count / TURBO_RATE).is_multiple_of(2)
+        {
+            for (scancode, bit, _) in TURBO_KEY_BINDINGS.iter()
+            {
+                if keyboard.is_scancode_pressed(*scancode) { buttons[0] |= *bit; }
+            }
+        }
+
+        // Physical controllers are players one and two in the order they were found (any more than that have nowhere
+        // to be plugged in, so are ignored)
+        for (controller, port) in controllers.iter().zip(buttons.iter_mut())
+        {
+            let mut pad = Buttons::empty();
+            for (button, bit, _) in BUTTON_BINDINGS.iter()
+            {
+                if controller.button(*button) { pad |= *bit; }
+            }
+            *port |= direction_source.combine(pad, get_stick_directions(controller, stick_deadzone));
+        }
+
+        for (id, (port, previous)) in buttons.iter().zip(previous_controllers.iter_mut()).enumerate()
+        {
+            *previous = socd_mode.resolve(*port, *previous);
+            memory.set_controller(id, *previous);
         }
 
+        // Both recording and playback begin from power on, which means rebuilding the machine
+        if toggle_recording
+        {
+            movie_state = match std::mem::replace(&mut movie_state, MovieState::Idle)
+            {
+                MovieState::Recording(movie) =>
+                {
+                    match movie.save_to_file(&get_movie_filename(&rom_filename))
+                    {
+                        Ok(()) => notifications.push(format!("Saved movie of {} frames", movie.inputs.len())),
+                        Err(error) => notifications.push(error.to_string())
+                    }
+                    MovieState::Idle
+                }
+
+                _ => match load_machine(&rom_filename, region_override, mirroring_override, illegal_opcode_policy, sample_rate)
+                {
+                    Ok(mut machine) =>
+                    {
+                        machine.2.keep_settings_from(&mut memory);
+                        (cpu, ppu, memory) = machine;
+                        emulation_error = None;
+                        notifications.push("Recording movie".to_string());
+                        MovieState::Recording(Movie::from_rom_hash(memory.get_rom_hash()))
+                    }
+                    Err(error) => { notifications.push(error.to_string()); MovieState::Idle }
+                }
+            };
+        }
+
+        if toggle_playback
+        {
+            movie_state = match std::mem::replace(&mut movie_state, MovieState::Idle)
+            {
+                MovieState::Playing(..) => { notifications.push("Stopped playing movie".to_string()); MovieState::Idle }
+
+                _ => match Movie::from_file(&get_movie_filename(&rom_filename))
+                {
+                    Ok(movie) if movie.rom_hash != memory.get_rom_hash() =>
+                    {
+                        notifications.push("Movie was recorded with a different ROM".to_string());
+                        MovieState::Idle
+                    }
+                    Ok(movie) => match load_machine(&rom_filename, region_override, mirroring_override, illegal_opcode_policy, sample_rate)
+                    {
+                        Ok(mut machine) =>
+                        {
+                            machine.2.keep_settings_from(&mut memory);
+                            (cpu, ppu, memory) = machine;
+                            emulation_error = None;
+                            notifications.push(format!("Playing movie of {} frames", movie.inputs.len()));
+                            MovieState::Playing(movie, 0)
+                        }
+                        Err(error) => { notifications.push(error.to_string()); MovieState::Idle }
+                    }
+                    Err(error) => { notifications.push(error.to_string()); MovieState::Idle }
+                }
+            };
+        }
+
+        // Work out how many frames are due based on how much time's passed (audio's only played at normal speed,
+        // so nothing else needs to be kept in sync when running slower or faster), within reason
+        let now = Instant::now();
+        frames_due += (now - last_frame_time).as_secs_f64() * ppu.region.frames_per_second() * SPEED_MULTIPLIERS[speed_index];
+        frames_due = frames_due.min(MAX_CATCH_UP_FRAMES * SPEED_MULTIPLIERS[speed_index].max(1.0));
+        last_frame_time = now;
+
+        // Whilst paused, frames would only pile up, to all be run at once on resuming
+        if stepping.paused { frames_due = 0.0; }
+
+        // Set here rather than when loading, so that they stick across ROMs and save states
+        ppu.frame_skip = display_settings.frame_skip;
+        ppu.sprite_limit = display_settings.sprite_limit;
+
         // Perform emulation
-        for _ in 0..speed {
-            on_emulation_cycle(&mut cpu, &mut ppu, &mut memory);
+        while frames_due >= 1.0
+        {
+            frames_due -= 1.0;
+
+            for _ in 0..speed {
+                if emulation_error.is_some() || rom_filename.is_empty() { break }
+
+                // Movies either take note of the controllers or take them over, every frame
+                match &mut movie_state
+                {
+                    MovieState::Recording(movie) => movie.inputs.push((memory.controller[0], memory.controller[1])),
+                    MovieState::Playing(movie, frame) =>
+                    {
+                        if let Some((one, two)) = movie.inputs.get(*frame)
+                        {
+                            memory.set_controller(0, Buttons::from_bits_truncate(*one));
+                            memory.set_controller(1, Buttons::from_bits_truncate(*two));
+                            *frame += 1;
+                        }
+                        else
+                        {
+                            notifications.push("Movie finished".to_string());
+                            movie_state = MovieState::Idle;
+                        }
+                    }
+                    MovieState::Idle => {}
+                }
+
+                let was_halted = cpu.halted;
+                match on_emulation_cycle(&mut cpu, &mut ppu, &mut memory)
+                {
+                    Ok(()) => fps_frames += 1,
+                    Err(error) =>
+                    {
+                        notifications.push(format!("Emulation stopped: {}", error));
+                        println!("Last instructions executed (newest first):\n{}", cpu.pc_history.dump());
+                        emulation_error = Some(error);
+                    }
+                }
+
+                if cpu.halted && !was_halted {
+                    notifications.push(format!("CPU jammed at {:#06x}", cpu.pc));
+                }
+            }
+        }
+
+        // Audio's only played at normal speed, as anything else would either pile up or run dry
+        if let Some(queue) = &audio_queue
+        {
+            let normal_speed = SPEED_MULTIPLIERS[speed_index] == 1.0 && speed == 1;
+            let queue_full = queue.size() >= audio_buffer_size as u32 * 4 * MAX_QUEUED_AUDIO_BUFFERS; // 4 bytes to a sample
+            if normal_speed && !queue_full && emulation_error.is_none() && !stepping.paused && !queue.queue(&memory.apu.samples) {
+                println!("Could not queue audio: {}", sdl2::get_error());
+            }
+        }
+        memory.apu.samples.clear();
+
+        let fps_elapsed = fps_start_time.elapsed().as_secs_f64();
+        if fps_elapsed >= 1.0
+        {
+            measured_fps = fps_frames as f64 / fps_elapsed;
+            fps_frames = 0;
+            fps_start_time = Instant::now();
         }
 
         // Draw ImGUI stuff
-        draw_gui
+        let load_rom_requested = draw_gui
         (
             // Emulation
             &mut cpu,
             &mut ppu,
             &mut memory,
+            &mut emulation_error,
+            &mut stepping,
 
             // Saved states
-            &mut saved_cpu,
-            &mut saved_ppu,
-            &mut saved_memory,
+            &mut save_slots,
+            current_slot,
+
+            // Loading ROMs
+            &rom_filename,
+            &mut rom_path,
+            &rom_load_error,
+
+            // Cheats
+            &mut cheat_code,
+            &mut cheat_error,
+            &mut poke,
+
+            // Timing
+            &mut speed_index,
+            measured_fps,
 
             // Input and output
             output_texture,
             &pattern_table_textures,
+            heatmap_texture,
+            &mut tiles_used,
             &mut palette,
+            &mut chr_bank,
+            &mut display_settings,
+            &mut show_help,
+            &mut notifications,
 
             // Rendering
             &mut imgui,
@@ -211,12 +940,55 @@ fn main()
         );
 
         window.gl_swap_window();
+
+        // Swap out the whole machine for the new ROM (save states are for the old one, so they have to go too)
+        if load_rom_requested
+        {
+            match load_machine(rom_path.to_str(), region_override, mirroring_override, illegal_opcode_policy, sample_rate)
+            {
+                Ok(machine) =>
+                {
+                    (cpu, ppu, memory) = machine;
+                    save_slots = Default::default();
+                    stepping.resume();
+                    emulation_error = run_frames(&mut cpu, &mut ppu, &mut memory, skip_frames).err();
+                    rom_load_error = None;
+                    rom_filename = rom_path.to_str().to_string();
+                    chr_bank = None;
+                    movie_state = MovieState::Idle;
+                    notifications.push(format!("Loaded {}", rom_filename));
+                    if let Some(watcher) = &mut rom_watcher { *watcher = RomWatcher::from_file(&rom_filename); }
+                }
+                Err(error) => rom_load_error = Some(error)
+            }
+        }
+
+        // Same again when the ROM's been rewritten, except that if it can't be loaded (say, the assembler's only
+        // half done), the old one keeps going until it's written again
+        if rom_watcher.as_mut().is_some_and(|watcher| watcher.has_changed(&rom_filename))
+        {
+            match load_machine(&rom_filename, region_override, mirroring_override, illegal_opcode_policy, sample_rate)
+            {
+                Ok(mut machine) =>
+                {
+                    machine.2.keep_settings_from(&mut memory);
+                    (cpu, ppu, memory) = machine;
+                    save_slots = Default::default();
+                    stepping.resume();
+                    emulation_error = run_frames(&mut cpu, &mut ppu, &mut memory, skip_frames).err();
+                    movie_state = MovieState::Idle;
+                    notifications.push(format!("Reloaded {}", rom_filename));
+                }
+                Err(error) => notifications.push(format!("Could not reload {}: {}", rom_filename, error))
+            }
+        }
     }
 
     // Clean up OpenGL
     unsafe
     {
         gl::DeleteTextures(1, &mut output_texture);
+        gl::DeleteTextures(1, &heatmap_texture);
 
         for i in 0..pattern_table_textures.len()
         {
@@ -225,81 +997,150 @@ fn main()
     }
 }
 
-fn on_emulation_cycle(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory)
+fn load_machine(rom_filename: &str, region_override: Option<Region>, mirroring_override: Option<Mirroring>, illegal_opcode_policy: IllegalOpcodePolicy, sample_rate: u32) -> Result<(Cpu, Ppu, Memory), EmuError>
 {
-    for i in 0..CYCLES_PER_FRAME
-    {
-        // PPU runs at, well... "PPU speed"
-        ppu.execute(memory);
-
-        // CPU runs at one third of the speed
-        if i % 3 == 0
-        {
-            // If DMA is happening, execution is temporarily halted
-            if memory.dma_happening
-            {
-                // The DMA circuitry is synced to the CPU clock only every two intervals, so we may need to wait
-                if memory.dma_waiting_for_sync
-                {
-                    if i % 2 == 1
-                    {
-                        memory.dma_waiting_for_sync = false;
-                    }
-                }
-                else
-                {
-                    // On even cycles, data is read
-                    if i % 2 == 0
-                    {
-                        memory.dma_data = memory.read_byte(ppu, (memory.dma_page as u16) << 8 | memory.dma_address as u16, false);
-                    }
+    let mut memory = Memory::from_file(rom_filename)?;
+    memory.mirroring_override = mirroring_override;
+    memory.apu.set_sample_rate(sample_rate);
+    let region = region_override.unwrap_or(memory.rom_header.get_region());
+    println!("Running as {:?}", region);
+    let mut ppu = Ppu::from_region(region);
+    let cpu = Cpu::from_memory_with_policy(&mut ppu, &mut memory, illegal_opcode_policy)?;
+    Ok((cpu, ppu, memory))
+}
 
-                    // On odd cycles, data is written
-                    else
-                    {
-                        ppu.object_attribute_memory[memory.dma_address as usize] = memory.dma_data;
-                        memory.dma_address = memory.dma_address.wrapping_add(1);
+// Runs as fast as possible, with nothing drawn in the meantime
+fn run_frames(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory, frames: usize) -> Result<(), EmuError>
+{
+    for _ in 0..frames {
+        on_emulation_cycle(cpu, ppu, memory)?;
+    }
+    Ok(())
+}
 
-                        // If we've looped back round to zero, we've written a full page, so stop (TODO: fix as per the DMA "todo" in memory.rs)
-                        if memory.dma_address == 0
-                        {
-                            memory.dma_happening = false;
-                            memory.dma_waiting_for_sync = true;
-                        }
-                    }
-                }
-            }
-            else
-            {
-                if cpu.cycles == 0 { cpu.execute(ppu, memory); }
-                cpu.cycles -= 1;
-            }
-        }
+// Stands in until a ROM's loaded, showing the test pattern (see "main") rather than running anything
+fn blank_machine(region_override: Option<Region>) -> (Cpu, Ppu, Memory)
+{
+    let mut memory = Memory::from_blank_cartridge();
+    let mut ppu = Ppu::from_region(region_override.unwrap_or(Region::Ntsc));
+    let cpu = Cpu::from_memory(&mut ppu, &mut memory).expect("a blank cartridge should always have a reset vector");
+    (cpu, ppu, memory)
+}
 
-        if ppu.due_non_maskable_interrupt
+// Times how long the given number of frames take; the hash of the last frame should never change unless the
+// emulation's meant to have, so it's a quick way to notice something's gone wrong whilst optimising
+fn bench(rom_filename: &str, region_override: Option<Region>, mirroring_override: Option<Mirroring>, illegal_opcode_policy: IllegalOpcodePolicy, frames: usize)
+{
+    let (mut cpu, mut ppu, mut memory) = match load_machine(rom_filename, region_override, mirroring_override, illegal_opcode_policy, DEFAULT_SAMPLE_RATE)
+    {
+        Ok(machine) => machine,
+        Err(error) =>
         {
-            ppu.due_non_maskable_interrupt = false;
-            cpu.on_non_maskable_interrupt(ppu, memory);
+            println!("{}", error);
+            std::process::exit(1);
         }
+    };
+
+    let start = Instant::now();
+    let result = run_frames(&mut cpu, &mut ppu, &mut memory, frames);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    if let Err(error) = result {
+        println!("Emulation stopped: {}", error);
+    }
+
+    println!("Ran {} frames in {:.3}s ({:.1} frames per second)", frames, elapsed, frames as f64 / elapsed);
+    println!("Final frame hash: {:016x}", ppu.get_output_hash());
+}
+
+// Movies live next to their ROMs, e.g. "mario.nes" has "mario.movie"
+fn get_movie_filename(rom_filename: &str) -> String
+{
+    Path::new(rom_filename).with_extension("movie").to_string_lossy().to_string()
+}
+
+// One file for each save slot, next to the ROM (e.g. "mario.state0")
+fn get_state_filename(rom_filename: &str, slot: usize) -> String
+{
+    Path::new(rom_filename).with_extension(format!("state{}", slot)).to_string_lossy().to_string()
+}
+
+fn get_vram_dump_filename(rom_filename: &str) -> String
+{
+    Path::new(rom_filename).with_extension("vram").to_string_lossy().to_string()
+}
+
+fn get_screenshot_filename(rom_filename: &str) -> String
+{
+    Path::new(rom_filename).with_extension("bmp").to_string_lossy().to_string()
+}
+
+// Copies the frame as an image, or if the clipboard won't take one (not every platform can), saves it next to the
+// ROM instead; either way, returns what happened
+fn copy_frame_to_clipboard(clipboard: &mut Option<Clipboard>, ppu: &Ppu, rom_filename: &str) -> String
+{
+    let rgba: Vec<u8> = ppu.output.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff]).collect();
+    let image = ImageData { width: SCREEN_WIDTH, height: SCREEN_HEIGHT, bytes: rgba.into() };
+
+    if clipboard.is_none() {
+        *clipboard = Clipboard::new().ok();
+    }
+
+    let error = match clipboard.as_mut().map(|clipboard| clipboard.set_image(image))
+    {
+        Some(Ok(())) => return "Copied screen to clipboard".to_string(),
+        Some(Err(error)) => error.to_string(),
+        None => "there's no clipboard".to_string()
+    };
+
+    let filename = get_screenshot_filename(rom_filename);
+    let mut rgb = ppu.output.clone();
+    match Surface::from_data(&mut rgb, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, (SCREEN_WIDTH * 3) as u32, PixelFormatEnum::RGB24)
+        .and_then(|surface| surface.save_bmp(&filename))
+    {
+        Ok(()) => format!("Could not copy screen to clipboard ({}), so saved it to {}", error, filename),
+        Err(save_error) => format!("Could not copy screen to clipboard ({}) or save it ({})", error, save_error)
     }
 }
 
+// Returns whether the user asked for the ROM at "rom_path" to be loaded
 fn draw_gui
 (
     // Emulation
     cpu: &mut Cpu,
     ppu: &mut Ppu,
     memory: &mut Memory,
+    emulation_error: &mut Option<EmuError>,
+    stepping: &mut Stepping,
 
     // Save states
-    saved_cpu: &mut Cpu,
-    saved_ppu: &mut Ppu,
-    saved_memory: &mut Memory,
+    save_slots: &mut [Option<Snapshot>; SAVE_SLOTS],
+    current_slot: usize,
+
+    // Loading ROMs
+    rom_filename: &str,
+    rom_path: &mut ImString,
+    rom_load_error: &Option<EmuError>,
+
+    // Cheats
+    cheat_code: &mut ImString,
+    cheat_error: &mut Option<EmuError>,
+    poke: &mut Poke,
+
+    // Timing
+    speed_index: &mut usize,
+    measured_fps: f64,
 
     // Input and output
     output_texture: u32,
     pattern_table_textures: &[u32; 2],
+    heatmap_texture: u32,
+    tiles_used: &mut Option<usize>,
     palette: &mut u8,
+    chr_bank: &mut Option<usize>,
+    display_settings: &mut DisplaySettings,
+    show_help: &mut bool,
+    notifications: &mut Notifications,
 
     // Rendering
     imgui: &mut Context,
@@ -307,7 +1148,7 @@ fn draw_gui
     renderer: &Renderer,
     window: &sdl2::video::Window,
     event_pump: &mut EventPump
-)
+) -> bool
 {
     // Prepare ImGui
     imgui_sdl2.prepare_frame(imgui.io_mut(), window, &event_pump.mouse_state());
@@ -318,13 +1159,25 @@ fn draw_gui
         gl::ClearColor(0.0, 0.0, 0.0, 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT);
 
-        gl::BindTexture(gl::TEXTURE_2D, output_texture);
-        gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32, gl::RGB, gl::UNSIGNED_BYTE, ppu.output.as_ptr() as *const c_void);
+        // The output only changes once per frame (and not at all if emulation's stopped), so don't upload it needlessly
+        if ppu.output_dirty
+        {
+            gl::BindTexture(gl::TEXTURE_2D, output_texture);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32, gl::RGB, gl::UNSIGNED_BYTE, ppu.output.as_ptr() as *const c_void);
+            ppu.output_dirty = false;
+        }
 
-        for i in 0..pattern_table_textures.len()
+        // Decoding the pattern tables is pretty slow, and they rarely change, so only do so when they have (and
+        // are actually going to be seen)
+        if ppu.pattern_tables_dirty && !display_settings.hide_debugger
         {
-            gl::BindTexture(gl::TEXTURE_2D, pattern_table_textures[i]);
-            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, gl::RGB, gl::UNSIGNED_BYTE, ppu.get_pattern_table(memory, i as u8, *palette).as_ptr() as *const c_void);
+            for i in 0..pattern_table_textures.len()
+            {
+                gl::BindTexture(gl::TEXTURE_2D, pattern_table_textures[i]);
+                gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, gl::RGB, gl::UNSIGNED_BYTE, ppu.get_pattern_table(memory, i as u8, *palette, *chr_bank).as_ptr() as *const c_void);
+            }
+
+            ppu.pattern_tables_dirty = false;
         }
     }
 
@@ -342,21 +1195,133 @@ fn draw_gui
     let output_width = (SCREEN_WIDTH*SCREEN_SCALE) as f32;
     let output_height = (SCREEN_HEIGHT*SCREEN_SCALE) as f32;
 
+    // Everything else is laid out around the above, but the output window itself can take up the whole window (or, with
+    // the debugger hidden, the whole window without even a title bar, however big it is)
+    let [display_width, display_height] = ui.io().display_size;
+    let (output_window_x, output_window_y, output_window_width, output_window_height) = if display_settings.hide_debugger {
+        (0.0, 0.0, display_width, display_height)
+    } else if display_settings.fit_to_window {
+        (output_x, output_y, WINDOW_WIDTH as f32 - margin * 2.0, WINDOW_HEIGHT as f32 - margin * 2.0 - bar_height - border_size)
+    } else { (output_x, output_y, output_width, output_height) };
+    let output_bar_height = if display_settings.hide_debugger { 0.0 } else { bar_height + border_size };
+
+    // Only part of the output's visible, once overscan's taken off
+    let overscan = &display_settings.overscan;
+    let visible_width = (SCREEN_WIDTH as u32 - overscan.left - overscan.right) as f32;
+    let visible_height = (SCREEN_HEIGHT as u32 - overscan.top - overscan.bottom) as f32;
+    let uv0 = [overscan.left as f32 / SCREEN_WIDTH as f32, overscan.top as f32 / SCREEN_HEIGHT as f32];
+    let uv1 = [1.0 - overscan.right as f32 / SCREEN_WIDTH as f32, 1.0 - overscan.bottom as f32 / SCREEN_HEIGHT as f32];
+
+    // Fit the image inside that as best we can for the chosen aspect ratio, centering it in whatever's left over
+    let aspect_ratio = visible_width / visible_height * if display_settings.correct_aspect_ratio { PIXEL_ASPECT_RATIO } else { 1.0 };
+    let image_width = output_window_width.min(output_window_height * aspect_ratio);
+    let image_height = image_width / aspect_ratio;
+
     Window::new(im_str!("Output"))
-        .position([output_x, output_y], Condition::Always)
+        .position([output_window_x, output_window_y], Condition::Always)
+        .size([output_window_width, output_window_height + output_bar_height], Condition::Always)
+        .title_bar(!display_settings.hide_debugger)
         .resizable(false)
         .build(&ui, ||
         {
-            Image::new(TextureId::from(output_texture as usize), [output_width, output_height]).build(&ui);
+            let [cursor_x, cursor_y] = ui.cursor_pos();
+            ui.set_cursor_pos([cursor_x + (output_window_width - image_width) / 2.0, cursor_y + (output_window_height - image_height) / 2.0]);
+            Image::new(TextureId::from(output_texture as usize), [image_width, image_height]).uv0(uv0).uv1(uv1).build(&ui);
+
+            // Holding shift whilst hovering shows what the background's made of at that point (it'd only get in the way otherwise)
+            if ui.is_item_hovered() && ui.io().key_shift
+            {
+                let [mouse_x, mouse_y] = ui.io().mouse_pos;
+                let [image_x, image_y] = ui.item_rect_min();
+                let x = (overscan.left as usize + ((mouse_x - image_x) / image_width * visible_width) as usize).min(SCREEN_WIDTH - 1);
+                let y = (overscan.top as usize + ((mouse_y - image_y) / image_height * visible_height) as usize).min(SCREEN_HEIGHT - 1);
+                let background = ppu.get_background_pixel(memory, x, y);
+
+                ui.tooltip_text(format!("({}, {})\nTile: {:#04x} at {:#06x}\nPalette: {}\nPixel: {}\nColour: {:#04x}",
+                    x, y, background.tile_id, background.name_table_address, background.palette, background.pixel, background.colour));
+            }
         });
 
     padding.pop(&ui);
 
+    // Controls, on top of everything else whilst open
+    if *show_help
+    {
+        Window::new(im_str!("Help"))
+            .position([WINDOW_WIDTH as f32 / 2.0, WINDOW_HEIGHT as f32 / 2.0], Condition::Appearing)
+            .position_pivot([0.5, 0.5])
+            .always_auto_resize(true)
+            .collapsible(false)
+            .focused(true)
+            .opened(show_help)
+            .build(&ui, ||
+            {
+                ui.text("Controller one");
+                ui.separator();
+                for (scancode, _, name) in KEY_BINDINGS.iter().chain(TURBO_KEY_BINDINGS.iter())
+                {
+                    ui.text(format!("{:<10} {}", scancode.name(), name));
+                }
+
+                ui.spacing();
+                ui.text("Gamepad (any connected)");
+                ui.separator();
+                for (button, _, name) in BUTTON_BINDINGS.iter()
+                {
+                    ui.text(format!("{:<10} {}", button.string(), name));
+                }
+                ui.text(format!("{:<10} {}", "Left stick", "Directions (see --directions)"));
+
+                ui.spacing();
+                ui.text("Emulator");
+                ui.separator();
+                for (key, action) in HOTKEYS.iter()
+                {
+                    ui.text(format!("{:<10} {}", key, action));
+                }
+            });
+    }
+
+    // Notifications, stacked up from the bottom left of the output, with the oldest at the top
+    notifications.messages.retain(|(_, time)| time.elapsed().as_secs_f32() < NOTIFICATION_DURATION);
+    if !notifications.messages.is_empty()
+    {
+        Window::new(im_str!("##notifications"))
+            .position([output_window_x + margin * 2.0, output_window_y + output_bar_height + output_window_height - margin * 2.0], Condition::Always)
+            .position_pivot([0.0, 1.0])
+            .always_auto_resize(true)
+            .no_decoration()
+            .no_inputs()
+            .no_nav()
+            .focus_on_appearing(false)
+            .bg_alpha(0.5)
+            .build(&ui, ||
+            {
+                for (message, time) in notifications.messages.iter()
+                {
+                    // Fade out over the last second
+                    let alpha = (NOTIFICATION_DURATION - time.elapsed().as_secs_f32()).clamp(0.0, 1.0);
+                    let fade = ui.push_style_var(StyleVar::Alpha(alpha));
+                    ui.text(message);
+                    fade.pop(&ui);
+                }
+            });
+    }
+
+    // Just playing, so that's everything
+    if display_settings.hide_debugger
+    {
+        border.pop(&ui);
+        imgui_sdl2.prepare_render(&ui, window);
+        renderer.render(ui);
+        return false
+    }
+
     // Registers
     let cpu_section_width = 700;
     let registers_x = output_x + output_width + border_size + margin - 1.0;
     let registers_width = cpu_section_width as f32 - registers_x - margin;
-    let registers_height = 140.0;
+    let registers_height = 257.0;
 
     Window::new(im_str!("Registers"))
         .position([registers_x, output_y], Condition::Always)
@@ -370,6 +1335,61 @@ fn draw_gui
             ui.text(format!("A: {:#04x}", cpu.a));
             ui.text(format!("X: {:#04x}", cpu.x));
             ui.text(format!("Y: {:#04x}", cpu.y));
+            ui.text(format!("Cycles: {}", cpu.total_cycles));
+            ui.text(format!("Frame: {} ({:.1} FPS)", ppu.frame_count, measured_fps));
+            ui.text(format!("Scanline: {}, dot: {}", ppu.get_scanline(), ppu.get_dot()));
+
+            // Stepping only makes sense whilst paused (and there's no stepping out of an error, but going back will)
+            if ui.small_button(if stepping.paused { im_str!("Resume") } else { im_str!("Pause") })
+            {
+                if stepping.paused { stepping.resume(); } else { stepping.paused = true; }
+            }
+
+            if stepping.paused
+            {
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Step")) && emulation_error.is_none()
+                {
+                    if let Err(error) = stepping.step(cpu, ppu, memory)
+                    {
+                        notifications.push(format!("Emulation stopped: {}", error));
+                        *emulation_error = Some(error);
+                    }
+                }
+
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Step back"))
+                {
+                    if stepping.step_back(cpu, ppu, memory) {
+                        *emulation_error = None;
+                    } else {
+                        notifications.push("Nothing to step back to".to_string());
+                    }
+                }
+
+                if ui.small_button(im_str!("Step scanline")) && emulation_error.is_none()
+                {
+                    if let Err(error) = stepping.step_scanline(cpu, ppu, memory)
+                    {
+                        notifications.push(format!("Emulation stopped: {}", error));
+                        *emulation_error = Some(error);
+                    }
+                }
+            }
+
+            // Resetting just the one half of the machine, to see which of them a bug's living in (the whole thing's
+            // reset with F3, or from the "Miscellaneous" window)
+            if ui.small_button(im_str!("Reset CPU"))
+            {
+                *emulation_error = cpu.reset(ppu, memory).err();
+                notifications.push("Reset the CPU".to_string());
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Reset PPU"))
+            {
+                ppu.reset();
+                notifications.push("Reset the PPU".to_string());
+            }
         });
 
     // Stack
@@ -388,7 +1408,7 @@ fn draw_gui
 
                 for i in 0..bytes.len()
                 {
-                    bytes[i] = memory.read_byte(ppu, row * rows as u16 + i as u16, true);
+                    bytes[i] = memory.read_byte(ppu, row * rows as u16 + i as u16, true).unwrap_or(0);
                 }
 
                 ui.text_colored([0.3, 0.3, 0.3, 1.0], format!(
@@ -414,16 +1434,16 @@ fn draw_gui
                 let current_pc = cpu.pc;
 
                 // Fetch opcode
-                let opcode = memory.read_byte(ppu, cpu.pc, true);
+                let opcode = memory.read_byte(ppu, cpu.pc, true).unwrap_or(0);
                 let Instruction(name, _, addressing_mode, _) = &INSTRUCTIONS[opcode as usize];
-                cpu.pc += 1;
+                cpu.pc = cpu.pc.wrapping_add(1);
 
                 // Fetch operand
-                let operand = cpu.fetch_operand(ppu, memory, addressing_mode, true);
+                let operand = cpu.fetch_operand(ppu, memory, addressing_mode, true).map(|operand| operand.data).unwrap_or(0);
 
                 // Display
                 let colour = if row == 0 { [1.0, 1.0, 1.0, 1.0] } else { [0.3, 0.3, 0.3, 1.0] };
-                ui.text_colored(colour, format!("{:#06x} {} {:#06x}", current_pc, name, operand.data))
+                ui.text_colored(colour, format!("{:#06x} {} {:#06x}", current_pc, name, operand))
             }
 
             cpu.pc = old_pc;
@@ -454,29 +1474,357 @@ fn draw_gui
 
     pattern_table_padding.pop(&ui);
 
+    // ROM info; tucked away by default as it's not often needed
+    Window::new(im_str!("ROM info"))
+        .position([registers_x, output_y + registers_height + margin], Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .resizable(false)
+        .build(&ui, ||
+        {
+            let header = &memory.rom_header;
+            ui.text(format!("Format: iNES{}", if header.get_ines_version() == 2 { " 2.0" } else { "" }));
+            ui.text(format!("Mapper: {}", header.get_mapper_number()));
+            ui.text(format!("PRG ROM: {} KB", header.get_pgr_size() / 1024));
+            if header.has_chr_ram() { ui.text(format!("CHR RAM: {} KB", memory.chr_rom.len() / 1024)); }
+            else { ui.text(format!("CHR ROM: {} KB", header.get_chr_size() / 1024)); }
+            ui.text(format!("Mirroring: {:?}", header.get_mirroring()));
+            ui.text(format!("Battery: {}", if header.has_persistent_memory() { "yes" } else { "no" }));
+            ui.text(format!("Trainer: {}", if header.has_trainer() { "yes" } else { "no" }));
+            ui.text(format!("Region: {:?}", header.get_region()));
+        });
+
+    // Where the CPU's been lately, newest first; tucked away like the above, just below it
+    Window::new(im_str!("PC history"))
+        .position([registers_x, output_y + registers_height + margin + bar_height + margin], Condition::FirstUseEver)
+        .size([registers_width, 250.0], Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(&ui, ||
+        {
+            if ui.small_button(im_str!("Clear")) { cpu.pc_history.clear(); }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Print")) { println!("Last instructions executed (newest first):\n{}", cpu.pc_history.dump()); }
+
+            for (i, (pc, opcode)) in cpu.pc_history.iter().enumerate()
+            {
+                let Instruction(name, ..) = &INSTRUCTIONS[opcode as usize];
+                let colour = if i == 0 { [1.0, 1.0, 1.0, 1.0] } else { [0.3, 0.3, 0.3, 1.0] };
+                ui.text_colored(colour, format!("{:#06x} {} ({:#04x})", pc, name, opcode));
+            }
+        });
+
+    // The PPU's registers and flags, for raster effects and the like (especially with stepping, to watch them change)
+    Window::new(im_str!("PPU status"))
+        .position([registers_x, output_y + registers_height + margin + (bar_height + margin) * 2.0], Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .resizable(false)
+        .build(&ui, ||
+        {
+            let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+            ui.text(format!("V-blank: {}", yes_no(ppu.in_vblank())));
+            ui.text(format!("Sprite zero hit: {}", yes_no(ppu.sprite_zero_hit())));
+            ui.text(format!("Sprite overflow: {}", yes_no(ppu.sprite_overflow())));
+
+            let control = ppu.get_control();
+            ui.separator();
+            ui.text(format!("PPUCTRL: {:#04x}", control.bits()));
+            ui.text(format!("Name table: {:#06x}", 0x2000 + (control.bits() & 3) as u16 * 0x400));
+            ui.text(format!("Increment: {}", if control.contains(PpuControl::VRAM_ADDR_INCREMENT) { 32 } else { 1 }));
+            ui.text(format!("Sprite patterns: {}", if control.contains(PpuControl::SPRITE_PATTERN_ADDR) { "0x1000" } else { "0x0000" }));
+            ui.text(format!("Background patterns: {}", if control.contains(PpuControl::BACKROUND_PATTERN_ADDR) { "0x1000" } else { "0x0000" }));
+            ui.text(format!("Sprite size: {}", if control.contains(PpuControl::SPRITE_SIZE) { "8x16" } else { "8x8" }));
+            ui.text(format!("NMI: {}", yes_no(control.contains(PpuControl::GENERATE_NMI))));
+
+            let mask = ppu.get_mask();
+            ui.separator();
+            ui.text(format!("PPUMASK: {:#04x}", mask.bits()));
+            ui.text(format!("Background: {} (leftmost: {})", yes_no(mask.contains(PpuMask::SHOW_BACKGROUND)), yes_no(mask.contains(PpuMask::SHOW_BACKGROUND_IN_LEFTMOST_PIXELS))));
+            ui.text(format!("Sprites: {} (leftmost: {})", yes_no(mask.contains(PpuMask::SHOW_SPRITES)), yes_no(mask.contains(PpuMask::SHOW_SPRITES_IN_LEFTMOST_PIXELS))));
+            ui.text(format!("Greyscale: {}", yes_no(mask.contains(PpuMask::GREYSCALE))));
+            ui.text(format!("Emphasis: {}{}{}",
+                if mask.contains(PpuMask::EMPHASISE_RED) { "R" } else { "-" },
+                if mask.contains(PpuMask::EMPHASISE_GREEN) { "G" } else { "-" },
+                if mask.contains(PpuMask::EMPHASISE_BLUE) { "B" } else { "-" }));
+        });
+
+    // Which background tiles the name tables are using, for seeing what CHR could be freed up. Scanning them
+    // every frame would be a waste when they're only looked at now and again, so it's only done on request.
+    Window::new(im_str!("Tile heatmap"))
+        .position([registers_x, output_y + registers_height + margin + (bar_height + margin) * 3.0], Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .resizable(false)
+        .build(&ui, ||
+        {
+            if ui.button(im_str!("Refresh"), [150.0, 20.0])
+            {
+                *tiles_used = Some(ppu.get_tile_usage(memory).iter().filter(|uses| **uses > 0).count());
+                unsafe
+                {
+                    gl::BindTexture(gl::TEXTURE_2D, heatmap_texture);
+                    gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, PATTERN_TABLE_SIZE as i32, PATTERN_TABLE_SIZE as i32, gl::RGB, gl::UNSIGNED_BYTE, ppu.get_tile_heatmap(memory, *palette, *chr_bank).as_ptr() as *const c_void);
+                }
+            }
+
+            if let Some(tiles_used) = tiles_used
+            {
+                ui.text(format!("{} of 256 tiles used", tiles_used));
+                Image::new(TextureId::from(heatmap_texture as usize), [(PATTERN_TABLE_SIZE * SCREEN_SCALE) as f32; 2]).build(&ui);
+            }
+        });
+
+    // Poking bytes into memory; tucked away with the rest
+    Window::new(im_str!("Poke"))
+        .position([registers_x, output_y + registers_height + margin + (bar_height + margin) * 4.0], Condition::FirstUseEver)
+        .collapsed(true, Condition::FirstUseEver)
+        .resizable(false)
+        .build(&ui, ||
+        {
+            let width = ui.push_item_width(60.0);
+            ui.input_text(im_str!("Address"), &mut poke.address).resize_buffer(true).build();
+            ui.input_text(im_str!("Value"), &mut poke.value).resize_buffer(true).build();
+            width.pop(&ui);
+            ui.checkbox(im_str!("Side effects"), &mut poke.allow_side_effects);
+            if ui.is_item_hovered() { ui.tooltip_text("Allow writes outside of RAM, which go to registers (PPU, APU, DMA or the mapper's) and act on them"); }
+
+            if ui.button(im_str!("Poke"), [150.0, 20.0])
+            {
+                match poke.write(ppu, memory)
+                {
+                    Ok((address, value)) =>
+                    {
+                        notifications.push(format!("Wrote {:#04x} to {:#06x}", value, address));
+                        poke.error = None;
+                    }
+                    Err(error) => poke.error = Some(error)
+                }
+            }
+
+            if let Some(error) = &poke.error
+            {
+                let wrap = ui.push_text_wrap_pos(0.0);
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+                wrap.pop(&ui);
+            }
+        });
+
     // Misc menu
+    let mut load_rom_requested = false;
     Window::new(im_str!("Miscellaneous"))
         .position([pattern_table_x, output_y + pattern_table_window_height*2.0], Condition::Always)
         .size([pattern_table_size, WINDOW_HEIGHT as f32 - pattern_table_window_height*2.0 - margin*2.0], Condition::Always)
         .resizable(false)
         .build(&ui, ||
         {
-            imgui::Slider::new(im_str!("Palette")).range(RangeInclusive::new(0, 7))
-                .build(&ui, palette);
+            if imgui::Slider::new(im_str!("Palette")).range(RangeInclusive::new(0, 7))
+                .build(&ui, palette)
+            {
+                ppu.pattern_tables_dirty = true;
+            }
+
+            // Games with more than one bank of CHR can have any of them shown, rather than just what's in use
+            let chr_banks = memory.chr_rom.len() / 0x2000;
+            if chr_banks > 1
+            {
+                let mut live = chr_bank.is_none();
+                if ui.checkbox(im_str!("Show current CHR bank"), &mut live)
+                {
+                    *chr_bank = if live { None } else { Some(0) };
+                    ppu.pattern_tables_dirty = true;
+                }
+
+                if let Some(bank) = chr_bank
+                {
+                    let mut selected = *bank as u32;
+                    if imgui::Slider::new(im_str!("CHR bank")).range(RangeInclusive::new(0, chr_banks as u32 - 1))
+                        .build(&ui, &mut selected)
+                    {
+                        *bank = selected as usize;
+                        ppu.pattern_tables_dirty = true;
+                    }
+                }
+            }
+
+            ui.checkbox(im_str!("Correct aspect ratio"), &mut display_settings.correct_aspect_ratio);
+
+            let overscan = &mut display_settings.overscan;
+            for (label, pixels) in [(im_str!("Crop top"), &mut overscan.top), (im_str!("Crop bottom"), &mut overscan.bottom),
+                (im_str!("Crop left"), &mut overscan.left), (im_str!("Crop right"), &mut overscan.right)]
+            {
+                // Values can be typed in too (with ctrl-click), which doesn't respect the range
+                if imgui::Slider::new(label).range(RangeInclusive::new(0, MAX_OVERSCAN)).build(&ui, pixels) {
+                    *pixels = (*pixels).min(MAX_OVERSCAN);
+                }
+            }
+            ui.checkbox(im_str!("Fit output to window"), &mut display_settings.fit_to_window);
+            imgui::Slider::new(im_str!("Frame skip")).range(RangeInclusive::new(0, MAX_FRAME_SKIP)).build(&ui, &mut display_settings.frame_skip);
+
+            // Only the filtering needs changing, so there's no need to recreate the texture
+            if ui.checkbox(im_str!("Smooth output"), &mut display_settings.smooth_output)
+            {
+                let filter = if display_settings.smooth_output { gl::LINEAR } else { gl::NEAREST };
+                unsafe
+                {
+                    gl::BindTexture(gl::TEXTURE_2D, output_texture);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+                }
+            }
+
+            // Stops emulation if the PC wanders off into I/O registers and the like
+            ui.checkbox(im_str!("Execution guard"), &mut cpu.execution_guard);
+
+            // Only matters for mappers made from discrete logic, but some games were released on both kinds of board
+            ui.checkbox(im_str!("Bus conflicts"), &mut memory.bus_conflicts);
+
+            // Off to draw every sprite, rather than the first 8 on each line, which gets rid of flicker
+            ui.checkbox(im_str!("Sprite limit"), &mut display_settings.sprite_limit);
+
+            // Purely a debugging aid; no game should ever need it
+            let mut mirroring = MIRRORING_OVERRIDES.iter().position(|(_, mirroring)| *mirroring == memory.mirroring_override).unwrap_or(0);
+            let labels: Vec<ImString> = MIRRORING_OVERRIDES.iter().map(|(label, _)| ImString::new(*label)).collect();
+            let labels: Vec<&ImStr> = labels.iter().map(|label| label.as_ref()).collect();
+            if imgui::ComboBox::new(im_str!("Mirroring (debug)")).build_simple_string(&ui, &mut mirroring, &labels) {
+                memory.mirroring_override = MIRRORING_OVERRIDES[mirroring].1;
+            }
+
+            // The same high- and low-pass filtering as the real thing, or off for the raw output
+            ui.checkbox(im_str!("Audio filters"), &mut memory.apu.filters_enabled);
+
+            ui.text(format!("Speed: {}x", SPEED_MULTIPLIERS[*speed_index]));
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("-")) { *speed_index = speed_index.saturating_sub(1); }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("+")) { *speed_index = (*speed_index + 1).min(SPEED_MULTIPLIERS.len() - 1); }
+
+            let slot = &mut save_slots[current_slot];
+            ui.text(format!("Save slot: {}{}", current_slot, if slot.is_none() { " (empty)" } else { "" }));
 
             ui.button(im_str!("Save emulation state"), [150.0, 20.0]).then(||
             {
-                *saved_cpu = *cpu;
-                *saved_ppu = *ppu;
-                *saved_memory = memory.clone();
+                *slot = Some(Snapshot::from_machine(cpu, ppu, memory));
+                notifications.push(format!("Saved state to slot {}", current_slot));
             });
 
             ui.button(im_str!("Load emulation state"), [150.0, 20.0]).then(||
+            {
+                if let Some(snapshot) = slot
+                {
+                    snapshot.restore(cpu, ppu, memory);
+                    *emulation_error = None;
+
+                    // Neither CHR nor the output might match what's currently on show
+                    ppu.pattern_tables_dirty = true;
+                    ppu.output_dirty = true;
+                    notifications.push(format!("Loaded state from slot {}", current_slot));
+                }
+                else
+                {
+                    notifications.push(format!("Save slot {} is empty", current_slot));
+                }
+            });
+
+            // The same, but kept on disk (see snapshot.rs for what's in them), so they last between runs
+            ui.button(im_str!("Save state to file"), [150.0, 20.0]).then(||
+            {
+                let filename = get_state_filename(rom_filename, current_slot);
+                match Snapshot::from_machine(cpu, ppu, memory).save_to_file(&filename, memory.get_rom_hash())
                 {
-                *cpu = *saved_cpu;
-                *ppu = *saved_ppu;
-                *memory = saved_memory.clone();
+                    Ok(()) => notifications.push(format!("Saved state to {}", filename)),
+                    Err(error) => notifications.push(error.to_string())
+                }
             });
+
+            ui.button(im_str!("Load state from file"), [150.0, 20.0]).then(||
+            {
+                let filename = get_state_filename(rom_filename, current_slot);
+                match Snapshot::from_file(&filename, cpu, ppu, memory)
+                {
+                    Ok(snapshot) =>
+                    {
+                        snapshot.restore(cpu, ppu, memory);
+                        *emulation_error = None;
+                        ppu.output_dirty = true;
+                        notifications.push(format!("Loaded state from {}", filename));
+                    }
+                    Err(error) => notifications.push(error.to_string())
+                }
+            });
+
+            ui.button(im_str!("Reset"), [150.0, 20.0]).then(||
+            {
+                *emulation_error = reset(cpu, ppu, memory).err();
+                notifications.push("Reset".to_string());
+            });
+
+            // Name tables, palettes and OAM, one after the other (see "Ppu::dump_vram")
+            if ui.button(im_str!("Dump VRAM"), [150.0, 20.0])
+            {
+                let filename = get_vram_dump_filename(rom_filename);
+                match std::fs::write(&filename, ppu.dump_vram())
+                {
+                    Ok(()) => notifications.push(format!("Dumped VRAM to {}", filename)),
+                    Err(error) => notifications.push(format!("Could not dump VRAM to {}: {}", filename, error))
+                }
+            }
+
+            if let Some(error) = emulation_error
+            {
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Emulation stopped: {}", error));
+            }
+
+            // Not an error as such (the rest of the machine carries on), but nothing more's going to happen
+            if cpu.halted
+            {
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("CPU jammed at {:#06x}", cpu.pc));
+            }
+
+            ui.separator();
+            ui.input_text(im_str!("##rom_path"), rom_path).resize_buffer(true).build();
+            load_rom_requested = ui.button(im_str!("Load ROM"), [150.0, 20.0]);
+
+            // Errors can be long (e.g. listing supported mappers), so wrap them
+            if let Some(error) = rom_load_error
+            {
+                let wrap = ui.push_text_wrap_pos(0.0);
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Could not load ROM: {}", error));
+                wrap.pop(&ui);
+            }
+
+            // Cheats, either as Game Genie codes or raw "address:value" pairs
+            ui.separator();
+            ui.input_text(im_str!("##cheat_code"), cheat_code).resize_buffer(true).build();
+            if ui.button(im_str!("Add cheat"), [150.0, 20.0])
+            {
+                match Cheat::from_code(cheat_code.to_str())
+                {
+                    Ok(cheat) =>
+                    {
+                        memory.cheats.push(cheat);
+                        cheat_code.clear();
+                        *cheat_error = None;
+                    }
+                    Err(error) => *cheat_error = Some(error)
+                }
+            }
+
+            if let Some(error) = cheat_error
+            {
+                let wrap = ui.push_text_wrap_pos(0.0);
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("{}", error));
+                wrap.pop(&ui);
+            }
+
+            let mut removed_cheat = None;
+            for (i, cheat) in memory.cheats.iter_mut().enumerate()
+            {
+                let id = ui.push_id(i as i32);
+                ui.checkbox(&ImString::new(format!("{:04x}={:02x}", cheat.address, cheat.value)), &mut cheat.enabled);
+                if ui.is_item_hovered() { ui.tooltip_text(&cheat.code); }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("x")) { removed_cheat = Some(i); }
+                id.pop(&ui);
+            }
+
+            if let Some(i) = removed_cheat { memory.cheats.remove(i); }
         });
 
     border.pop(&ui);
@@ -484,4 +1832,6 @@ fn draw_gui
     // Render ImGui
     imgui_sdl2.prepare_render(&ui, &window);
     renderer.render(ui);
+
+    load_rom_requested
 }
\ No newline at end of file
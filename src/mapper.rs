@@ -0,0 +1,136 @@
+use super::error::EmuError;
+use super::memory::RomHeader;
+use super::snapshot::{StateReader, StateWriter};
+
+// Cartridges fit more ROM into the NES's address space than it'd otherwise allow by switching "banks" in and out,
+// usually by having the game write to registers that sit over the ROM itself. Each mapper turns CPU and PPU addresses
+// into offsets within PRG and CHR, whilst memory keeps hold of the ROM and decides what's a register write.
+
+pub const SUPPORTED_MAPPERS: [u8; 3] = [0, 3, 66];
+
+pub trait Mapper
+{
+    // 0x8000-0xffff to an offset in PRG ROM
+    fn map_prg_address(&self, address: u16) -> usize;
+
+    // 0x0000-0x1fff to an offset in CHR
+    fn map_chr_address(&self, address: u16) -> usize;
+
+    // Anything written to 0x8000-0xffff
+    fn map_cpu_write(&mut self, address: u16, value: u8);
+
+    // Boards built from discrete logic (rather than a mapper chip) leave the ROM driving the bus whilst the
+    // CPU writes to it, so the value that arrives is ANDed with whatever's in ROM at that address
+    fn has_bus_conflicts(&self) -> bool;
+
+    // Whichever banks are switched in, for save states (see snapshot.rs)
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>;
+
+    // So that memory (and thus snapshots) can still be cloned
+    fn box_clone(&self) -> Box<dyn Mapper>;
+}
+
+impl Clone for Box<dyn Mapper>
+{
+    fn clone(&self) -> Self
+    {
+        self.box_clone()
+    }
+}
+
+pub fn from_rom_header(header: &RomHeader) -> Result<Box<dyn Mapper>, EmuError>
+{
+    match header.get_mapper_number()
+    {
+        0 => Ok(Box::new(Nrom { pgr_size: header.get_pgr_size() })),
+        3 => Ok(Box::new(Cnrom { pgr_size: header.get_pgr_size(), chr_banks: (header.get_chr_size() / 0x2000).max(1), chr_bank: 0 })),
+        66 => Ok(Box::new(Gxrom
+        {
+            pgr_size: header.get_pgr_size(),
+            chr_banks: (header.get_chr_size() / 0x2000).max(1),
+            prg_bank: 0,
+            chr_bank: 0
+        })),
+        mapper => Err(EmuError::UnsupportedMapper(mapper))
+    }
+}
+
+// 16 or 32 KB of PRG (the former being mirrored) and 8 KB of CHR, with nothing to switch
+#[derive(Clone)]
+pub struct Nrom
+{
+    pgr_size: usize
+}
+
+impl Mapper for Nrom
+{
+    fn map_prg_address(&self, address: u16) -> usize { (address as usize - 0x8000) % self.pgr_size }
+    fn map_chr_address(&self, address: u16) -> usize { address as usize }
+    fn map_cpu_write(&mut self, _address: u16, _value: u8) {}
+    fn has_bus_conflicts(&self) -> bool { false }
+    fn save_state(&self, _state: &mut StateWriter) {}
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), EmuError> { Ok(()) }
+    fn box_clone(&self) -> Box<dyn Mapper> { Box::new(self.clone()) }
+}
+
+// As NROM, but any write to ROM picks which 8 KB bank of CHR is in use
+#[derive(Clone)]
+pub struct Cnrom
+{
+    pgr_size: usize,
+    chr_banks: usize,
+    chr_bank: usize
+}
+
+impl Mapper for Cnrom
+{
+    fn map_prg_address(&self, address: u16) -> usize { (address as usize - 0x8000) % self.pgr_size }
+    fn map_chr_address(&self, address: u16) -> usize { self.chr_bank * 0x2000 + address as usize }
+    fn map_cpu_write(&mut self, _address: u16, value: u8) { self.chr_bank = value as usize % self.chr_banks; }
+    fn has_bus_conflicts(&self) -> bool { true }
+    fn save_state(&self, state: &mut StateWriter) { state.write_u8(self.chr_bank as u8); }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError> { self.chr_bank = state.read_u8()? as usize % self.chr_banks; Ok(()) }
+    fn box_clone(&self) -> Box<dyn Mapper> { Box::new(self.clone()) }
+}
+
+// Switches all 32 KB of PRG and all 8 KB of CHR at once, from the two nibbles of whatever's written to ROM
+// (PRG bank in the top half and CHR bank in the bottom, though boards only wire up a couple of bits of each)
+#[derive(Clone)]
+pub struct Gxrom
+{
+    pgr_size: usize,
+    chr_banks: usize,
+    prg_bank: usize,
+    chr_bank: usize
+}
+
+impl Mapper for Gxrom
+{
+    // Anything less than 32 KB is mirrored, as with NROM
+    fn map_prg_address(&self, address: u16) -> usize { (self.prg_bank * 0x8000 + address as usize - 0x8000) % self.pgr_size }
+    fn map_chr_address(&self, address: u16) -> usize { self.chr_bank * 0x2000 + address as usize }
+
+    fn map_cpu_write(&mut self, _address: u16, value: u8)
+    {
+        self.prg_bank = (value >> 4) as usize % (self.pgr_size / 0x8000).max(1);
+        self.chr_bank = (value & 0xf) as usize % self.chr_banks;
+    }
+
+    fn has_bus_conflicts(&self) -> bool { true }
+
+    fn save_state(&self, state: &mut StateWriter)
+    {
+        state.write_u8(self.prg_bank as u8);
+        state.write_u8(self.chr_bank as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>
+    {
+        self.prg_bank = state.read_u8()? as usize % (self.pgr_size / 0x8000).max(1);
+        self.chr_bank = state.read_u8()? as usize % self.chr_banks;
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> { Box::new(self.clone()) }
+}
@@ -1,6 +1,10 @@
 use super::ppu::Ppu;
-use std::fs::File;
-use std::io::Read;
+use super::ppu::Region;
+use super::error::EmuError;
+use super::cheat::Cheat;
+use super::apu::Apu;
+use super::snapshot::{StateReader, StateWriter};
+use super::mapper::{self, Mapper};
 use std::ops::BitAnd;
 use bitflags::bitflags;
 
@@ -8,22 +12,92 @@ use bitflags::bitflags;
 pub struct Memory
 {
     pub ram: [u8; 2048],
+    pub prg_ram: [u8; 8192], // Cartridge RAM at 0x6000-0x7fff - often battery-backed
     pub pgr_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
     pub internal_controller: [u8; 2], // What is readable by the CPU; has to be written to update
     pub controller: [u8; 2], // The actual state, as set by the emulator
+    pub controller_strobe: bool, // Whilst set, "internal_controller" is continually reloaded
     pub rom_header: RomHeader,
+    pub mapper: Box<dyn Mapper>,
+    pub bus_conflicts: bool, // Defaults to whatever's usual for the mapper, but some games want the opposite
+    pub mirroring_override: Option<Mirroring>, // For debugging; none means whatever the cartridge says
+
+    // Whatever was last read from or written to the data bus, which lingers for reads that don't drive every bit
+    pub last_bus_value: u8,
+
+    // Applied to every CPU read; these belong to the user rather than the game, so aren't kept in snapshots
+    pub cheats: Vec<Cheat>,
+
+    // Only the bits games wait on, for now (see apu.rs)
+    pub apu: Apu,
 
     // DMA
     pub dma_page: u8,
     pub dma_address: u8,
     pub dma_data: u8,
     pub dma_happening: bool,
-    pub dma_waiting_for_sync: bool,
+    pub dma_cycles_left: u16, // How long the CPU's still stalled for, once the DMA's got going (and 0 until then)
+}
+
+// How the PPU's four logical name tables (0x2000, 0x2400, 0x2800 and 0x2c00) map onto the two it actually has,
+// which is wired up by the cartridge. Four-screen boards bring their own VRAM for the other two, and single-screen
+// ones put all four in the same place.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mirroring
+{
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen
+}
+
+impl Mirroring
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "horizontal" => Some(Mirroring::Horizontal),
+            "vertical" => Some(Mirroring::Vertical),
+            "single-lower" => Some(Mirroring::SingleScreenLower),
+            "single-upper" => Some(Mirroring::SingleScreenUpper),
+            "four-screen" => Some(Mirroring::FourScreen),
+            _ => None
+        }
+    }
+
+    // Which of the four physical name tables (see "Ppu::name_tables") a logical one (0-3) ends up in
+    pub fn get_name_table(&self, logical: usize) -> usize
+    {
+        match self
+        {
+            Mirroring::Horizontal => logical / 2,
+            Mirroring::Vertical => logical % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => logical
+        }
+    }
 }
 
 bitflags!
 {
+    // Which bit of a controller's byte each button is; the CPU reads them out from the top down, starting with A
+    #[derive(Default)]
+    pub struct Buttons: u8
+    {
+        const A      = 0b10000000;
+        const B      = 0b01000000;
+        const SELECT = 0b00100000;
+        const START  = 0b00010000;
+        const UP     = 0b00001000;
+        const DOWN   = 0b00000100;
+        const LEFT   = 0b00000010;
+        const RIGHT  = 0b00000001;
+    }
+
     #[derive(Default)]
     struct FlagsSix: u8
     {
@@ -37,20 +111,36 @@ bitflags!
     #[derive(Default)]
     struct FlagsSeven: u8
     {
+        const VS_UNISYSTEM = 0b1;
+        const PLAYCHOICE_10 = 0b10;
+        const NES_2_IDENTIFIER = 0b1100; // Equal to 0b1000 for NES 2.0 headers
         const MAPPER_NUMBER_HIGHER_NIBBLE = 0b11110000;
     }
 
     #[derive(Default)]
-    struct FlagsEight: u8 {}
+    struct FlagsEight: u8
+    {
+        const PRG_RAM_SIZE = 0b11111111; // In 8 KB units, with 0 meaning 8 KB too
+    }
 
     #[derive(Default)]
-    struct FlagsNine: u8 {}
+    struct FlagsNine: u8
+    {
+        const TV_SYSTEM = 0b1; // 0 = NTSC, 1 = PAL
+        const RESERVED = 0b11111110;
+    }
 
     #[derive(Default)]
-    struct FlagsTen: u8 {}
+    struct FlagsTen: u8 // Unofficial
+    {
+        const TV_SYSTEM = 0b11; // 0 = NTSC, 2 = PAL, 1 or 3 = dual compatible
+        const PRG_RAM_ABSENT = 0b10000;
+        const HAS_BUS_CONFLICTS = 0b100000;
+        const RESERVED = 0b11001100;
+    }
 }
 
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 #[allow(dead_code)]
 pub struct RomHeader
 {
@@ -68,6 +158,8 @@ impl RomHeader
 {
     fn from_bytes(bytes: &[u8; 16]) -> Self
     {
+        // Plenty of headers (particularly older ones) have junk in them, so every bit is accounted for in the
+        // flags, and truncating makes sure anything unexpected could never stop a ROM from loading anyway
         RomHeader
         {
             header_string: [
@@ -75,18 +167,20 @@ impl RomHeader
             ],
             pgr_size: bytes[4] as usize * 16384,
             chr_size: bytes[5] as usize * 8192,
-            flags_six: FlagsSix::from_bits(bytes[6]).unwrap(),
-            flags_seven: FlagsSeven::from_bits(bytes[7]).unwrap(),
-            flags_eight: FlagsEight::from_bits(bytes[8]).unwrap(),
-            flags_nine: FlagsNine::from_bits(bytes[9]).unwrap(),
-            flags_ten: FlagsTen::from_bits(bytes[10]).unwrap()
+            flags_six: FlagsSix::from_bits_truncate(bytes[6]),
+            flags_seven: FlagsSeven::from_bits_truncate(bytes[7]),
+            flags_eight: FlagsEight::from_bits_truncate(bytes[8]),
+            flags_nine: FlagsNine::from_bits_truncate(bytes[9]),
+            flags_ten: FlagsTen::from_bits_truncate(bytes[10])
         }
     }
 
-    fn get_mapper_number(&self) -> u8
+    // The below are read-only so that the header can be shown in the debugger
+
+    pub fn get_mapper_number(&self) -> u8
     {
-        return ((self.flags_seven.bits & FlagsSeven::MAPPER_NUMBER_HIGHER_NIBBLE.bits) << 4) |
-            (self.flags_six.bits & FlagsSix::MAPPER_NUMBER_LOWER_NIBBLE.bits);
+        return (self.flags_seven.bits & FlagsSeven::MAPPER_NUMBER_HIGHER_NIBBLE.bits) |
+            ((self.flags_six.bits & FlagsSix::MAPPER_NUMBER_LOWER_NIBBLE.bits) >> 4);
     }
 
     pub fn has_vertical_mirroring(&self) -> bool
@@ -94,28 +188,114 @@ impl RomHeader
         self.flags_six.contains(FlagsSix::MIRRORING)
     }
 
-    fn has_trainer(&self) -> bool
+    // Four-screen boards ignore the mirroring bit altogether
+    pub fn get_mirroring(&self) -> Mirroring
+    {
+        if self.flags_six.contains(FlagsSix::IGNORE_MIRRORING_CONTROL) { Mirroring::FourScreen }
+        else if self.has_vertical_mirroring() { Mirroring::Vertical }
+        else { Mirroring::Horizontal }
+    }
+
+    // Rarely set properly by dumps, so it's worth allowing the user to override it
+    pub fn get_region(&self) -> Region
+    {
+        if self.flags_nine.contains(FlagsNine::TV_SYSTEM) { Region::Pal } else { Region::Ntsc }
+    }
+
+    pub fn has_trainer(&self) -> bool
     {
         return !self.flags_six.bitand(FlagsSix::HAS_TRAINER).is_empty();
     }
+
+    pub fn get_pgr_size(&self) -> usize { self.pgr_size }
+    pub fn get_chr_size(&self) -> usize { self.chr_size }
+
+    // No CHR ROM means the board has 8 KB of RAM instead, for the game to copy its tiles into
+    pub fn has_chr_ram(&self) -> bool { self.chr_size == 0 }
+
+    // Usually a battery, so that saves last
+    pub fn has_persistent_memory(&self) -> bool
+    {
+        self.flags_six.contains(FlagsSix::CONTAINS_PERSISTENT_MEMORY)
+    }
+
+    // NES 2.0 headers are marked by bits 2 and 3 of flags 7 being 0b10 (the extra fields aren't understood yet, though)
+    pub fn get_ines_version(&self) -> u8
+    {
+        if self.flags_seven.bits & FlagsSeven::NES_2_IDENTIFIER.bits == 0b1000 { 2 } else { 1 }
+    }
+}
+
+// What the console's RAM holds when it's switched on. Real hardware is a bit random about this (and some games
+// and test ROMs behave differently depending on it), so zeroes are the default for the sake of reproducibility,
+// but a fixed fill or the pattern commonly seen on real consoles (four zeroes then four 0xffs) can be picked too.
+// For something that actually looks random, there's a seeded fill, which is still the same every time for the
+// same seed - so movies and frame hashes stay reproducible. (Anything else random that gets added later on should
+// come from the seed too, for the same reason.)
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum RamInitMode
+{
+    #[default]
+    Zeroed,
+    Filled(u8),
+    HardwarePattern,
+    Random(u64) // The seed
+}
+
+impl RamInitMode
+{
+    pub fn value_at(&self, address: usize) -> u8
+    {
+        match self
+        {
+            RamInitMode::Zeroed => 0,
+            RamInitMode::Filled(value) => *value,
+            RamInitMode::HardwarePattern => if address & 4 == 0 { 0x00 } else { 0xff },
+
+            // SplitMix64, which only needs the seed and the address, rather than any state carried between calls
+            RamInitMode::Random(seed) =>
+            {
+                let mut z = seed.wrapping_add((address as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+                (z ^ (z >> 31)) as u8
+            }
+        }
+    }
 }
 
 impl Memory
 {
-    pub fn default() -> Self
+    pub fn from_file(rom_filename: &str) -> Result<Self, EmuError>
+    {
+        Self::from_file_with_ram_init(rom_filename, RamInitMode::default())
+    }
+
+    pub fn from_file_with_ram_init(rom_filename: &str, ram_init: RamInitMode) -> Result<Self, EmuError>
     {
-        // Get std args
-        let args: Vec<String> = std::env::args().collect();
+        // Read whole ROM into buffer
+        let rom_data = std::fs::read(rom_filename).map_err(|error| EmuError::BadRom(format!("Could not read ROM file {}: {}", rom_filename, error)))?;
+        Self::from_bytes_with_ram_init(&rom_data, ram_init)
+    }
 
-        // Open ROM and get size
-        let rom_filename = args[1].clone();
-        let mut rom_file = File::open(&rom_filename).expect("Could not find ROM file");
-        let rom_size = std::fs::metadata(&rom_filename).expect("Could not read ROM metadata").len() as usize;
+    // For when there's no file to read from, as in the browser
+    pub fn from_bytes(rom_data: &[u8]) -> Result<Self, EmuError>
+    {
+        Self::from_bytes_with_ram_init(rom_data, RamInitMode::default())
+    }
 
-        // Fill into buffer
-        let mut rom_data = vec![0; rom_size];
-        rom_file.read(&mut rom_data).expect("Could not find enough space to read ROM into buffer");
+    // An NROM cartridge with nothing on it (and CHR RAM), for when there's no ROM to load but there still has to be
+    // a machine; there's nothing in it to run, so it's up to whoever makes one not to try
+    pub fn from_blank_cartridge() -> Self
+    {
+        let mut rom_data = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom_data.resize(16 + 0x4000, 0);
+        Self::from_bytes(&rom_data).expect("a blank cartridge should always load")
+    }
 
+    pub fn from_bytes_with_ram_init(rom_data: &[u8], ram_init: RamInitMode) -> Result<Self, EmuError>
+    {
         /*
             ROM will be in "iNES" format (aka ".nes" files), whereupon the structure will be as so:
             - First 16 bytes: header
@@ -125,43 +305,212 @@ impl Memory
          */
 
         // Get header
+        if rom_data.len() < 16 {
+            return Err(EmuError::BadRom(format!("ROM is only {} bytes long, which is too small to even contain a header", rom_data.len())));
+        }
+        // Disk images are another thing entirely (and need the FDS BIOS besides), but they're easy to mistake for a ROM,
+        // so it's worth saying so rather than just calling them a bad one. They either have a header of their own, or
+        // begin straight away with the first disk's info block.
+        if rom_data.starts_with(b"FDS\x1a") || rom_data.starts_with(b"\x01*NINTENDO-HVC*") {
+            return Err(EmuError::FdsNotSupported);
+        }
+
         let header = RomHeader::from_bytes(&rom_data[0..16].try_into().unwrap());
 
         // Check it's actually a .nes file
-        assert_eq!(&header.header_string[0..3], "NES".as_bytes());
+        if &header.header_string[0..3] != "NES".as_bytes() {
+            return Err(EmuError::BadRom("ROM is not in iNES format (the header doesn't begin with \"NES\")".to_string()));
+        }
+
+        // Every mapper needs some PRG to map, if only for the reset vector
+        if header.pgr_size == 0 {
+            return Err(EmuError::BadRom("ROM has no PRG ROM".to_string()));
+        }
 
         // Determine mapper type
-        if header.get_mapper_number() != 0 {
-            panic!("Attempted to load ROM with unrecognised mapper type {}", header.get_mapper_number());
+        let mapper = mapper::from_rom_header(&header)?;
+
+        // Make sure the file's actually as big as the header claims before slicing it up
+        let pgr_offset = 16 + if header.has_trainer() { 512 } else { 0 } as usize;
+        let chr_offset = pgr_offset + header.pgr_size;
+        let expected_size = chr_offset + header.chr_size;
+        if rom_data.len() < expected_size {
+            return Err(EmuError::BadRom(format!("ROM is truncated: the header describes {} bytes but the file is only {}", expected_size, rom_data.len())));
         }
 
         // Retrieve PGR ROM
-        let pgr_offset = 16 + if header.has_trainer() { 512 } else { 0 } as usize;
-        let pgr_rom = &rom_data[pgr_offset..pgr_offset + header.pgr_size as usize];
+        let pgr_rom = &rom_data[pgr_offset..chr_offset];
 
-        // Retrieve CHR ROM
-        let chr_offset = pgr_offset + header.pgr_size;
-        let chr_rom = &rom_data[chr_offset..chr_offset + header.chr_size as usize];
+        // Retrieve CHR ROM, or if there isn't any, make room for CHR RAM in its place (reads and writes go to the
+        // same buffer either way, so nothing else need know the difference)
+        let chr_rom = if header.has_chr_ram() { vec![0; 0x2000] } else { rom_data[chr_offset..expected_size].to_vec() };
+
+        // The trainer's rarely used, but the few old ROMs that have one expect to find it at 0x7000-0x71ff
+        let mut prg_ram = [0; 8192];
+        if header.has_trainer() {
+            prg_ram[0x1000..0x1200].copy_from_slice(&rom_data[16..pgr_offset]);
+        }
 
-        Memory
+        let mut ram = [0; 2048];
+        for (address, byte) in ram.iter_mut().enumerate() {
+            *byte = ram_init.value_at(address);
+        }
+
+        Ok(Memory
         {
-            ram: [0; 2048],
+            ram,
+            prg_ram,
             pgr_rom: pgr_rom.to_vec(),
-            chr_rom: chr_rom.to_vec(),
+            chr_rom,
             controller: [0; 2],
             internal_controller: [0; 2],
+            controller_strobe: false,
             rom_header: header,
+            bus_conflicts: mapper.has_bus_conflicts(),
+            mirroring_override: None,
+            mapper,
+            last_bus_value: 0,
+            cheats: Vec::new(),
+            apu: Apu::default(),
             dma_page: 0,
             dma_address: 0,
             dma_data: 0,
             dma_happening: false,
-            dma_waiting_for_sync: true,
+            dma_cycles_left: 0,
+        })
+    }
+
+    // What's held on a controller (0 or 1); there are only two ports, so anything else is ignored. The CPU sees it
+    // the next time it strobes the controllers.
+    pub fn set_controller(&mut self, id: usize, buttons: Buttons)
+    {
+        if let Some(controller) = self.controller.get_mut(id) {
+            *controller = buttons.bits();
+        }
+    }
+
+    // What the PPU goes by; none of the supported mappers can change it, so it's the header's unless overridden
+    pub fn current_mirroring(&self) -> Mirroring
+    {
+        self.mirroring_override.unwrap_or(self.rom_header.get_mirroring())
+    }
+
+    // Identifies the game (for movies and the like) with a 64-bit FNV-1a hash of its PRG ROM (CHR may well be
+    // written to, so is left out); not cryptographic, but it only has to notice the wrong game being used
+    pub fn get_rom_hash(&self) -> u64
+    {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.pgr_rom.iter()
+        {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
         }
+        hash
+    }
+
+    // As with the PPU, snapshots leave out what never changes (PRG ROM), but CHR is kept in case it's being written to
+
+    pub fn snapshot(&self) -> Self
+    {
+        Memory { pgr_rom: Vec::new(), chr_rom: self.chr_rom.clone(), mapper: self.mapper.clone(), cheats: Vec::new(), apu: self.apu.clone(), ..*self }
+    }
+
+    // As with the PPU, the user's settings (cheats, bus conflicts, the mirroring override and the audio filters)
+    // are kept as they are
+    pub fn restore(&mut self, snapshot: &Memory)
+    {
+        let pgr_rom = std::mem::take(&mut self.pgr_rom);
+        let cheats = std::mem::take(&mut self.cheats);
+        let mut apu = snapshot.apu.clone();
+        apu.filters_enabled = self.apu.filters_enabled;
+
+        *self = Memory
+        {
+            pgr_rom,
+            chr_rom: snapshot.chr_rom.clone(),
+            mapper: snapshot.mapper.clone(),
+            cheats,
+            apu,
+            bus_conflicts: self.bus_conflicts,
+            mirroring_override: self.mirroring_override,
+            ..*snapshot
+        };
+    }
+
+    // For when the machine's rebuilt from the same ROM (reloading it, or starting a movie), which shouldn't lose
+    // those same settings any more than restoring a snapshot does
+    pub fn keep_settings_from(&mut self, old: &mut Memory)
+    {
+        self.cheats = std::mem::take(&mut old.cheats);
+        self.bus_conflicts = old.bus_conflicts;
+        self.mirroring_override = old.mirroring_override;
+        self.apu.filters_enabled = old.apu.filters_enabled;
+    }
+
+    // CHR's only saved when it's RAM, as otherwise it's part of the ROM; cheats, bus conflicts and the mirroring
+    // override are the user's, so aren't saved at all
+    pub fn save_state(&self, state: &mut StateWriter)
+    {
+        state.write_bytes(&self.ram);
+        state.write_bytes(&self.prg_ram);
+        if self.rom_header.has_chr_ram() { state.write_bytes(&self.chr_rom); }
+        state.write_bytes(&self.internal_controller);
+        state.write_bytes(&self.controller);
+        state.write_bool(self.controller_strobe);
+        state.write_u8(self.last_bus_value);
+        self.apu.save_state(state);
+        self.mapper.save_state(state);
+
+        state.write_u8(self.dma_page);
+        state.write_u8(self.dma_address);
+        state.write_u8(self.dma_data);
+        state.write_bool(self.dma_happening);
+        state.write_u16(self.dma_cycles_left);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>
+    {
+        state.read_into(&mut self.ram)?;
+        state.read_into(&mut self.prg_ram)?;
+        if self.rom_header.has_chr_ram() { state.read_into(&mut self.chr_rom)?; }
+        state.read_into(&mut self.internal_controller)?;
+        state.read_into(&mut self.controller)?;
+        self.controller_strobe = state.read_bool()?;
+        self.last_bus_value = state.read_u8()?;
+        self.apu.load_state(state)?;
+        self.mapper.load_state(state)?;
+
+        self.dma_page = state.read_u8()?;
+        self.dma_address = state.read_u8()?;
+        self.dma_data = state.read_u8()?;
+        self.dma_happening = state.read_bool()?;
+        self.dma_cycles_left = state.read_u16()?;
+        Ok(())
+    }
+
+    // Whether there's any RAM or ROM at an address, which is to say anywhere code could reasonably be run from
+    pub fn is_executable(&self, address: u16) -> bool
+    {
+        address <= 0x1fff || address >= 0x6000
     }
 
     // For debugging purposes, reading must have no affect on internal registers like the PPU address
 
-    pub fn read_byte(&mut self, ppu: &mut Ppu, address: u16, debugger: bool) -> u8
+    pub fn read_byte(&mut self, ppu: &mut Ppu, address: u16, debugger: bool) -> Result<u8, EmuError>
+    {
+        let mut value = self.map_read_byte(ppu, address, debugger)?;
+
+        // As with a real Game Genie, the cheat's what ends up on the bus, so it's what lingers too
+        for cheat in self.cheats.iter()
+        {
+            if let Some(replacement) = cheat.apply(address, value) { value = replacement; }
+        }
+
+        if !debugger { self.last_bus_value = value; }
+        Ok(value)
+    }
+
+    fn map_read_byte(&mut self, ppu: &mut Ppu, address: u16, debugger: bool) -> Result<u8, EmuError>
     {
         /*
             0x0000-0x07ff - 2kb internal RAM
@@ -174,46 +523,56 @@ impl Memory
         */
 
         if address <= 0x1fff {
-            return self.ram[(address & 0x7ff) as usize];
+            return Ok(self.ram[(address & 0x7ff) as usize]);
         }
 
-        if address >= 0x2000 && address <= 0x2007 {
-            return ppu.read_byte_from_cpu(self, address, debugger);
+        // Only the bottom three bits are decoded, so the registers repeat every 8 bytes all the way up
+        if address >= 0x2000 && address <= 0x3fff {
+            return Ok(ppu.read_byte_from_cpu(self, 0x2000 | (address & 7), debugger));
         }
 
         if address == 0x4016 || address == 0x4017
         {
-            // Read from correct controller then shift bits down
+            // Read from correct controller then shift bits down - unless the strobe is still high,
+            // in which case the state keeps being reloaded, so we'll only ever see button A
             let id = (address & 1) as usize;
+            if self.controller_strobe { self.internal_controller[id] = self.controller[id]; }
+
+            // Official controllers shift in ones behind the buttons, so every read after the 8th returns 1
             let value = (self.internal_controller[id] & 0x80) > 0;
-            self.internal_controller[id] <<= 1;
-            return if value { 1 } else { 0 }
+            if !self.controller_strobe { self.internal_controller[id] = (self.internal_controller[id] << 1) | 1; }
+
+            // Only the bottom bits are actually driven, so the top three are whatever was last on the
+            // bus (usually 0x40 from the address itself, hence games commonly seeing 0x40 and 0x41)
+            return Ok((self.last_bus_value & 0xe0) | if value { 1 } else { 0 })
         }
 
-        if address >= 0x4000 && address <= 0x401f { return 0 }
+        if address == 0x4015 { return Ok(self.apu.read_status(self.last_bus_value, debugger)) }
+
+        if address >= 0x4000 && address <= 0x401f { return Ok(0) }
 
-        // Assume ROM with mapper type 0 - "NROM"
         else if address >= 0x4020
         {
-            // First 16 KB of ROM
-            if address >= 0x8000 && address <= 0xbfff { return self.pgr_rom[address as usize - 0x8000]; }
+            // Cartridge RAM
+            if address >= 0x6000 && address <= 0x7fff { return Ok(self.prg_ram[address as usize - 0x6000]); }
+
+            // ROM, wherever the mapper says it is
+            if address >= 0x8000 { return Ok(self.pgr_rom[self.mapper.map_prg_address(address)]); }
 
-            // Last 16 KB of ROM... or the first 16 KB mirrored (depending on size)
-            if address >= 0xc000 && self.rom_header.pgr_size == 0x4000 { return self.pgr_rom[address as usize - 0xc000]; }
-            if address >= 0xc000 && self.rom_header.pgr_size == 0x8000 { return self.pgr_rom[address as usize - 0x8000]; }
-            
 			// All other addresses are invalid, but may be called by the debugger, so as a "quick fix":
-			if debugger { return 0 }
+			if debugger { return Ok(0) }
         }
 
-        panic!("Could not map memory read for address {:#06x}", address);
+        Err(EmuError::UnmappedRead(address))
     }
 
-    pub fn read_word(&mut self, ppu: &mut Ppu, address: u16, debugger: bool) -> u16
+    // Little-endian, and read in that order too (as the 6502 does), which matters for anything with side effects
+
+    pub fn read_word(&mut self, ppu: &mut Ppu, address: u16, debugger: bool) -> Result<u16, EmuError>
     {
-        let high = self.read_byte(ppu, address.wrapping_add(1), debugger) as u16;
-        let low = self.read_byte(ppu, address, debugger) as u16;
-        (high << 8) | low
+        let low = self.read_byte(ppu, address, debugger)? as u16;
+        let high = self.read_byte(ppu, address.wrapping_add(1), debugger)? as u16;
+        Ok((high << 8) | low)
     }
 
     // In indirect addressing modes, we read words from memory, but the nature of the read
@@ -221,14 +580,14 @@ impl Memory
     // taking a u16 as a function argument causes problems, so to avoid human error by
     // calling this function in other places, it therefore has a very specific name!
 
-    pub fn read_word_from_first_page(&mut self, ppu: &mut Ppu, address: u8, debugger: bool) -> u16
+    pub fn read_word_from_first_page(&mut self, ppu: &mut Ppu, address: u8, debugger: bool) -> Result<u16, EmuError>
     {
-        let high = self.read_byte(ppu, address.wrapping_add(1) as u16, debugger) as u16;
-        let low = self.read_byte(ppu, address as u16, debugger) as u16;
-        (high << 8) | low
+        let low = self.read_byte(ppu, address as u16, debugger)? as u16;
+        let high = self.read_byte(ppu, address.wrapping_add(1) as u16, debugger)? as u16;
+        Ok((high << 8) | low)
     }
 
-    pub fn write_byte(&mut self, ppu: &mut Ppu, address: u16, value: u8)
+    pub fn write_byte(&mut self, ppu: &mut Ppu, address: u16, value: u8) -> Result<(), EmuError>
     {
         /*
             0x0000-0x07ff - 2kb internal RAM
@@ -240,16 +599,18 @@ impl Memory
             0x4020-0xffff - Actual cartridge ROM (subject to mappers)
         */
 
-        if address <= 0x7ff
+        self.last_bus_value = value;
+
+        if address <= 0x1fff
         {
-            self.ram[address as usize] = value;
-            return
+            self.ram[(address & 0x7ff) as usize] = value;
+            return Ok(())
         }
 
-        if address >= 0x2000 && address <= 0x2007
+        if address >= 0x2000 && address <= 0x3fff
         {
-            ppu.write_byte_from_cpu(self, address, value);
-            return
+            ppu.write_byte_from_cpu(self, 0x2000 | (address & 7), value);
+            return Ok(())
         }
 
         if address == 0x4014
@@ -260,26 +621,39 @@ impl Memory
             self.dma_happening = true;
         }
 
-        if address == 0x4016 || address == 0x4017
+        if address == 0x4016
         {
-            let id = (address & 1) as usize;
-            self.internal_controller[id] = self.controller[id];
+            // Bit 0 is the strobe, which (for both controllers) reloads the state whilst it's high, so
+            // the bits only start being shifted out once it's been written low again
+            self.controller_strobe = (value & 1) != 0;
+            if self.controller_strobe { self.internal_controller = self.controller; }
+        }
+
+        // Everything else up to here is the APU's (0x4017 included, as writing it doesn't touch the second controller)
+        if (0x4000..=0x4013).contains(&address) || address == 0x4015 || address == 0x4017 {
+            self.apu.write_register(address, value);
         }
 
-        if address >= 0x4000 && address <= 0x401f { return }
+        if address >= 0x4000 && address <= 0x401f { return Ok(()) }
 
-        // Assume ROM with mapper type 0 - "NROM"
         if address >= 0x4020
         {
-            // First 16 KB of ROM
-            if address >= 0x8000 && address <= 0xbfff { self.pgr_rom[address as usize - 0x8000] = value; return }
-
-            // Last 16 KB of ROM... or the first 16 KB mirrored (depending on size)
-            if address >= 0xc000 && self.rom_header.pgr_size == 0x4000 { self.pgr_rom[address as usize - 0xc000] = value; return }
-            if address >= 0xc000 && self.rom_header.pgr_size == 0x8000 { self.pgr_rom[address as usize - 0x8000] = value; return }
+            // Cartridge RAM
+            if address >= 0x6000 && address <= 0x7fff { self.prg_ram[address as usize - 0x6000] = value; return Ok(()) }
+
+            // The ROM itself is, well... read-only, but mappers tend to put their registers over it
+            if address >= 0x8000
+            {
+                let value = if self.bus_conflicts { value & self.pgr_rom[self.mapper.map_prg_address(address)] } else { value };
+                self.mapper.map_cpu_write(address, value);
+
+                // Which bank of CHR is in use may well have just changed
+                ppu.pattern_tables_dirty = true;
+                return Ok(())
+            }
         }
 
-        panic!("Could not map memory write for address {:#06x}", address);
+        Err(EmuError::UnmappedWrite(address))
     }
 
     pub fn pages_differ(&self, first_address: u16, second_address: u16) -> bool
@@ -295,14 +669,14 @@ impl Memory
     pub fn read_byte_from_ppu(&self, address: u16) -> (bool, u8)
     {
         // Address is relative to cartridge anyway because we're being called from the PPU
-        if address <= 0x1fff { return (true, self.chr_rom[address as usize]) }
+        if address <= 0x1fff { return (true, self.chr_rom[self.mapper.map_chr_address(address)]) }
         (false, 0)
     }
 
     pub fn write_byte_from_ppu(&mut self, address: u16, value: u8) -> bool
     {
         // Address is relative to cartridge anyway because we're being called from the PPU
-        if address <= 0x1fff { self.chr_rom[address as usize] = value; return true }
+        if address <= 0x1fff { let address = self.mapper.map_chr_address(address); self.chr_rom[address] = value; return true }
         false
     }
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use super::error::EmuError;
+
+// A recording of the controllers, frame by frame, from power on. As the emulation is entirely deterministic
+// (RAM starts zeroed, and there's nothing random in the CPU or PPU), playing the inputs back in the same
+// order gives exactly the same game, so long as it's the same ROM, which the hash is there to check.
+//
+// On disk, a movie is laid out as so (all little-endian):
+// - "MOV" followed by 0x1a (like iNES)
+// - Version (one byte)
+// - ROM hash (8 bytes; see "Memory::get_rom_hash")
+// - Number of frames (4 bytes)
+// - Each frame's controllers, one byte each for controllers one and two
+
+const MAGIC: [u8; 4] = [b'M', b'O', b'V', 0x1a];
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 17;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie
+{
+    pub rom_hash: u64,
+    pub inputs: Vec<(u8, u8)>
+}
+
+impl Movie
+{
+    pub fn from_rom_hash(rom_hash: u64) -> Self
+    {
+        Movie { rom_hash, inputs: Vec::new() }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EmuError>
+    {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+            return Err(EmuError::BadMovie("File is not a movie".to_string()));
+        }
+
+        if bytes[4] != VERSION {
+            return Err(EmuError::BadMovie(format!("Movie is version {}, but only version {} is supported", bytes[4], VERSION)));
+        }
+
+        let rom_hash = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+        let frames = u32::from_le_bytes(bytes[13..17].try_into().unwrap()) as usize;
+
+        if bytes.len() < HEADER_SIZE + frames * 2 {
+            return Err(EmuError::BadMovie(format!("Movie is truncated: it should have {} frames", frames)));
+        }
+
+        let inputs = bytes[HEADER_SIZE..HEADER_SIZE + frames * 2]
+            .chunks(2)
+            .map(|frame| (frame[0], frame[1]))
+            .collect();
+
+        Ok(Movie { rom_hash, inputs })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.inputs.len() * 2);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+
+        for (one, two) in &self.inputs
+        {
+            bytes.push(*one);
+            bytes.push(*two);
+        }
+
+        bytes
+    }
+
+    pub fn from_file(filename: &str) -> Result<Self, EmuError>
+    {
+        let bytes = std::fs::read(filename).map_err(|error| EmuError::BadMovie(format!("Could not read movie file {}: {}", filename, error)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), EmuError>
+    {
+        std::fs::write(filename, self.to_bytes()).map_err(|error| EmuError::BadMovie(format!("Could not write movie file {}: {}", filename, error)))
+    }
+}
@@ -99,6 +99,9 @@ pub enum Operation
     // Placeholder for unknown
     XXX,
 
+    // Locks up the CPU (see "IllegalOpcodePolicy")
+    JAM,
+
     // Unofficial opcodes
     LAX,
     SAX,
@@ -113,7 +116,15 @@ pub enum Operation
     ALR,
     ANC,
     ARR,
-    AXS
+    AXS,
+
+    // Unofficial and unstable, in that they depend on analogue effects that vary between consoles (see cpu.rs)
+    XAA,
+    AHX,
+    TAS,
+    SHY,
+    SHX,
+    LAS
 }
 
 pub fn operation_requires_fetched_argument(operation: &Operation) -> bool
@@ -152,6 +163,8 @@ pub fn operation_requires_fetched_argument(operation: &Operation) -> bool
         Operation::ANC => true,
         Operation::ARR => true,
         Operation::AXS => true,
+        Operation::XAA => true,
+        Operation::LAS => true,
 
         _ => false
     }
@@ -163,7 +176,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 [
     Instruction("BRK", Operation::BRK, AddressingMode::Immediate, 7),
     Instruction("ORA", Operation::ORA, AddressingMode::IndirectX, 6),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x02 - unofficial
     Instruction("SLO", Operation::SLO, AddressingMode::IndirectX, 8),       // 0x03 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPage, 3),        // 0x04 - unofficial
     Instruction("ORA", Operation::ORA, AddressingMode::ZeroPage, 3),
@@ -180,7 +193,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BPL", Operation::BPL, AddressingMode::Relative, 2),
     Instruction("ORA", Operation::ORA, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x12 - unofficial
     Instruction("SLO", Operation::SLO, AddressingMode::IndirectY, 8),       // 0x13 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0x14 - unofficial
     Instruction("ORA", Operation::ORA, AddressingMode::ZeroPageX, 4),
@@ -197,7 +210,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("JSR", Operation::JSR, AddressingMode::Absolute, 6),
     Instruction("AND", Operation::AND, AddressingMode::IndirectX, 6),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x22 - unofficial
     Instruction("RLA", Operation::RLA, AddressingMode::IndirectX, 8),       // 0x23 - unofficial
     Instruction("BIT", Operation::BIT, AddressingMode::ZeroPage, 3),
     Instruction("AND", Operation::AND, AddressingMode::ZeroPage, 3),
@@ -214,7 +227,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BMI", Operation::BMI, AddressingMode::Relative, 2),
     Instruction("AND", Operation::AND, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x32 - unofficial
     Instruction("RLA", Operation::RLA, AddressingMode::IndirectY, 8),       // 0x33 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0x34 - unofficial
     Instruction("AND", Operation::AND, AddressingMode::ZeroPageX, 4),
@@ -231,7 +244,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("RTI", Operation::RTI, AddressingMode::Implied, 6),
     Instruction("EOR", Operation::EOR, AddressingMode::IndirectX, 6),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x42 - unofficial
     Instruction("SRE", Operation::SRE, AddressingMode::IndirectX, 8),       // 0x43 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPage, 3),        // 0x44 - unofficial
     Instruction("EOR", Operation::EOR, AddressingMode::ZeroPage, 3),
@@ -248,7 +261,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BVC", Operation::BVC, AddressingMode::Relative, 2),
     Instruction("EOR", Operation::EOR, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x52 - unofficial
     Instruction("SRE", Operation::SRE, AddressingMode::IndirectY, 8),       // 0x53 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0x54 - unofficial
     Instruction("EOR", Operation::EOR, AddressingMode::ZeroPageX, 4),
@@ -265,7 +278,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("RTS", Operation::RTS, AddressingMode::Implied, 6),
     Instruction("ADC", Operation::ADC, AddressingMode::IndirectX, 6),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x62 - unofficial
     Instruction("RRA", Operation::RRA, AddressingMode::IndirectX, 8),       // 0x63 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPage, 3),        // 0x64 - unofficial
     Instruction("ADC", Operation::ADC, AddressingMode::ZeroPage, 3),
@@ -282,7 +295,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BVS", Operation::BVS, AddressingMode::Relative, 2),
     Instruction("ADC", Operation::ADC, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x72 - unofficial
     Instruction("RRA", Operation::RRA, AddressingMode::IndirectY, 8),       // 0x73 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0x74 - unofficial
     Instruction("ADC", Operation::ADC, AddressingMode::ZeroPageX, 4),
@@ -308,7 +321,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
     Instruction("DEY", Operation::DEY, AddressingMode::Implied, 2),
     Instruction("SKB", Operation::SKB, AddressingMode::Immediate, 2),       // 0x89 - unofficial
     Instruction("TXA", Operation::TXA, AddressingMode::Implied, 2),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("XAA", Operation::XAA, AddressingMode::Immediate, 2),       // 0x8b - unofficial (unstable)
     Instruction("STY", Operation::STY, AddressingMode::Absolute, 4),
     Instruction("STA", Operation::STA, AddressingMode::Absolute, 4),
     Instruction("STX", Operation::STX, AddressingMode::Absolute, 4),
@@ -316,8 +329,8 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BCC", Operation::BCC, AddressingMode::Relative, 2),
     Instruction("STA", Operation::STA, AddressingMode::IndirectY, 6),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 6),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0x92 - unofficial
+    Instruction("AHX", Operation::AHX, AddressingMode::IndirectY, 6),       // 0x93 - unofficial (unstable)
     Instruction("STY", Operation::STY, AddressingMode::ZeroPageX, 4),
     Instruction("STA", Operation::STA, AddressingMode::ZeroPageX, 4),
     Instruction("STX", Operation::STX, AddressingMode::ZeroPageY, 4),
@@ -325,11 +338,11 @@ pub static INSTRUCTIONS: [Instruction; 256] =
     Instruction("TYA", Operation::TYA, AddressingMode::Implied, 2),
     Instruction("STA", Operation::STA, AddressingMode::AbsoluteY, 5),
     Instruction("TXS", Operation::TXS, AddressingMode::Implied, 2),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 5),
+    Instruction("TAS", Operation::TAS, AddressingMode::AbsoluteY, 5),       // 0x9b - unofficial (unstable)
+    Instruction("SHY", Operation::SHY, AddressingMode::AbsoluteX, 5),       // 0x9c - unofficial (unstable)
     Instruction("STA", Operation::STA, AddressingMode::AbsoluteX, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 5),
+    Instruction("SHX", Operation::SHX, AddressingMode::AbsoluteY, 5),       // 0x9e - unofficial (unstable)
+    Instruction("AHX", Operation::AHX, AddressingMode::AbsoluteY, 5),       // 0x9f - unofficial (unstable)
 
     Instruction("LDY", Operation::LDY, AddressingMode::Immediate, 2),
     Instruction("LDA", Operation::LDA, AddressingMode::IndirectX, 6),
@@ -350,7 +363,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BCS", Operation::BCS, AddressingMode::Relative, 2),
     Instruction("LDA", Operation::LDA, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0xb2 - unofficial
     Instruction("LAX", Operation::LAX, AddressingMode::IndirectY, 5),       // 0xb3 - unofficial
     Instruction("LDY", Operation::LDY, AddressingMode::ZeroPageX, 4),
     Instruction("LDA", Operation::LDA, AddressingMode::ZeroPageX, 4),
@@ -359,7 +372,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
     Instruction("CLV", Operation::CLV, AddressingMode::Implied, 2),
     Instruction("LDA", Operation::LDA, AddressingMode::AbsoluteY, 4),
     Instruction("TSX", Operation::TSX, AddressingMode::Implied, 2),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 4),
+    Instruction("LAS", Operation::LAS, AddressingMode::AbsoluteY, 4),       // 0xbb - unofficial (unstable)
     Instruction("LDY", Operation::LDY, AddressingMode::AbsoluteX, 4),
     Instruction("LDA", Operation::LDA, AddressingMode::AbsoluteX, 4),
     Instruction("LDX", Operation::LDX, AddressingMode::AbsoluteY, 4),
@@ -384,7 +397,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BNE", Operation::BNE, AddressingMode::Relative, 2),
     Instruction("CMP", Operation::CMP, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0xd2 - unofficial
     Instruction("DCP", Operation::DCP, AddressingMode::IndirectY, 8),       // 0xd3 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0xd4 - unofficial
     Instruction("CMP", Operation::CMP, AddressingMode::ZeroPageX, 4),
@@ -418,7 +431,7 @@ pub static INSTRUCTIONS: [Instruction; 256] =
 
     Instruction("BEQ", Operation::BEQ, AddressingMode::Relative, 2),
     Instruction("SBC", Operation::SBC, AddressingMode::IndirectY, 5),
-    Instruction("???", Operation::XXX, AddressingMode::Implied, 2),
+    Instruction("JAM", Operation::JAM, AddressingMode::Implied, 2),         // 0xf2 - unofficial
     Instruction("ISC", Operation::ISC, AddressingMode::IndirectY, 8),       // 0xf3 - unofficial
     Instruction("IGN", Operation::IGN, AddressingMode::ZeroPageX, 4),       // 0xf4 - unofficial
     Instruction("SBC", Operation::SBC, AddressingMode::ZeroPageX, 4),
@@ -2,13 +2,103 @@ use bitflags::bitflags;
 use super::memory::Memory;
 use super::palette_table::Colour;
 use super::palette_table::PALETTE_TABLE;
+use super::error::EmuError;
+use super::snapshot::{StateReader, StateWriter};
 
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 pub const PATTERN_TABLE_SIZE: usize = 128;
-pub const CYCLES_PER_FRAME: usize = (341 / 3) * (262+1);
 
-#[derive(Copy, Clone)]
+// The real PPU only has room for 8 sprites per scanline, and any more than that don't get drawn (which is why
+// games flicker them), but with the limit turned off, every sprite in OAM can be on the one line
+pub const SPRITES_PER_SCANLINE: usize = 8;
+const MAX_SPRITES_PER_SCANLINE: usize = 64;
+
+// The test pattern's bars, left to right, as palette entries: white, yellow, cyan, green, magenta, red, blue and black
+pub const TEST_PATTERN_BARS: [u8; 8] = [0x30, 0x28, 0x2c, 0x2a, 0x24, 0x16, 0x12, 0x0f];
+pub const TEST_PATTERN_GRID_SIZE: usize = 16;
+
+// Each scanline is 341 "dots" (PPU cycles) long, of which only 256 are visible
+pub const DOTS_PER_SCANLINE: usize = 341;
+pub const SCANLINES_PER_FRAME: usize = 262;
+pub const PAL_SCANLINES_PER_FRAME: usize = 312;
+
+// The NES came in two main flavours: NTSC (America and Japan) and PAL (Europe and Australia). The PAL
+// PPU draws 312 scanlines rather than 262 - the extra ones all being spent in v-blank - and the CPU runs
+// at 1/3.2 of the PPU's speed rather than 1/3. Mixing them up makes games run at the wrong speed. (The
+// PAL palette differs slightly too, though mostly in how colour emphasis is wired, which isn't emulated.)
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Region
+{
+    Ntsc,
+    Pal
+}
+
+impl Region
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            _ => None
+        }
+    }
+
+    // Includes the pre-render scanline (-1)
+    pub fn scanlines_per_frame(&self) -> usize
+    {
+        match self
+        {
+            Region::Ntsc => SCANLINES_PER_FRAME,
+            Region::Pal => PAL_SCANLINES_PER_FRAME
+        }
+    }
+
+    // In PPU cycles, so 89342 for NTSC (or one less on odd frames when rendering, though that's close enough
+    // for working out how long to run for). The CPU only runs on some of these (see below), and as its
+    // share doesn't divide evenly into a frame (29780.67 for NTSC), it's kept in step by the PPU's running
+    // cycle count rather than restarting every frame.
+    pub fn cycles_per_frame(&self) -> usize
+    {
+        DOTS_PER_SCANLINE * self.scanlines_per_frame()
+    }
+
+    // How many frames the real thing draws every second, going by its master clock
+    pub fn frames_per_second(&self) -> f64
+    {
+        match self
+        {
+            Region::Ntsc => 60.0988,
+            Region::Pal => 50.0070
+        }
+    }
+
+    // How many times a second the CPU (and so the APU) is clocked
+    pub fn cpu_clock_rate(&self) -> f64
+    {
+        match self
+        {
+            Region::Ntsc => 1789773.0,
+            Region::Pal => 1662607.0
+        }
+    }
+
+    // NTSC runs the CPU every third PPU cycle, but PAL has a ratio of 3.2, which is to say that the CPU runs
+    // 5 times for every 16 PPU cycles - so spread those 5 as evenly as possible
+    pub fn cpu_runs_on_cycle(&self, cycle: u64) -> bool
+    {
+        match self
+        {
+            Region::Ntsc => cycle.is_multiple_of(3),
+            Region::Pal => (cycle * 5) % 16 < 5
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Ppu
 {
     // Registers
@@ -25,12 +115,15 @@ pub struct Ppu
     address_latch: bool,
     data_buffer: u8,
 
+    // Set by reading the status just before v-blank begins, which stops it being flagged at all (see below)
+    suppress_vblank: bool,
+
     // Timing
     scanline: i16,
     cycles: i16,
 
     // Memory
-    name_tables: [[u8; 1024]; 2],
+    name_tables: [[u8; 1024]; 4], // Only four-screen cartridges use the last two (see "Mirroring")
     palette: [u8; 32],
 
     // "In-progress" rendering
@@ -48,22 +141,31 @@ pub struct Ppu
     oam_address: u8,
 
     // "In-progress" sprite rendering
-    current_scanline_sprites: [ObjectAttribute; 8],
+    current_scanline_sprites: [ObjectAttribute; MAX_SPRITES_PER_SCANLINE],
     current_scanline_sprites_count: u8,
-    sprite_shifter_pattern_low: [u8; 8],
-    sprite_shifter_pattern_high: [u8; 8],
+    sprite_shifter_pattern_low: [u8; MAX_SPRITES_PER_SCANLINE],
+    sprite_shifter_pattern_high: [u8; MAX_SPRITES_PER_SCANLINE],
     sprite_zero_in_scanline: bool, // For collision
     sprite_zero_being_rendered: bool, // For collision
 
     // Input and output
-    pub output: [u8; SCREEN_WIDTH*SCREEN_HEIGHT*3],
+    pub output: Vec<u8>, // SCREEN_WIDTH*SCREEN_HEIGHT*3, on the heap so that it can be left out of snapshots
     pub due_non_maskable_interrupt: bool,
+    pub pattern_tables_dirty: bool, // Set whenever CHR or the palettes are written, so the debugger knows to redraw them
+    pub output_dirty: bool, // Set once a frame's been finished, so it only need be uploaded then
+    pub frame_skip: u32, // Frames left undrawn between each one that is (everything else is still emulated), for slow machines
+    pub sprite_limit: bool, // Off to draw every sprite on a scanline (see "SPRITES_PER_SCANLINE"), flicker-free
+
+    // Timing differs by region, which the main loop needs to know about too, hence the "pub"
+    pub region: Region,
+    pub total_cycles: u64, // Ever-increasing, so the CPU knows when to run, even across frames
+    pub frame_count: u64, // Likewise, but for whole frames (counting from the pre-render line)
 }
 
 bitflags!
 {
     #[derive(Default)]
-    struct PpuControl: u8
+    pub struct PpuControl: u8
     {
         const NAMETABLE_ADDR1        = 0b00000001;
         const NAMETABLE_ADDR2        = 0b00000010;
@@ -76,16 +178,16 @@ bitflags!
     }
 
     #[derive(Default)]
-    struct PpuMask: u8
+    pub struct PpuMask: u8
     {
         const GREYSCALE                          = 0b00000001;
         const SHOW_BACKGROUND_IN_LEFTMOST_PIXELS = 0b00000010;
         const SHOW_SPRITES_IN_LEFTMOST_PIXELS    = 0b00000100;
         const SHOW_BACKGROUND                    = 0b00001000;
         const SHOW_SPRITES                       = 0b00010000;
-        const EMPHASISE_RED                      = 0b00100000; // TODO: emulate
-        const EMPHASISE_GREEN                    = 0b01000000; // TODO: emulate
-        const EMPHASISE_BLUE                     = 0b10000000; // TODO: emulate
+        const EMPHASISE_RED                      = 0b00100000; // Red and green are swapped on PAL
+        const EMPHASISE_GREEN                    = 0b01000000;
+        const EMPHASISE_BLUE                     = 0b10000000;
     }
 
     #[derive(Default)]
@@ -195,6 +297,34 @@ impl ObjectAttribute
         self.id & 0x1
     }
 
+    // Works out the address of the low pattern byte for the given row of the sprite (counting from its top). Vertical
+    // mirroring is done simply by flipping the row: each row in an 8x8 sprite is a byte, and to get the pattern bytes,
+    // we just sample at some row within that sprite, so flipping the "row address" on its head flips the image too!
+    // The pattern table is set by the control register for 8x8 sprites, but by the ID for 8x16 ones.
+    pub fn get_pattern_address(&self, row: u8, sprite_size: u8, pattern_table: u8) -> u16
+    {
+        if sprite_size == 8
+        {
+            let row = if self.is_flipped_vertically() { 7 - (row & 7) } else { row & 7 };
+
+            ((pattern_table as u16) << 12) |                                            // Pattern table
+            ((self.id as u16) << 4) |                                                   // Cell
+            row as u16                                                                  // Row
+        }
+
+        else
+        {
+            // Sprites that're effectively "two sprites tall" are made of two tiles, one after the other, so flipping
+            // them swaps the tiles as well as the rows within them - the top half on screen is the bottom tile
+            let row = if self.is_flipped_vertically() { 15 - (row & 15) } else { row & 15 };
+            let cell = (self.id & 0xfe) as u16 + if row >= 8 { 1 } else { 0 };
+
+            ((self.get_double_height_pattern_table() as u16) << 12) |                  // Pattern table
+            (cell << 4) |                                                               // Cell
+            (row & 7) as u16                                                            // Row
+        }
+    }
+
     // The first four palettes are for background tiles, leaving the last four for sprites
     fn get_palette(&self) -> u8 { (self.attributes & 3) + 4 }
 
@@ -205,9 +335,20 @@ impl ObjectAttribute
     }
 }
 
+// What the background would show at some point on screen, worked out from scratch by the debugger
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BackgroundPixel
+{
+    pub name_table_address: u16, // Where "tile_id" came from
+    pub tile_id: u8,
+    pub palette: u8, // 0-3, from the attribute table
+    pub pixel: u8, // 0-3, from the pattern table (0 being transparent)
+    pub colour: u8 // The entry in palette memory that the above amount to
+}
+
 impl Ppu
 {
-    pub fn default() -> Self
+    pub fn from_region(region: Region) -> Self
     {
         Ppu
         {
@@ -224,13 +365,14 @@ impl Ppu
             // Memory access
             address_latch: false,
             data_buffer: 0,
+            suppress_vblank: false,
 
             // Timing
             scanline: 0,
             cycles: 0,
 
             // Memory
-            name_tables: [[0; 1024]; 2],
+            name_tables: [[0; 1024]; 4],
             palette: [0; 32],
 
             // "In-progress" rendering
@@ -248,17 +390,168 @@ impl Ppu
             oam_address: 0,
 
             // "In-progress" sprite rendering
-            current_scanline_sprites: [ObjectAttribute::default(); 8],
+            current_scanline_sprites: [ObjectAttribute::default(); MAX_SPRITES_PER_SCANLINE],
             current_scanline_sprites_count: 0,
-            sprite_shifter_pattern_low: [0; 8],
-            sprite_shifter_pattern_high: [0; 8],
+            sprite_shifter_pattern_low: [0; MAX_SPRITES_PER_SCANLINE],
+            sprite_shifter_pattern_high: [0; MAX_SPRITES_PER_SCANLINE],
             sprite_zero_in_scanline: false,
             sprite_zero_being_rendered: false,
 
             // Input and output
-            output: [0; SCREEN_WIDTH*SCREEN_HEIGHT*3],
+            output: vec![0; SCREEN_WIDTH*SCREEN_HEIGHT*3],
             due_non_maskable_interrupt: false,
+            pattern_tables_dirty: true,
+            output_dirty: true,
+            frame_skip: 0,
+            sprite_limit: true,
+            region,
+            total_cycles: 0,
+            frame_count: 0,
+        }
+    }
+
+    // What the reset button does, as opposed to powering on; control, mask and the scroll are cleared, as are the
+    // address latch and the read buffer, but VRAM, OAM, palettes and the VRAM address itself are left as they were
+    pub fn reset(&mut self)
+    {
+        self.ppu_control = PpuControl::default();
+        self.ppu_mask = PpuMask::default();
+        self.table_ram_address = 0;
+        self.fine_x = 0;
+        self.address_latch = false;
+        self.data_buffer = 0;
+        self.scanline = 0;
+        self.cycles = 0;
+        self.due_non_maskable_interrupt = false;
+    }
+
+    // Colour bars with a grid over the top and a border around the edge, drawn straight into the output for when
+    // there's no ROM running - it shows the window's working, and whether anything's being cropped or stretched
+    pub fn draw_test_pattern(&mut self)
+    {
+        for y in 0..SCREEN_HEIGHT
+        {
+            for x in 0..SCREEN_WIDTH
+            {
+                let border = x == 0 || y == 0 || x == SCREEN_WIDTH - 1 || y == SCREEN_HEIGHT - 1;
+                let grid = x % TEST_PATTERN_GRID_SIZE == 0 || y % TEST_PATTERN_GRID_SIZE == 0;
+                let colour = if border { 0x30 } else if grid { 0x00 }
+                    else { TEST_PATTERN_BARS[x * TEST_PATTERN_BARS.len() / SCREEN_WIDTH] };
+
+                let Colour(red, green, blue) = PALETTE_TABLE[colour as usize];
+                let i = (y * SCREEN_WIDTH + x) * 3;
+                self.output[i..i + 3].copy_from_slice(&[red, green, blue]);
+            }
+        }
+
+        self.output_dirty = true;
+    }
+
+    // A copy of everything but the framebuffer (which'll be redrawn soon enough anyway), so that keeping
+    // lots of these around for save states and the like doesn't get expensive
+    pub fn snapshot(&self) -> Self
+    {
+        Ppu { output: Vec::new(), ..*self }
+    }
+
+    // The user's settings are kept as they are, rather than going back to whatever they were at the time
+    pub fn restore(&mut self, snapshot: &Ppu)
+    {
+        let output = std::mem::take(&mut self.output);
+        *self = Ppu { output, frame_skip: self.frame_skip, sprite_limit: self.sprite_limit, ..*snapshot };
+    }
+
+    // Everything bar the output (as with snapshots) and the user's settings, in the order it's declared in
+    pub fn save_state(&self, state: &mut StateWriter)
+    {
+        state.write_u8(self.ppu_control.bits);
+        state.write_u8(self.ppu_mask.bits);
+        state.write_u8(self.ppu_status.bits);
+        state.write_u16(self.ppu_address);
+        state.write_u16(self.table_ram_address);
+        state.write_u8(self.fine_x);
+        state.write_bool(self.address_latch);
+        state.write_u8(self.data_buffer);
+        state.write_bool(self.suppress_vblank);
+        state.write_i16(self.scanline);
+        state.write_i16(self.cycles);
+
+        for name_table in self.name_tables.iter() { state.write_bytes(name_table); }
+        state.write_bytes(&self.palette);
+
+        state.write_u8(self.next_background_tile_id);
+        state.write_u8(self.next_background_tile_attribute);
+        state.write_u8(self.next_background_tile_lsb);
+        state.write_u8(self.next_background_tile_msb);
+        state.write_u16(self.shifter_pattern_low);
+        state.write_u16(self.shifter_pattern_high);
+        state.write_u16(self.shifter_attribute_low);
+        state.write_u16(self.shifter_attribute_high);
+
+        state.write_bytes(&self.object_attribute_memory);
+        state.write_u8(self.oam_address);
+
+        for sprite in self.current_scanline_sprites.iter() { state.write_bytes(&[sprite.y, sprite.id, sprite.attributes, sprite.x]); }
+        state.write_u8(self.current_scanline_sprites_count);
+        state.write_bytes(&self.sprite_shifter_pattern_low);
+        state.write_bytes(&self.sprite_shifter_pattern_high);
+        state.write_bool(self.sprite_zero_in_scanline);
+        state.write_bool(self.sprite_zero_being_rendered);
+
+        state.write_bool(self.due_non_maskable_interrupt);
+        state.write_u8(if self.region == Region::Pal { 1 } else { 0 });
+        state.write_u64(self.total_cycles);
+        state.write_u64(self.frame_count);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmuError>
+    {
+        self.ppu_control = PpuControl::from_bits_truncate(state.read_u8()?);
+        self.ppu_mask = PpuMask::from_bits_truncate(state.read_u8()?);
+        self.ppu_status = PpuStatus::from_bits_truncate(state.read_u8()?);
+        self.ppu_address = state.read_u16()?;
+        self.table_ram_address = state.read_u16()?;
+        self.fine_x = state.read_u8()?;
+        self.address_latch = state.read_bool()?;
+        self.data_buffer = state.read_u8()?;
+        self.suppress_vblank = state.read_bool()?;
+        self.scanline = state.read_i16()?;
+        self.cycles = state.read_i16()?;
+
+        for name_table in self.name_tables.iter_mut() { state.read_into(name_table)?; }
+        state.read_into(&mut self.palette)?;
+
+        self.next_background_tile_id = state.read_u8()?;
+        self.next_background_tile_attribute = state.read_u8()?;
+        self.next_background_tile_lsb = state.read_u8()?;
+        self.next_background_tile_msb = state.read_u8()?;
+        self.shifter_pattern_low = state.read_u16()?;
+        self.shifter_pattern_high = state.read_u16()?;
+        self.shifter_attribute_low = state.read_u16()?;
+        self.shifter_attribute_high = state.read_u16()?;
+
+        state.read_into(&mut self.object_attribute_memory)?;
+        self.oam_address = state.read_u8()?;
+
+        for sprite in self.current_scanline_sprites.iter_mut()
+        {
+            let bytes = state.read_bytes(4)?;
+            *sprite = ObjectAttribute { y: bytes[0], id: bytes[1], attributes: bytes[2], x: bytes[3] };
         }
+        self.current_scanline_sprites_count = state.read_u8()?;
+        state.read_into(&mut self.sprite_shifter_pattern_low)?;
+        state.read_into(&mut self.sprite_shifter_pattern_high)?;
+        self.sprite_zero_in_scanline = state.read_bool()?;
+        self.sprite_zero_being_rendered = state.read_bool()?;
+
+        self.due_non_maskable_interrupt = state.read_bool()?;
+        self.region = if state.read_u8()? == 1 { Region::Pal } else { Region::Ntsc };
+        self.total_cycles = state.read_u64()?;
+        self.frame_count = state.read_u64()?;
+
+        // CHR and the palettes could be anything now
+        self.pattern_tables_dirty = true;
+        Ok(())
     }
 
     // "debugger" prevents debug code modifying the PPU address
@@ -271,19 +564,33 @@ impl Ppu
         if address == 0x2002
         {
             // Reading this register also resets the v-blank status and the address latch,
-            // but this must be done *after* the data has been returned!
+            // but this must be done *after* the data has been returned! The debugger mustn't
+            // do so at all, or peeking at it halfway through a pair of 0x2006 writes would
+            // leave the game writing the wrong half of the address.
             let old_status = self.ppu_status.bits;
 
-            self.ppu_status.set(PpuStatus::V_BLANK, false);
-            self.address_latch = false;
+            if !debugger
+            {
+                self.ppu_status.set(PpuStatus::V_BLANK, false);
+                self.address_latch = false;
+
+                // Reading right as v-blank's about to start races with it being set: a dot early and the flag
+                // reads clear and then never gets set at all, whilst on the dot itself it reads as set, but
+                // either way there's no NMI that frame (which games that poll 0x2002 in a loop can run into)
+                if self.at_vblank_start() { self.suppress_vblank = true; }
+                if self.scanline == 241 && self.cycles == 2 { self.due_non_maskable_interrupt = false; }
+            }
 
             return old_status
         }
 
         if address == 0x2003 { return 0 } // OAM address; not readable
 
-        // OAM data
-        if address == 0x2004 {
+        // OAM data. Whilst rendering, reads see whatever sprite evaluation's up to instead, which for the first 64
+        // dots of a line is clearing secondary OAM to 0xff; after that, what's at the address is close enough
+        if address == 0x2004
+        {
+            if self.is_rendering() && self.scanline >= 0 && self.cycles >= 1 && self.cycles <= 64 { return 0xff }
             return self.object_attribute_memory[self.oam_address as usize]
         }
 
@@ -302,13 +609,14 @@ impl Ppu
             // is still updated), so set it immediately to the contents of the buffer
             if self.ppu_address >= 0x3f00 { data = self.data_buffer; }
 
-            // Reading also increments "ppu_address", respecting the "ppu_control" register too
-            if self.ppu_control.contains(PpuControl::VRAM_ADDR_INCREMENT) && debugger { self.ppu_address += 32; }
-            else if !debugger { self.ppu_address += 1; }
+            // Reading also increments "ppu_address" (though the debugger mustn't disturb it)
+            if !debugger { self.increment_address_after_data_access(); }
             return data
         }
 
-        panic!("Could not map external PPU read for address {:#06x}", address);
+        // Memory only forwards 0x2000-0x2007 here (mirrors included), so anything else is our own fault
+        debug_assert!(false, "Could not map external PPU read for address {:#06x}", address);
+        0
     }
 
     pub fn write_byte_from_cpu(&mut self, memory: &mut Memory, address: u16, value: u8)
@@ -316,9 +624,15 @@ impl Ppu
         // PPU control
         if address == 0x2000
         {
-            // Set control bits
+            // Set control bits; the NMI's edge-triggered, so turning it on part way through v-blank fires it straight
+            // away (and turning it off and on again fires it again)
+            let nmi_was_enabled = self.ppu_control.contains(PpuControl::GENERATE_NMI);
             self.ppu_control.bits = value;
 
+            if !nmi_was_enabled && self.ppu_control.contains(PpuControl::GENERATE_NMI) && self.ppu_status.contains(PpuStatus::V_BLANK) {
+                self.due_non_maskable_interrupt = true;
+            }
+
             // Update name tables as a result
             let mut loopy = LoopyRegister::from(self.table_ram_address);
             loopy.name_table_x = if self.ppu_control.contains(PpuControl::NAMETABLE_ADDR1) { 1 } else { 0 };
@@ -331,11 +645,24 @@ impl Ppu
         // PPU mask
         if address == 0x2001 { self.ppu_mask.bits = value; return }
 
+        // PPU status; read only, so writing does nothing
+        if address == 0x2002 { return }
+
         // OAM address
         if address == 0x2003 { self.oam_address = value; return }
 
-        // OAM data
-        if address == 0x2004 { self.object_attribute_memory[self.oam_address as usize] = value; }
+        // OAM data, which moves the address along afterwards. Whilst rendering, OAM's busy with sprite evaluation,
+        // so the write's lost, and the address jumps to the next sprite (i.e. along by 4) instead
+        if address == 0x2004
+        {
+            if self.is_rendering() {
+                self.oam_address = self.oam_address.wrapping_add(4);
+            } else {
+                self.object_attribute_memory[self.oam_address as usize] = value;
+                self.oam_address = self.oam_address.wrapping_add(1);
+            }
+            return
+        }
 
         // Scrolling
         if address == 0x2005
@@ -389,12 +716,11 @@ impl Ppu
         {
             // Similar to with reading, but with no buffer
             self.write_byte_from_ppu(memory, self.ppu_address, value);
-            if self.ppu_control.contains(PpuControl::VRAM_ADDR_INCREMENT) { self.ppu_address += 32; }
-            else { self.ppu_address += 1; }
+            self.increment_address_after_data_access();
             return
         }
 
-        panic!("Could not map external PPU write for address {:#06x}", address);
+        debug_assert!(false, "Could not map external PPU write for address {:#06x}", address);
     }
 
     pub fn read_byte_from_ppu(&mut self, memory: &mut Memory, mut address: u16) -> u8
@@ -416,21 +742,8 @@ impl Ppu
         if address >= 0x2000 && address <= 0x3eff
         {
             let name_table_address = (address & 0xfff) as usize;
-
-            if memory.rom_header.has_vertical_mirroring()
-            {
-                if                                name_table_address <= 0x3ff { return self.name_tables[0][name_table_address & 0x3ff] }
-                if name_table_address >= 0x400 && name_table_address <= 0x7ff { return self.name_tables[1][name_table_address & 0x3ff] }
-                if name_table_address >= 0x800 && name_table_address <= 0xbff { return self.name_tables[0][name_table_address & 0x3ff] }
-                if name_table_address >= 0xc00 && name_table_address <= 0xfff { return self.name_tables[1][name_table_address & 0x3ff] }
-            }
-            else
-            {
-                if                                name_table_address <= 0x3ff { return self.name_tables[0][name_table_address & 0x3ff] }
-                if name_table_address >= 0x400 && name_table_address <= 0x7ff { return self.name_tables[0][name_table_address & 0x3ff] }
-                if name_table_address >= 0x800 && name_table_address <= 0xbff { return self.name_tables[1][name_table_address & 0x3ff] }
-                if name_table_address >= 0xc00 && name_table_address <= 0xfff { return self.name_tables[1][name_table_address & 0x3ff] }
-            }
+            let name_table = memory.current_mirroring().get_name_table(name_table_address / 0x400);
+            return self.name_tables[name_table][name_table_address & 0x3ff]
         }
 
         // Palettes
@@ -450,7 +763,9 @@ impl Ppu
             return self.palette[palette_address] & colour_mask;
         }
 
-        panic!("Could not map internal PPU read for address {:#06x}", address);
+        // The address was masked to 14 bits above, so every case should have been covered
+        debug_assert!(false, "Could not map internal PPU read for address {:#06x}", address);
+        0
     }
 
     pub fn write_byte_from_ppu(&mut self, memory: &mut Memory, mut address: u16, value: u8)
@@ -465,28 +780,18 @@ impl Ppu
         address &= 0x3fff;
 
         // Check cartridge first;
-        if memory.write_byte_from_ppu(address, value) { return }
+        if memory.write_byte_from_ppu(address, value)
+        {
+            self.pattern_tables_dirty = true;
+            return
+        }
 
         // Name tables with mirroring
         if address >= 0x2000 && address <= 0x3eff
         {
             let name_table_address = (address & 0xfff) as usize;
-
-            if memory.rom_header.has_vertical_mirroring()
-            {
-                if                                name_table_address <= 0x3ff { self.name_tables[0][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0x400 && name_table_address <= 0x7ff { self.name_tables[1][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0x800 && name_table_address <= 0xbff { self.name_tables[0][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0xc00 && name_table_address <= 0xfff { self.name_tables[1][name_table_address & 0x3ff] = value; }
-            }
-            else
-            {
-                if                                name_table_address <= 0x3ff { self.name_tables[0][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0x400 && name_table_address <= 0x7ff { self.name_tables[0][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0x800 && name_table_address <= 0xbff { self.name_tables[1][name_table_address & 0x3ff] = value; }
-                if name_table_address >= 0xc00 && name_table_address <= 0xfff { self.name_tables[1][name_table_address & 0x3ff] = value; }
-            }
-
+            let name_table = memory.current_mirroring().get_name_table(name_table_address / 0x400);
+            self.name_tables[name_table][name_table_address & 0x3ff] = value;
             return
         }
 
@@ -499,10 +804,11 @@ impl Ppu
             if palette_address == 0x18 { palette_address = 0x8; }
             if palette_address == 0x1c { palette_address = 0xc; }
             self.palette[palette_address] = value;
+            self.pattern_tables_dirty = true;
             return
         }
 
-        panic!("Could not map internal PPU write for address {:#06x}", address);
+        debug_assert!(false, "Could not map internal PPU write for address {:#06x}", address);
     }
 
     pub fn execute(&mut self, memory: &mut Memory)
@@ -510,8 +816,8 @@ impl Ppu
         // Deal with visible scanlines (and -1)
         if self.scanline >= -1 && self.scanline < 240
         {
-            // Odd frame cycle skip
-            if self.scanline == 0 && self.cycles == 0 { self.cycles = 1; }
+            // Odd frame cycle skip (which PAL doesn't have)
+            if self.scanline == 0 && self.cycles == 0 && self.region == Region::Ntsc { self.cycles = 1; }
 
             // On the *second* tick of line -1 (that is to say when "cycles" equals 1), the
             // v-blank flag is reset. This is pretty much when a new frame starts, so reset
@@ -522,11 +828,8 @@ impl Ppu
                 self.ppu_status.set(PpuStatus::SPRITE_OVERFLOW, false);
                 self.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, false);
 
-                for i in 0..8
-                {
-                    self.sprite_shifter_pattern_low[i] = 0;
-                    self.sprite_shifter_pattern_high[i] = 0;
-                }
+                self.sprite_shifter_pattern_low = [0; MAX_SPRITES_PER_SCANLINE];
+                self.sprite_shifter_pattern_high = [0; MAX_SPRITES_PER_SCANLINE];
             }
 
             // Fetch next background tile, then deal with sprites
@@ -534,19 +837,26 @@ impl Ppu
             self.process_sprites(memory);
         }
 
-        // Nothing is done on scanline 240, and then afterwards it's V-blank time
-        if self.scanline >= 241 && self.scanline < 261
+        // Nothing is done on scanline 240, and then afterwards it's V-blank time (until the last scanline)
+        let last_scanline = self.region.scanlines_per_frame() as i16 - 1;
+        if self.scanline >= 241 && self.scanline < last_scanline
         {
             if self.scanline == 241 && self.cycles == 1
             {
                 // "Vertical blanking lines" - a.k.a. v-blank! On the *second* tick of line 241,
-                // we update the v-blank flag and call the NMI too
-                self.ppu_status.set(PpuStatus::V_BLANK, true);
+                // we update the v-blank flag and call the NMI too (unless the status was read just before)
+                if self.is_drawing_frame() { self.output_dirty = true; }
 
-                if self.ppu_control.contains(PpuControl::GENERATE_NMI) {
-                    self.due_non_maskable_interrupt = true;
+                if !self.suppress_vblank
+                {
+                    self.ppu_status.set(PpuStatus::V_BLANK, true);
+
+                    if self.ppu_control.contains(PpuControl::GENERATE_NMI) {
+                        self.due_non_maskable_interrupt = true;
+                    }
                 }
 
+                self.suppress_vblank = false;
             }
         }
 
@@ -554,17 +864,19 @@ impl Ppu
         let (tile_pixel, tile_palette) = self.get_background_tile_to_draw();
         let (sprite_pixel, sprite_palette, sprite_priority) = self.get_sprite_to_draw();
 
-        // Combine pixels based on how ordering should work; checks for sprite zero hit too
+        // Combine pixels based on how ordering should work; checks for sprite zero hit too, which is why this much
+        // still has to happen on frames that are being skipped
         let (final_pixel, final_palette) = self.get_final_pixel(tile_pixel, tile_palette, sprite_pixel, sprite_palette, sprite_priority);
 
-        // Lookup pixel in palette and work out X and Y based on progress of PPU along screen
-        let Colour(red, green, blue) = self.get_colour_from_palette(memory, final_palette, final_pixel);
+        // Work out X and Y based on progress of PPU along screen
         let screen_x = (self.cycles - 1) as usize;
         let screen_y = self.scanline as usize;
 
-        // If within visible bounds, plot pixel
-        if screen_x < SCREEN_WIDTH && screen_y < SCREEN_HEIGHT
+        // If within visible bounds (and the frame's not being skipped), lookup pixel in palette and plot it
+        if screen_x < SCREEN_WIDTH && screen_y < SCREEN_HEIGHT && self.is_drawing_frame()
         {
+            let colour = self.get_colour_from_palette(memory, final_palette, final_pixel);
+            let Colour(red, green, blue) = self.apply_emphasis(colour);
             self.output[(screen_y * SCREEN_WIDTH + screen_x) * 3 + 0] = red;
             self.output[(screen_y * SCREEN_WIDTH + screen_x) * 3 + 1] = green;
             self.output[(screen_y * SCREEN_WIDTH + screen_x) * 3 + 2] = blue;
@@ -572,20 +884,86 @@ impl Ppu
 
         // Advance cycles
         self.cycles += 1;
+        self.total_cycles += 1;
 
         // Every 341 cycles, the scanline advances
-        if self.cycles >= 341
+        if self.cycles >= DOTS_PER_SCANLINE as i16
         {
             self.cycles = 0;
             self.scanline += 1;
 
-            // Every 261 scanlines, we go back to the top (which is actually at -1)
-            if self.scanline >= 261 {
+            // Once the last scanline's done, we go back to the top (which is actually at -1)
+            if self.scanline >= last_scanline {
                 self.scanline = -1;
+                self.frame_count += 1;
             }
         }
     }
 
+    // With "frame_skip", only every so many frames make it into "output"
+    fn is_drawing_frame(&self) -> bool
+    {
+        self.frame_count.is_multiple_of(self.frame_skip as u64 + 1)
+    }
+
+    // Where the PPU's got to, and the address it's about to fetch from (which, whilst rendering, is the scroll
+    // position), mostly for tests and the debugger
+
+    pub fn get_scanline(&self) -> i16 { self.scanline }
+    pub fn get_dot(&self) -> i16 { self.cycles }
+    pub fn get_vram_address(&self) -> u16 { self.ppu_address }
+
+    // The registers and status flags, for the debugger to look at without reading 0x2002 (and so clearing v-blank)
+    pub fn get_control(&self) -> PpuControl { self.ppu_control }
+    pub fn get_mask(&self) -> PpuMask { self.ppu_mask }
+    pub fn in_vblank(&self) -> bool { self.ppu_status.contains(PpuStatus::V_BLANK) }
+    pub fn sprite_zero_hit(&self) -> bool { self.ppu_status.contains(PpuStatus::SPRITE_ZERO_HIT) }
+    pub fn sprite_overflow(&self) -> bool { self.ppu_status.contains(PpuStatus::SPRITE_OVERFLOW) }
+
+    // Everything a game uploads to the PPU bar CHR, for looking at elsewhere: both name tables (as they are in
+    // VRAM, so before any mirroring), then palette memory, then OAM - 2048 + 32 + 256 bytes in all. Four-screen
+    // cartridges' extra two name tables are on the cartridge rather than in VRAM, so are left out.
+    pub fn dump_vram(&self) -> Vec<u8>
+    {
+        let mut bytes = Vec::with_capacity(2048 + 32 + 256);
+        bytes.extend_from_slice(&self.name_tables[0]);
+        bytes.extend_from_slice(&self.name_tables[1]);
+        bytes.extend_from_slice(&self.palette);
+        bytes.extend_from_slice(&self.object_attribute_memory);
+        bytes
+    }
+
+    // A 64-bit FNV-1a hash of the framebuffer, so that frames can be compared without keeping them around
+    pub fn get_output_hash(&self) -> u64
+    {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.output.iter()
+        {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    // The dot on which v-blank begins; by now the whole visible frame has been drawn into "output", so it's
+    // a good place for anything outside the emulation to stop and look at it
+    pub fn at_vblank_start(&self) -> bool
+    {
+        self.scanline == 241 && self.cycles == 1
+    }
+
+    // Runs the PPU (and only the PPU) up to the start of the next scanline, drawing whatever's left of this one, so
+    // that a frame can be watched building up a line at a time. Anything that needs the CPU kept in step too (which
+    // is most things) wants "run_scanline" in lib.rs instead.
+    pub fn render_scanline(&mut self, memory: &mut Memory)
+    {
+        let scanline = self.scanline;
+        while self.scanline == scanline
+        {
+            self.execute(memory);
+        }
+    }
+
     fn process_background_tiles(&mut self, memory: &mut Memory)
     {
         // Main "fetching stage" for PPU background tiles - split across 8 cycles
@@ -685,7 +1063,9 @@ impl Ppu
             self.current_scanline_sprites_count = 0;
             self.sprite_zero_in_scanline = false;
 
-            // Now go through OAM memory, and look for the first 8 sprites; the "divide by 4" is because each attribute entry is 4 bytes
+            // Now go through OAM memory, and look for the first 8 sprites (or all of them, without the limit); the "divide by 4"
+            // is because each attribute entry is 4 bytes
+            let capacity = if self.sprite_limit { SPRITES_PER_SCANLINE } else { MAX_SPRITES_PER_SCANLINE };
             for i in 0..(self.object_attribute_memory.len()/4)
             {
                 // Convert bytes in memory to nice struct format
@@ -702,7 +1082,7 @@ impl Ppu
                 if y_difference >= 0 && y_difference < self.ppu_control.get_sprite_size() as i16
                 {
                     // If there's "room on the broom" in the current scanline, add sprite
-                    if self.current_scanline_sprites_count != 8
+                    if (self.current_scanline_sprites_count as usize) < capacity
                     {
                         // If it's sprite zero in the scanline, update collision variable
                         if i == 0 { self.sprite_zero_in_scanline = true; }
@@ -711,12 +1091,11 @@ impl Ppu
                         self.current_scanline_sprites_count += 1;
                     }
 
+                    // Too many sprites on this scanline; the flag then stays set until the end of v-blank. Without the
+                    // limit, there's never any overflow to speak of.
                     else { self.ppu_status.set(PpuStatus::SPRITE_OVERFLOW, true); }
                 }
             }
-
-            // Now we can also figure out if we had too many sprites on this scanline
-            self.ppu_status.set(PpuStatus::SPRITE_OVERFLOW, self.current_scanline_sprites_count > 8);
         }
 
         // Once we know what sprites are coming up, let's prime then into shifters, just like background tiles
@@ -726,83 +1105,13 @@ impl Ppu
             {
                 let mut sprite_pattern_bits_low: u8;
                 let mut sprite_pattern_bits_high: u8;
-                let sprite_pattern_address_low: u16;
                 let sprite_pattern_address_high: u16;
                 let sprite = self.current_scanline_sprites[i as usize];
 
-                // Fetch the pattern bytes from memory, applying vertical mirroring if need be. This can be done simply
-                // by subtracting seven from the address. Think about it: each row in an 8x8 sprite (or "half sprite" if
-                // we're talking double height sprites) is a byte, and to get the pattern bytes, we just sample at some
-                // row within that sprite. Flipping the "row address" on its head will therefore flip the image too!
-
-                if self.ppu_control.get_sprite_size() == 8
-                {
-                    // The control bit affects which pattern table the sprite is fetched from
-                    let pattern_table = if self.ppu_control.contains(PpuControl::SPRITE_PATTERN_ADDR) { 1 } else { 0 };
-
-                    // Apply vertical flipping if need be
-                    if !sprite.is_flipped_vertically()
-                    {
-                        sprite_pattern_address_low =
-                            ((pattern_table as u16) << 12) |                            // Pattern table
-                            ((sprite.id as u16) << 4) |                                 // Cell
-                            (self.scanline - sprite.y as i16) as u16;                   // Row
-                    }
-                    else
-                    {
-                        sprite_pattern_address_low =
-                            ((pattern_table as u16) << 12) |                            // Pattern table
-                            ((sprite.id as u16) << 4) |                                 // Cell
-                            (7 - (self.scanline - sprite.y as i16)) as u16;             // Row
-                    }
-                }
-
-                else
-                {
-                    // Sprites that're effectively "two sprites tall" have their pattern table set by their id
-                    let pattern_table = sprite.get_double_height_pattern_table();
-
-                    if !sprite.is_flipped_vertically()
-                    {
-                        // Top half
-                        if self.scanline - (sprite.y as i16) < 8
-                        {
-                            sprite_pattern_address_low =
-                                ((pattern_table as u16) << 12) |                        // Pattern table
-                                (((sprite.id & 0xfe) as u16) << 4) |                    // Cell
-                                ((self.scanline - sprite.y as i16) & 7) as u16;         // Row
-                        }
-
-                        // Bottom half
-                        else
-                        {
-                            sprite_pattern_address_low =
-                                ((pattern_table as u16) << 12) |                        // Pattern table
-                                (((sprite.id & 0xfe) as u16 + 1) << 4) |                // Cell
-                                ((self.scanline - sprite.y as i16) & 7) as u16;         // Row
-                        }
-                    }
-                    else
-                    {
-                        // Top half
-                        if self.scanline - (sprite.y as i16) < 8
-                        {
-                            sprite_pattern_address_low =
-                                ((pattern_table as u16) << 12) |                        // Pattern table
-                                (((sprite.id & 0xfe) as u16) << 4) |                    // Cell
-                                (7 - (self.scanline - sprite.y as i16) & 7) as u16;     // Row
-                        }
-
-                        // Bottom half
-                        else
-                        {
-                            sprite_pattern_address_low =
-                                ((pattern_table as u16) << 12) |                        // Pattern table
-                                (((sprite.id & 0xfe) as u16 + 1) << 4) |                // Cell
-                                (7 - (self.scanline - sprite.y as i16) & 7) as u16;     // Row
-                        }
-                    }
-                }
+                // Fetch the pattern bytes from memory, applying vertical mirroring if need be (see "get_pattern_address")
+                let pattern_table = if self.ppu_control.contains(PpuControl::SPRITE_PATTERN_ADDR) { 1 } else { 0 };
+                let row = (self.scanline - sprite.y as i16) as u8;
+                let sprite_pattern_address_low = sprite.get_pattern_address(row, self.ppu_control.get_sprite_size(), pattern_table);
 
                 // For the high address we can simply just skip ahead
                 sprite_pattern_address_high = sprite_pattern_address_low + 8;
@@ -897,7 +1206,7 @@ impl Ppu
         let mut rendered_pixel = 0;
         let mut rendered_palette = 0;
 
-        // Transparent background, solid sprite
+        // Transparent background, solid sprite - which shows even if it's meant to be behind the background
         if tile_pixel == 0 && sprite_pixel > 0
         {
             rendered_pixel = sprite_pixel;
@@ -922,15 +1231,16 @@ impl Ppu
                 && self.ppu_mask.contains(PpuMask::SHOW_BACKGROUND) && self.ppu_mask.contains(PpuMask::SHOW_SPRITES)
             {
                 // If we're not drawing sprites or the background in the very left of the screen,
-                // the window for collision is smaller - TODO: visibly respect this
+                // the window for collision is smaller - TODO: visibly respect this. Either way, the
+                // hardware never reports a hit on the rightmost pixel (x = 255, which is cycle 256).
 
                 if !self.ppu_mask.contains(PpuMask::SHOW_BACKGROUND_IN_LEFTMOST_PIXELS) || !self.ppu_mask.contains(PpuMask::SHOW_SPRITES_IN_LEFTMOST_PIXELS)
                 {
-                    if self.cycles >= 9 && self.cycles < 258 { self.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, true); }
+                    if self.cycles >= 9 && self.cycles < 256 { self.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, true); }
                 }
                 else
                 {
-                    if self.cycles >= 1 && self.cycles < 258 { self.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, true); }
+                    if self.cycles >= 1 && self.cycles < 256 { self.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, true); }
                 }
 
             }
@@ -951,6 +1261,46 @@ impl Ppu
         PALETTE_TABLE[(colour & 0x3f) as usize]
     }
 
+    // Emphasis darkens the colours that *aren't* emphasised, rather than brightening the ones that are. Greyscale's
+    // already been applied by this point (it's done on the palette index), which matches the order hardware does
+    // them in, so a greyscale picture with emphasis comes out tinted. Only the final output is affected, as the
+    // debugger's views wouldn't make much sense otherwise.
+    fn apply_emphasis(&self, colour: Colour) -> Colour
+    {
+        const ATTENUATION: f32 = 0.816;
+
+        let (mut red, mut green) = (self.ppu_mask.contains(PpuMask::EMPHASISE_RED), self.ppu_mask.contains(PpuMask::EMPHASISE_GREEN));
+        let blue = self.ppu_mask.contains(PpuMask::EMPHASISE_BLUE);
+        if self.region == Region::Pal { std::mem::swap(&mut red, &mut green); }
+        if !red && !green && !blue { return colour }
+
+        let attenuate = |channel: u8, emphasised: bool| if emphasised { channel } else { (channel as f32 * ATTENUATION) as u8 };
+        let Colour(r, g, b) = colour;
+        Colour(attenuate(r, red), attenuate(g, green), attenuate(b, blue))
+    }
+
+    // Whether "ppu_address" is currently being used to fetch tiles (i.e. we're on a visible or pre-render line)
+    fn is_rendering(&self) -> bool
+    {
+        self.ppu_mask.rendering_enabled() && self.scanline >= -1 && self.scanline < 240
+    }
+
+    // Accessing 0x2007 normally just steps along by 1 or 32, but whilst rendering, the address is busy being used
+    // for scrolling, and so instead both the coarse X and Y get bumped at once (which some games rely on)
+    fn increment_address_after_data_access(&mut self)
+    {
+        if self.is_rendering()
+        {
+            self.increment_scroll_x();
+            self.increment_scroll_y();
+        }
+        else
+        {
+            let increment = if self.ppu_control.contains(PpuControl::VRAM_ADDR_INCREMENT) { 32 } else { 1 };
+            self.ppu_address = (self.ppu_address + increment) & 0x7fff;
+        }
+    }
+
     fn increment_scroll_x(&mut self)
     {
         // Make sure rendering is enabled
@@ -1063,7 +1413,9 @@ impl Ppu
             for i in 0..self.current_scanline_sprites_count as usize
             {
                 // The easiest way to move the data of each sprite along to us is by decrementing
-                // the X coordinate, and then beginning to shift once we hit the sprite
+                // the X coordinate, and then beginning to shift once we hit the sprite. X then stays at 0 for
+                // the rest of the scanline, so the sprite's drawn for the 8 dots it takes to shift its pattern
+                // out, after which the shifters are empty (and thus transparent) rather than repeating it.
                 if self.current_scanline_sprites[i].x > 0 { self.current_scanline_sprites[i].x -= 1; }
                 else
                 {
@@ -1084,8 +1436,18 @@ impl Ppu
         value
     }
 
-    // Debugging code
-    pub fn get_pattern_table(&mut self, memory: &mut Memory, pattern_table: u8, palette: u8) -> [u8; PATTERN_TABLE_SIZE*PATTERN_TABLE_SIZE*3]
+    // Reads CHR from a given bank, going around the mapper, or through it if there's no bank given
+    fn read_pattern_byte(&mut self, memory: &mut Memory, chr_bank: Option<usize>, address: u16) -> u8
+    {
+        match chr_bank
+        {
+            Some(bank) if !memory.chr_rom.is_empty() => memory.chr_rom[(bank * 0x2000 + address as usize) % memory.chr_rom.len()],
+            _ => self.read_byte_from_ppu(memory, address)
+        }
+    }
+
+    // Debugging code; "chr_bank" picks an 8 KB bank of CHR to show, or if none, whatever the mapper currently has switched in
+    pub fn get_pattern_table(&mut self, memory: &mut Memory, pattern_table: u8, palette: u8, chr_bank: Option<usize>) -> [u8; PATTERN_TABLE_SIZE*PATTERN_TABLE_SIZE*3]
     {
         let mut output = [0; PATTERN_TABLE_SIZE*PATTERN_TABLE_SIZE*3];
 
@@ -1099,8 +1461,8 @@ impl Ppu
                 for row in 0..8
                 {
                     // Fetch row byte from both planes (where each bit is one pixel in its corresponding column)
-                    let mut tile_lower_plane = self.read_byte_from_ppu(memory, pattern_table as u16 * 0x1000 + tile_address_offset + row);
-                    let mut tile_higher_plane = self.read_byte_from_ppu(memory, pattern_table as u16 * 0x1000 + tile_address_offset + row + 8);
+                    let mut tile_lower_plane = self.read_pattern_byte(memory, chr_bank, pattern_table as u16 * 0x1000 + tile_address_offset + row);
+                    let mut tile_higher_plane = self.read_pattern_byte(memory, chr_bank, pattern_table as u16 * 0x1000 + tile_address_offset + row + 8);
 
                     for col in 0..8
                     {
@@ -1124,4 +1486,85 @@ impl Ppu
 
         output
     }
+
+    // Debugging code; how many times each tile ID appears across the name tables (but not the attribute tables
+    // after them). All four are looked at, so with mirroring, everything's counted twice - which makes no odds
+    // when it's only the proportions that matter.
+    pub fn get_tile_usage(&mut self, memory: &mut Memory) -> [u32; 256]
+    {
+        let mut usage = [0; 256];
+        for name_table in 0..4
+        {
+            for tile in 0..0x3c0
+            {
+                let tile_id = self.read_byte_from_ppu(memory, 0x2000 + name_table * 0x400 + tile);
+                usage[tile_id as usize] += 1;
+            }
+        }
+        usage
+    }
+
+    // Debugging code; the background's pattern table, with tiles the name tables don't use dimmed, and the rest
+    // tinted from yellow (rarely used) to red (used the most)
+    pub fn get_tile_heatmap(&mut self, memory: &mut Memory, palette: u8, chr_bank: Option<usize>) -> [u8; PATTERN_TABLE_SIZE*PATTERN_TABLE_SIZE*3]
+    {
+        let pattern_table = if self.ppu_control.contains(PpuControl::BACKROUND_PATTERN_ADDR) { 1 } else { 0 };
+        let mut output = self.get_pattern_table(memory, pattern_table, palette, chr_bank);
+        let usage = self.get_tile_usage(memory);
+        let most_used = *usage.iter().max().unwrap_or(&1) as f32;
+
+        for (i, pixel) in output.chunks_exact_mut(3).enumerate()
+        {
+            let (x, y) = (i % PATTERN_TABLE_SIZE, i / PATTERN_TABLE_SIZE);
+            let uses = usage[(y / 8) * 16 + x / 8];
+            if uses == 0
+            {
+                pixel.iter_mut().for_each(|channel| *channel /= 4);
+                continue
+            }
+
+            // Half the tile's own colour, half the tint
+            let heat = uses as f32 / most_used;
+            let tint = [255.0, 255.0 * (1.0 - heat), 0.0];
+            for (channel, tint) in pixel.iter_mut().zip(tint) {
+                *channel = (*channel as f32 * 0.5 + tint * 0.5) as u8;
+            }
+        }
+
+        output
+    }
+
+    // Looks up the background under a point on screen, using the scroll that the game's set for the frame (rather than
+    // the VRAM address, which, once rendering's done, has long since moved on) and whatever's in VRAM right now
+    pub fn get_background_pixel(&mut self, memory: &mut Memory, x: usize, y: usize) -> BackgroundPixel
+    {
+        let scroll = LoopyRegister::from(self.table_ram_address);
+        let scroll_x = scroll.name_table_x as usize * 256 + scroll.coarse_x as usize * 8 + self.fine_x as usize;
+        let scroll_y = scroll.name_table_y as usize * 240 + scroll.coarse_y as usize * 8 + scroll.fine_y as usize;
+
+        // The four name tables make a 512x480 area that wraps round on itself
+        let x = (scroll_x + x) % 512;
+        let y = (scroll_y + y) % 480;
+        let (name_table_x, name_table_y) = ((x / 256) as u16, (y / 240) as u16);
+        let (tile_x, tile_y) = (((x % 256) / 8) as u16, ((y % 240) / 8) as u16);
+
+        let name_table_address = 0x2000 | (name_table_y << 11) | (name_table_x << 10) | (tile_y << 5) | tile_x;
+        let tile_id = self.read_byte_from_ppu(memory, name_table_address);
+
+        // As with rendering, each attribute byte covers 4x4 tiles, 2 bits for each 2x2 within it
+        let attribute = self.read_byte_from_ppu(memory, 0x23c0 | (name_table_y << 11) | (name_table_x << 10) | ((tile_y / 4) << 3) | (tile_x / 4));
+        let palette = (attribute >> (((tile_y & 2) << 1) | (tile_x & 2))) & 3;
+
+        let background_bit = if self.ppu_control.contains(PpuControl::BACKROUND_PATTERN_ADDR) { 1 } else { 0 };
+        let pattern_address = (background_bit << 12) + ((tile_id as u16) << 4) + (y % 8) as u16;
+        let column = 7 - (x % 8);
+        let low = (self.read_byte_from_ppu(memory, pattern_address) >> column) & 1;
+        let high = (self.read_byte_from_ppu(memory, pattern_address + 8) >> column) & 1;
+        let pixel = (high << 1) | low;
+
+        // Transparent pixels all show the backdrop
+        let colour = self.read_byte_from_ppu(memory, 0x3f00 + if pixel == 0 { 0 } else { (palette * 4 + pixel) as u16 });
+
+        BackgroundPixel { name_table_address, tile_id, palette, pixel, colour }
+    }
 }
\ No newline at end of file
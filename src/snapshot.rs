@@ -0,0 +1,149 @@
+use super::cpu::Cpu;
+use super::error::EmuError;
+use super::ppu::Ppu;
+use super::memory::Memory;
+
+// The whole machine at a single point in time, for save states (and anything else that wants to go back
+// in time). The PPU and memory leave out their framebuffer and ROM respectively, so these stay fairly small.
+//
+// Snapshots can also be saved to disk, laid out as so (all little-endian):
+// - "SAV" followed by 0x1a (like iNES)
+// - Version (one byte)
+// - ROM hash (8 bytes; see "Memory::get_rom_hash")
+// - The CPU, then the PPU, then memory (APU and mapper included), each as written by its own "save_state"
+//
+// Anything that's the user's rather than the game's (cheats, the sprite limit, frame skip and so on) is left out,
+// and stays as it was when a state's loaded. Whenever what's saved changes, the version has to go up, so that older
+// states are turned away rather than read as garbage; if it's ever worth keeping them, "from_bytes" is where
+// they'd be brought up to date.
+
+const MAGIC: [u8; 4] = [b'S', b'A', b'V', 0x1a];
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 13;
+
+#[derive(Clone)]
+pub struct Snapshot
+{
+    pub cpu: Cpu,
+    pub ppu: Ppu,
+    pub memory: Memory
+}
+
+impl Snapshot
+{
+    pub fn from_machine(cpu: &Cpu, ppu: &Ppu, memory: &Memory) -> Self
+    {
+        Snapshot
+        {
+            cpu: *cpu,
+            ppu: ppu.snapshot(),
+            memory: memory.snapshot()
+        }
+    }
+
+    pub fn restore(&self, cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory)
+    {
+        *cpu = self.cpu;
+        ppu.restore(&self.ppu);
+        memory.restore(&self.memory);
+    }
+
+    // The machine's needed to fill in everything that isn't saved, and to check the state's for the same ROM
+    pub fn from_bytes(bytes: &[u8], cpu: &Cpu, ppu: &Ppu, memory: &Memory) -> Result<Self, EmuError>
+    {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+            return Err(EmuError::BadState("File is not a save state".to_string()));
+        }
+
+        if bytes[4] != VERSION {
+            return Err(EmuError::BadState(format!("Save state is version {}, but only version {} is supported", bytes[4], VERSION)));
+        }
+
+        if u64::from_le_bytes(bytes[5..13].try_into().unwrap()) != memory.get_rom_hash() {
+            return Err(EmuError::BadState("Save state was made with a different ROM".to_string()));
+        }
+
+        let mut snapshot = Snapshot::from_machine(cpu, ppu, memory);
+        let mut state = StateReader::from_bytes(&bytes[HEADER_SIZE..]);
+        snapshot.cpu.load_state(&mut state)?;
+        snapshot.ppu.load_state(&mut state)?;
+        snapshot.memory.load_state(&mut state)?;
+        Ok(snapshot)
+    }
+
+    pub fn to_bytes(&self, rom_hash: u64) -> Vec<u8>
+    {
+        let mut state = StateWriter::default();
+        state.write_bytes(&MAGIC);
+        state.write_u8(VERSION);
+        state.write_u64(rom_hash);
+
+        self.cpu.save_state(&mut state);
+        self.ppu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        state.bytes
+    }
+
+    pub fn from_file(filename: &str, cpu: &Cpu, ppu: &Ppu, memory: &Memory) -> Result<Self, EmuError>
+    {
+        let bytes = std::fs::read(filename).map_err(|error| EmuError::BadState(format!("Could not read save state file {}: {}", filename, error)))?;
+        Self::from_bytes(&bytes, cpu, ppu, memory)
+    }
+
+    pub fn save_to_file(&self, filename: &str, rom_hash: u64) -> Result<(), EmuError>
+    {
+        std::fs::write(filename, self.to_bytes(rom_hash)).map_err(|error| EmuError::BadState(format!("Could not write save state file {}: {}", filename, error)))
+    }
+}
+
+// What each part of the machine writes its state into (and reads it back out of), one field after another
+#[derive(Default)]
+pub struct StateWriter
+{
+    pub bytes: Vec<u8>
+}
+
+impl StateWriter
+{
+    pub fn write_u8(&mut self, value: u8) { self.bytes.push(value); }
+    pub fn write_u16(&mut self, value: u16) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+    pub fn write_u32(&mut self, value: u32) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+    pub fn write_u64(&mut self, value: u64) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+    pub fn write_i16(&mut self, value: i16) { self.bytes.extend_from_slice(&value.to_le_bytes()); }
+    pub fn write_bool(&mut self, value: bool) { self.bytes.push(value as u8); }
+    pub fn write_bytes(&mut self, bytes: &[u8]) { self.bytes.extend_from_slice(bytes); }
+}
+
+pub struct StateReader<'a>
+{
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> StateReader<'a>
+{
+    pub fn from_bytes(bytes: &'a [u8]) -> Self
+    {
+        StateReader { bytes, position: 0 }
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], EmuError>
+    {
+        let bytes = self.bytes.get(self.position..self.position + length).ok_or_else(|| EmuError::BadState("Save state is truncated".to_string()))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_into(&mut self, destination: &mut [u8]) -> Result<(), EmuError>
+    {
+        destination.copy_from_slice(self.read_bytes(destination.len())?);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EmuError> { Ok(self.read_bytes(1)?[0]) }
+    pub fn read_u16(&mut self) -> Result<u16, EmuError> { Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap())) }
+    pub fn read_u32(&mut self) -> Result<u32, EmuError> { Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap())) }
+    pub fn read_u64(&mut self) -> Result<u64, EmuError> { Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap())) }
+    pub fn read_i16(&mut self) -> Result<i16, EmuError> { Ok(i16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap())) }
+    pub fn read_bool(&mut self) -> Result<bool, EmuError> { Ok(self.read_u8()? != 0) }
+}
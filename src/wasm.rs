@@ -0,0 +1,74 @@
+use super::on_emulation_cycle;
+use super::cpu::Cpu;
+use super::memory::{Buttons, Memory};
+use super::ppu::{Ppu, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+use wasm_bindgen::prelude::*;
+
+// Bindings for running in the browser (see web/index.html), where JS does everything main.rs would otherwise:
+// it fetches the ROM, calls "step_frame" on its own timer, copies the frame straight out of the module's memory
+// onto a canvas and passes the keyboard through to "set_controller". There's no sound yet, but when there is,
+// it'll be up to JS to play it too.
+
+#[wasm_bindgen]
+pub struct WebNes
+{
+    cpu: Cpu,
+    ppu: Ppu,
+    memory: Memory,
+    framebuffer: Vec<u8> // The PPU's output, but RGBA, as that's what canvases want
+}
+
+#[wasm_bindgen]
+impl WebNes
+{
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Result<WebNes, JsValue>
+    {
+        let mut memory = Memory::from_bytes(rom).map_err(|error| JsValue::from_str(&error.to_string()))?;
+        let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+        let cpu = Cpu::from_memory(&mut ppu, &mut memory).map_err(|error| JsValue::from_str(&error.to_string()))?;
+
+        Ok(WebNes
+        {
+            cpu,
+            ppu,
+            memory,
+            framebuffer: vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
+        })
+    }
+
+    pub fn step_frame(&mut self) -> Result<(), JsValue>
+    {
+        on_emulation_cycle(&mut self.cpu, &mut self.ppu, &mut self.memory).map_err(|error| JsValue::from_str(&error.to_string()))?;
+
+        // Alpha's left at 0xff from when the buffer was made
+        for (rgba, rgb) in self.framebuffer.chunks_exact_mut(4).zip(self.ppu.output.chunks_exact(3)) {
+            rgba[..3].copy_from_slice(rgb);
+        }
+
+        Ok(())
+    }
+
+    // JS reads the frame from the module's memory directly, rather than it being copied out every frame
+    pub fn framebuffer_pointer(&self) -> *const u8
+    {
+        self.framebuffer.as_ptr()
+    }
+
+    pub fn framebuffer_length(&self) -> usize
+    {
+        self.framebuffer.len()
+    }
+
+    // Buttons are as in "memory::Buttons"
+    pub fn set_controller(&mut self, port: usize, buttons: u8)
+    {
+        self.memory.set_controller(port, Buttons::from_bits_truncate(buttons));
+    }
+
+    pub fn frames_per_second(&self) -> f64
+    {
+        self.ppu.region.frames_per_second()
+    }
+}
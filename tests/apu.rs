@@ -0,0 +1,151 @@
+mod common;
+
+use common::{build_rom, load_rom};
+use nes_emulator_rust::on_emulation_cycle;
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::apu::{Apu, OnePoleFilter, FilterKind};
+use nes_emulator_rust::ppu::{Ppu, Region};
+
+#[test]
+fn frame_irq_fires_and_is_acknowledged_by_reading_status()
+{
+    let mut rom = build_rom(&[
+        0x58,               // CLI
+        0x4c, 0x01, 0x80,   // JMP $8001
+    ]);
+
+    // IRQ handler at 0x8010 reads the status into 0x00 and then stays put
+    let handler = [0xad, 0x15, 0x40, 0x85, 0x00, 0x4c, 0x15, 0x80]; // LDA $4015, STA $00, JMP $8015
+    rom[16 + 0x10..16 + 0x18].copy_from_slice(&handler);
+    rom[16 + 0x3ffe] = 0x10;
+    rom[16 + 0x3fff] = 0x80;
+
+    let mut memory = load_rom(&rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+
+    // The 4-step sequence is a little longer than a frame, so there's nothing after the first one...
+    on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert!(!memory.apu.frame_irq);
+    assert_eq!(memory.ram[0], 0);
+    assert!(cpu.pc >= 0x8001 && cpu.pc < 0x8004);
+
+    // ... but there is by the end of the second
+    on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0] & 0x40, 0x40);
+    assert!(!memory.apu.frame_irq);
+    assert_eq!(cpu.pc, 0x8015);
+}
+
+#[test]
+fn frame_irq_can_be_inhibited()
+{
+    let mut rom = build_rom(&[
+        0xa9, 0x40,         // LDA #$40
+        0x8d, 0x17, 0x40,   // STA $4017
+        0x58,               // CLI
+        0x4c, 0x06, 0x80,   // JMP $8006
+    ]);
+    rom[16 + 0x3ffe] = 0x10;
+    rom[16 + 0x3fff] = 0x80;
+
+    let mut memory = load_rom(&rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+
+    // Long enough that it would have fired otherwise
+    for _ in 0..2 { on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap(); }
+    assert!(!memory.apu.frame_irq);
+    assert!(cpu.pc >= 0x8006 && cpu.pc < 0x8009);
+}
+
+#[test]
+fn samples_are_made_at_the_chosen_rate()
+{
+    for sample_rate in [44100, 48000]
+    {
+        let mut memory = load_rom(&build_rom(&[0x4c, 0x00, 0x80])).unwrap(); // JMP $8000
+        memory.apu.set_sample_rate(sample_rate);
+        let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+        let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+
+        on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+
+        let expected = sample_rate as f64 / ppu.region.frames_per_second();
+        assert!((memory.apu.samples.len() as f64 - expected).abs() <= 1.0,
+            "{} samples at {} Hz, expected about {}", memory.apu.samples.len(), sample_rate, expected);
+        assert_eq!(memory.apu.resampler.get_sample_rate(), sample_rate);
+    }
+}
+
+#[test]
+fn filters_block_what_they_should_and_pass_the_rest()
+{
+    let clock_rate = Region::Ntsc.cpu_clock_rate();
+    let mut high_pass = OnePoleFilter::from_cutoff(FilterKind::HighPass, 90.0);
+    let mut low_pass = OnePoleFilter::from_cutoff(FilterKind::LowPass, 14000.0);
+
+    // A tenth of a second of DC is plenty for the high-pass to settle back to nothing, whilst the low-pass lets it through
+    let (mut high, mut low) = (0.0, 0.0);
+    for _ in 0..clock_rate as usize / 10
+    {
+        high = high_pass.process(1.0, clock_rate);
+        low = low_pass.process(1.0, clock_rate);
+    }
+    assert!(high.abs() < 0.01, "high-pass let {} of DC through", high);
+    assert!((low - 1.0).abs() < 0.01, "low-pass let {} of DC through", low);
+
+    // The other way round for the highest frequency there is (flipping every cycle), once they've settled into it
+    let (mut high_swing, mut low_swing) = (0.0f32, 0.0f32);
+    let (mut previous_high, mut previous_low) = (high, low);
+    for i in 0..100000
+    {
+        let input = if i % 2 == 0 { 1.0 } else { -1.0 };
+        let (high, low) = (high_pass.process(input, clock_rate), low_pass.process(input, clock_rate));
+        if i >= 99000
+        {
+            high_swing = high_swing.max((high - previous_high).abs());
+            low_swing = low_swing.max((low - previous_low).abs());
+        }
+        (previous_high, previous_low) = (high, low);
+    }
+    assert!(high_swing > 1.9, "high-pass only swung by {}", high_swing);
+    assert!(low_swing < 0.1, "low-pass swung by {}", low_swing);
+}
+
+#[test]
+fn length_counters_load_from_the_table_and_count_down_to_zero()
+{
+    let mut apu = Apu::default();
+    apu.write_register(0x4015, 0x0f);
+
+    // Index 3 is 2 half frames long, and each 5-step write to 0x4017 clocks a half frame straight away
+    apu.pulse_sequence_steps[0] = 5;
+    apu.write_register(0x4003, 3 << 3);
+    assert_eq!(apu.length_counters[0], 2);
+    assert!(apu.envelope_restarts[0]);
+    assert_eq!(apu.pulse_sequence_steps[0], 0);
+    assert_eq!(apu.read_status(0, true) & 0x01, 0x01);
+
+    apu.write_register(0x4017, 0x80);
+    assert_eq!(apu.length_counters[0], 1);
+    apu.write_register(0x4017, 0x80);
+    assert_eq!(apu.length_counters[0], 0);
+    apu.write_register(0x4017, 0x80);
+    assert_eq!(apu.length_counters[0], 0);
+    assert_eq!(apu.read_status(0, true) & 0x01, 0);
+
+    // Halting freezes the counter (with the triangle's halt flag being in a different bit to the others')
+    apu.write_register(0x4000, 0x20);
+    apu.write_register(0x4003, 1 << 3);
+    apu.write_register(0x4008, 0x80);
+    apu.write_register(0x400b, 1 << 3);
+    apu.write_register(0x4017, 0x80);
+    assert_eq!(apu.length_counters[0], 254);
+    assert_eq!(apu.length_counters[2], 254);
+
+    // Turning a channel off clears its counter, and stops it loading
+    apu.write_register(0x4015, 0x00);
+    apu.write_register(0x400f, 0);
+    assert_eq!(apu.length_counters, [0; 4]);
+}
@@ -0,0 +1,89 @@
+use nes_emulator_rust::on_emulation_cycle;
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::memory::Memory;
+use nes_emulator_rust::ppu::Ppu;
+
+use std::path::Path;
+
+// Blargg's test ROMs (e.g. instr_test-v5's "rom_singles") report their progress through cartridge RAM:
+// 0x6000 holds the status (0x80 whilst running, 0x81 if a reset is needed, and otherwise the result, with
+// 0 meaning success), 0x6001-0x6003 hold a signature so we know the status is valid, and a null-terminated
+// message follows from 0x6004. The ROMs aren't distributed with the repo, so place any to be tested in
+// "tests/roms" - if there aren't any, there's nothing to do.
+
+const ROM_DIRECTORY: &str = "tests/roms";
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const MAX_FRAMES: usize = 60 * 60;
+
+struct TestResult
+{
+    status: u8,
+    message: String
+}
+
+fn run_test_rom(path: &Path) -> Result<TestResult, String>
+{
+    let mut memory = Memory::from_file(path.to_str().unwrap()).map_err(|error| error.to_string())?;
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).map_err(|error| error.to_string())?;
+
+    for _ in 0..MAX_FRAMES
+    {
+        on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).map_err(|error| error.to_string())?;
+
+        // Wait for the signature to appear and the test to stop running
+        let signature = [memory.prg_ram[1], memory.prg_ram[2], memory.prg_ram[3]];
+        let status = memory.prg_ram[0];
+        if signature != SIGNATURE || status == 0x80 { continue }
+
+        if status == 0x81 { return Err("ROM requested a reset, which isn't supported by the test harness".to_string()) }
+
+        let message = memory.prg_ram[4..].iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect();
+
+        return Ok(TestResult { status, message })
+    }
+
+    Err(format!("ROM didn't finish within {} frames", MAX_FRAMES))
+}
+
+#[test]
+fn blargg_test_roms()
+{
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIRECTORY);
+    let mut paths: Vec<_> = match std::fs::read_dir(&directory)
+    {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "nes"))
+            .collect(),
+        Err(_) => Vec::new()
+    };
+
+    if paths.is_empty()
+    {
+        println!("No test ROMs found in {}; skipping", directory.display());
+        return
+    }
+
+    paths.sort();
+    let mut failures = Vec::new();
+
+    for path in paths
+    {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        // The emulator may well panic on a broken ROM, but the rest should still be run
+        match std::panic::catch_unwind(|| run_test_rom(&path))
+        {
+            Ok(Ok(result)) if result.status == 0 => println!("{}: passed", name),
+            Ok(Ok(result)) => failures.push(format!("{}: failed with status {}\n{}", name, result.status, result.message)),
+            Ok(Err(error)) => failures.push(format!("{}: {}", name, error)),
+            Err(_) => failures.push(format!("{}: emulator panicked", name))
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
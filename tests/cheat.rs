@@ -0,0 +1,68 @@
+mod common;
+
+use common::machine_from_program;
+use nes_emulator_rust::cheat::Cheat;
+
+#[test]
+fn six_letter_game_genie_codes_decode()
+{
+    // Super Mario Bros.' infinite lives
+    let cheat = Cheat::from_code("SXIOPO").unwrap();
+    assert_eq!(cheat.address, 0x91d9);
+    assert_eq!(cheat.value, 0xad);
+    assert_eq!(cheat.compare, None);
+
+    // Lowercase is fine too
+    assert_eq!(Cheat::from_code("sxiopo").unwrap().address, 0x91d9);
+}
+
+#[test]
+fn eight_letter_game_genie_codes_decode()
+{
+    let cheat = Cheat::from_code("SXSOPOOO").unwrap();
+    assert_eq!(cheat.address, 0x91d9);
+    assert_eq!(cheat.value, 0xad);
+    assert_eq!(cheat.compare, Some(0x99));
+
+    assert!(Cheat::from_code("SXIOP").is_err());
+    assert!(Cheat::from_code("SXIOPB").is_err());
+}
+
+#[test]
+fn game_genie_codes_only_replace_matching_bytes()
+{
+    let (_, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x55,         // LDA #$55
+    ]);
+
+    // 0x8001 becomes 0x42, but only if it was 0x55 (which it is) or 0x66 (which it isn't)
+    memory.cheats.push(Cheat::from_code("ZGEAPAII").unwrap());
+    assert_eq!(memory.read_byte(&mut ppu, 0x8001, false).unwrap(), 0x42);
+
+    memory.cheats[0] = Cheat::from_code("ZGEAPATT").unwrap();
+    assert_eq!(memory.read_byte(&mut ppu, 0x8001, false).unwrap(), 0x55);
+
+    // And not at all once turned off
+    memory.cheats[0] = Cheat::from_code("ZGEAPAII").unwrap();
+    memory.cheats[0].enabled = false;
+    assert_eq!(memory.read_byte(&mut ppu, 0x8001, false).unwrap(), 0x55);
+}
+
+#[test]
+fn compare_bytes_use_both_of_the_last_two_letters()
+{
+    // Unlike the codes above, the last two letters differ here, so mixing up which bits come from which
+    // would show (the expected values are from the Game Genie's documented bit layout)
+    let cheat = Cheat::from_code("SXSOPOPE").unwrap();
+    assert_eq!(cheat.address, 0x91d9);
+    assert_eq!(cheat.value, 0xad);
+    assert_eq!(cheat.compare, Some(0x09));
+
+    // And such a code still gets applied when its compare matches
+    let (_, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x32,         // LDA #$32
+    ]);
+    memory.cheats.push(Cheat::from_code("ZGEAPAZL").unwrap());
+    assert_eq!(memory.cheats[0].compare, Some(0x32));
+    assert_eq!(memory.read_byte(&mut ppu, 0x8001, false).unwrap(), 0x42);
+}
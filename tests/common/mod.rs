@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::{Memory, RamInitMode};
+use nes_emulator_rust::ppu::Ppu;
+
+// Builds a 16 KB NROM image with the given program at the start of PRG ROM (0x8000, and mirrored at
+// 0xc000), with the reset vector pointing to it
+
+pub fn build_rom(program: &[u8]) -> Vec<u8>
+{
+    let mut pgr_rom = vec![0u8; 0x4000];
+    pgr_rom[..program.len()].copy_from_slice(program);
+    pgr_rom[0x3ffc] = 0x00;
+    pgr_rom[0x3ffd] = 0x80;
+
+    let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(pgr_rom);
+    rom.extend(vec![0u8; 0x2000]);
+    rom
+}
+
+pub fn load_rom(rom: &[u8]) -> Result<Memory, EmuError>
+{
+    Memory::from_bytes(rom)
+}
+
+pub fn load_rom_with_ram_init(rom: &[u8], ram_init: RamInitMode) -> Result<Memory, EmuError>
+{
+    Memory::from_bytes_with_ram_init(rom, ram_init)
+}
+
+pub fn machine_from_program(program: &[u8]) -> (Cpu, Ppu, Memory)
+{
+    let mut memory = load_rom(&build_rom(program)).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    (cpu, ppu, memory)
+}
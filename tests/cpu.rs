@@ -0,0 +1,589 @@
+mod common;
+
+use common::{build_rom, load_rom, machine_from_program};
+use nes_emulator_rust::{on_emulation_cycle, on_emulation_tick, reset, run_instruction};
+use nes_emulator_rust::cpu::{Cpu, IllegalOpcodePolicy, ProcessorState, PC_HISTORY_SIZE};
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::Memory;
+use nes_emulator_rust::ppu::Ppu;
+use nes_emulator_rust::snapshot::Snapshot;
+
+#[test]
+fn ror_sets_flags_from_result()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0x38,               // SEC
+        0xa9, 0x01,         // LDA #$01
+        0x6a,               // ROR A
+        0x18,               // CLC
+        0xa9, 0x01,         // LDA #$01
+        0x6a,               // ROR A
+        0x38,               // SEC
+        0x66, 0x10,         // ROR $10
+    ]);
+
+    // Carry rotates into bit 7, and bit 0 rotates out into the carry
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0x80);
+    assert!(cpu.flags.contains(ProcessorState::NEGATIVE));
+    assert!(!cpu.flags.contains(ProcessorState::ZERO));
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+
+    // Without carry, the result is zero
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0x00);
+    assert!(!cpu.flags.contains(ProcessorState::NEGATIVE));
+    assert!(cpu.flags.contains(ProcessorState::ZERO));
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+
+    // Memory is rotated in place
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(memory.ram[0x10], 0x80);
+    assert!(cpu.flags.contains(ProcessorState::NEGATIVE));
+    assert!(!cpu.flags.contains(ProcessorState::ZERO));
+    assert!(!cpu.flags.contains(ProcessorState::CARRY));
+}
+
+#[test]
+fn lsr_sets_flags_from_shifted_value()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x01,         // LDA #$01
+        0x4a,               // LSR A
+        0xa9, 0xff,         // LDA #$ff
+        0x46, 0x10,         // LSR $10
+    ]);
+    memory.ram[0x10] = 0x81;
+
+    // Accumulator: bit 0 goes into carry, leaving zero
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0x00);
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+    assert!(cpu.flags.contains(ProcessorState::ZERO));
+    assert!(!cpu.flags.contains(ProcessorState::NEGATIVE));
+
+    // Zero page: flags come from the byte written back, not the (negative) accumulator
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(memory.ram[0x10], 0x40);
+    assert_eq!(cpu.a, 0xff);
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+    assert!(!cpu.flags.contains(ProcessorState::ZERO));
+    assert!(!cpu.flags.contains(ProcessorState::NEGATIVE));
+}
+
+#[test]
+fn total_cycles_counts_every_instruction()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x01,         // LDA #$01
+        0xa2, 0xff,         // LDX #$ff
+        0xbd, 0x01, 0x00,   // LDA $0001,X (crosses a page)
+        0xea,               // NOP
+    ]);
+
+    // The reset sequence takes 7 cycles
+    assert_eq!(cpu.total_cycles, 7);
+
+    let expected = [2, 2, 5, 2];
+    for cycles in expected
+    {
+        let before = cpu.total_cycles;
+        assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), cycles);
+        assert_eq!(cpu.total_cycles - before, cycles as u64);
+    }
+}
+
+#[test]
+fn indirect_y_pointer_wraps_within_zero_page()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa0, 0x10,         // LDY #$10
+        0xb1, 0xff,         // LDA ($ff),Y
+    ]);
+
+    // The pointer's high byte comes from 0x00, not 0x100, giving 0x02f8 (plus Y crosses into the next page)
+    memory.ram[0xff] = 0xf8;
+    memory.ram[0x00] = 0x02;
+    memory.ram[0x100] = 0x05;
+    memory.ram[0x308] = 0x42;
+    memory.ram[0x608] = 0x99;
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 6);
+    assert_eq!(cpu.a, 0x42);
+
+    // Without the page crossing, there's no extra cycle
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa0, 0x04,         // LDY #$04
+        0xb1, 0xff,         // LDA ($ff),Y
+    ]);
+    memory.ram[0xff] = 0xf8;
+    memory.ram[0x00] = 0x02;
+    memory.ram[0x2fc] = 0x24;
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 5);
+    assert_eq!(cpu.a, 0x24);
+}
+
+#[test]
+fn indirect_jmp_wraps_within_page()
+{
+    // 0x30ff would be a PPU register, so the same thing's done in RAM instead
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0x6c, 0xff, 0x02,   // JMP ($02ff)
+    ]);
+
+    // The famous bug: the high byte comes from 0x0200 rather than 0x0300
+    memory.ram[0x2ff] = 0x34;
+    memory.ram[0x200] = 0x12;
+    memory.ram[0x300] = 0x56;
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.pc, 0x1234);
+
+    // Anywhere else, it's just the next byte
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0x6c, 0x80, 0x02,   // JMP ($0280)
+    ]);
+    memory.ram[0x280] = 0x78;
+    memory.ram[0x281] = 0x56;
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.pc, 0x5678);
+}
+
+#[test]
+fn execution_guard_stops_at_registers()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0x4c, 0x00, 0x40,   // JMP $4000
+    ]);
+
+    cpu.execution_guard = true;
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.step(&mut ppu, &mut memory), Err(EmuError::InvalidExecution(0x4000)));
+
+    // Left off, it'd happily carry on (0x4000 reads as a BRK)
+    cpu.execution_guard = false;
+    assert!(cpu.step(&mut ppu, &mut memory).is_ok());
+}
+
+#[test]
+fn code_runs_from_ram()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[]);
+
+    // A routine in zero page that rewrites its own immediate operand before going round again
+    memory.ram[0x10..0x19].copy_from_slice(&[
+        0xa9, 0x42,         // LDA #$42
+        0x85, 0x00,         // STA $00
+        0x8d, 0x11, 0x00,   // STA $0011
+        0xe6, 0x11,         // INC $11
+    ]);
+    memory.ram[0x19..0x1c].copy_from_slice(&[0x4c, 0x10, 0x00]); // JMP $0010
+    cpu.pc = 0x10;
+
+    for _ in 0..5 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(memory.ram[0x00], 0x42);
+    assert_eq!(memory.ram[0x11], 0x43);
+    assert_eq!(cpu.pc, 0x10);
+
+    // Second time round, the load sees what was written over it
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(memory.ram[0x00], 0x43);
+    assert_eq!(cpu.pc, 0x14);
+
+    // The guard's happy with RAM, too
+    cpu.execution_guard = true;
+    assert!(cpu.step(&mut ppu, &mut memory).is_ok());
+}
+
+#[test]
+fn illegal_opcodes_jam_or_do_nothing()
+{
+    let program = [
+        0x02,               // JAM
+        0xa9, 0x42,         // LDA #$42
+    ];
+
+    // Jammed, it stays put, and nothing else gets run
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&program);
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert!(cpu.halted);
+    assert_eq!(cpu.pc, 0x8000);
+
+    on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.a, 0);
+    assert_eq!(cpu.pc, 0x8000);
+
+    // Otherwise it's skipped over
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&program);
+    cpu.illegal_opcode_policy = IllegalOpcodePolicy::Nop;
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert!(!cpu.halted);
+    assert_eq!(cpu.a, 0x42);
+}
+
+#[test]
+fn unstable_opcodes_advance_past_their_operands()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa2, 0xff,         // LDX #$ff
+        0xa0, 0x01,         // LDY #$01
+        0xa9, 0x0f,         // LDA #$0f
+        0x8b, 0x3c,         // XAA #$3c
+        0x9c, 0xf0, 0x02,   // SHY $02f0,X (crosses a page)
+        0x9e, 0x10, 0x03,   // SHX $0310,Y
+        0x9f, 0x20, 0x03,   // AHX $0320,Y
+        0x93, 0x40,         // AHX ($40),Y
+        0x9b, 0x30, 0x03,   // TAS $0330,Y
+        0xbb, 0x00, 0x04,   // LAS $0400,Y
+    ]);
+    memory.ram[0x40] = 0x50;
+    memory.ram[0x41] = 0x03;
+    memory.ram[0x401] = 0xf3;
+
+    let expected = [(0x8002, 2), (0x8004, 2), (0x8006, 2), (0x8008, 2), (0x800b, 5), (0x800e, 5), (0x8011, 5), (0x8013, 6), (0x8016, 5), (0x8019, 4)];
+    for (i, (pc, cycles)) in expected.into_iter().enumerate()
+    {
+        assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), cycles);
+        assert_eq!(cpu.pc, pc);
+
+        // XAA: (0x0f | 0xee) & 0xff & 0x3c
+        if i == 3 { assert_eq!(cpu.a, 0x2c); }
+    }
+
+    // SHY: Y & (0x02 + 1), and as 0x02f0 + 0xff crossed into 0x03ef, that's the page it went to instead
+    assert_eq!(memory.ram[0x1ef], 0x01);
+    assert_eq!(memory.ram[0x3ef], 0x00);
+
+    // SHX and AHX: X (or A & X) & (0x03 + 1)
+    assert_eq!(memory.ram[0x311], 0x04);
+    assert_eq!(memory.ram[0x321], 0x2c & 0x04);
+    assert_eq!(memory.ram[0x351], 0x2c & 0x04);
+
+    // TAS puts A & X in SP, and stores that in the same way; LAS then ANDs SP with memory into A, X and SP
+    assert_eq!(memory.ram[0x331], 0x2c & 0x04);
+    assert_eq!(cpu.a, 0xf3 & 0x2c);
+    assert_eq!(cpu.x, 0xf3 & 0x2c);
+    assert_eq!(cpu.sp, 0xf3 & 0x2c);
+}
+
+#[test]
+fn instructions_can_be_stepped_and_undone()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x05,         // LDA #$05
+        0x85, 0x10,         // STA $10
+    ]);
+
+    // Each step runs the whole of one instruction, and no more
+    let start_cycles = cpu.total_cycles;
+    run_instruction(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.pc, 0x8002);
+    assert_eq!(cpu.total_cycles, start_cycles + 2);
+    assert_eq!(cpu.cycles, 0);
+
+    let before_store = Snapshot::from_machine(&cpu, &ppu, &memory);
+    let ppu_cycles = ppu.total_cycles;
+    run_instruction(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.pc, 0x8004);
+    assert_eq!(cpu.total_cycles, start_cycles + 5);
+    assert_eq!(memory.ram[0x10], 0x05);
+
+    // Going back undoes the write, along with the time it took
+    before_store.restore(&mut cpu, &mut ppu, &mut memory);
+    assert_eq!(cpu.pc, 0x8002);
+    assert_eq!(cpu.total_cycles, start_cycles + 2);
+    assert_eq!(ppu.total_cycles, ppu_cycles);
+    assert_eq!(memory.ram[0x10], 0x00);
+}
+
+#[test]
+fn pc_history_keeps_the_most_recent_instructions()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xe8,               // INX
+        0x4c, 0x00, 0x80,   // JMP $8000
+    ]);
+
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    let history: Vec<(u16, u8)> = cpu.pc_history.iter().collect();
+    assert_eq!(history, vec![(0x8000, 0xe8), (0x8001, 0x4c), (0x8000, 0xe8)]);
+
+    // Only so many are kept, with the oldest being dropped first
+    for _ in 0..PC_HISTORY_SIZE * 2 + 1 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.pc_history.iter().count(), PC_HISTORY_SIZE);
+    assert_eq!(cpu.pc_history.iter().next(), Some((0x8001, 0x4c)));
+    assert!(cpu.pc_history.dump().starts_with("0x8001 JMP (0x4c)\n0x8000 INX (0xe8)"));
+
+    cpu.pc_history.clear();
+    assert_eq!(cpu.pc_history.iter().count(), 0);
+}
+
+#[test]
+fn taken_branches_cost_more_when_crossing_from_the_next_instruction()
+{
+    let mut program = vec![0xea; 0x200];
+    program[0x000..0x004].copy_from_slice(&[0x38, 0x4c, 0xfd, 0x80]); // SEC, JMP $80fd
+    program[0x0fd..0x0ff].copy_from_slice(&[0x90, 0x10]);             // BCC +$10 (not taken)
+    program[0x0ff..0x101].copy_from_slice(&[0xb0, 0x02]);             // BCS +$02 (from 0x8101, to 0x8103)
+    program[0x103..0x105].copy_from_slice(&[0xb0, 0xf0]);             // BCS -$10 (from 0x8105, to 0x80f5)
+    program[0x0f5..0x0f7].copy_from_slice(&[0xb0, 0x02]);             // BCS +$02 (from 0x80f7, to 0x80f9)
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&program);
+
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.pc, 0x80fd);
+
+    // Not taken, so no extra cycles at all
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 2);
+    assert_eq!(cpu.pc, 0x80ff);
+
+    // The branch itself is on the page before, but what counts is the instruction after it, which isn't
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 3);
+    assert_eq!(cpu.pc, 0x8103);
+
+    // Back over the page boundary
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 4);
+    assert_eq!(cpu.pc, 0x80f5);
+
+    // And within the page
+    assert_eq!(cpu.step(&mut ppu, &mut memory).unwrap(), 3);
+    assert_eq!(cpu.pc, 0x80f9);
+}
+
+#[test]
+fn start_pc_can_be_overridden()
+{
+    let mut program = vec![0xea; 0x20];
+    program[0x10..0x12].copy_from_slice(&[0xa9, 0x01]); // LDA #$01
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&program);
+    assert_eq!(cpu.pc, 0x8000);
+
+    cpu.set_pc(0x8010);
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.a, 0x01);
+    assert_eq!(cpu.pc, 0x8012);
+}
+
+#[test]
+fn reset_keeps_ram_and_unjams_the_cpu()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0x58,               // CLI
+        0xa9, 0x0f,         // LDA #$0f
+        0x8d, 0x15, 0x40,   // STA $4015
+        0x85, 0x10,         // STA $10
+        0x02,               // JAM
+    ]);
+    on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert!(cpu.halted);
+    let sp = cpu.sp;
+
+    reset(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, 0x8000);
+    assert_eq!(cpu.sp, sp.wrapping_sub(3));
+    assert!(cpu.flags.contains(ProcessorState::DISABLE_INTERRUPTS));
+    assert_eq!(cpu.a, 0x0f);
+    assert_eq!(memory.ram[0x10], 0x0f);
+    assert_eq!(memory.apu.channels_enabled, [false; 4]);
+
+    // And it runs again from the top
+    run_instruction(&mut cpu, &mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.pc, 0x8001);
+}
+
+fn assert_negative_and_zero(cpu: &Cpu, negative: bool, zero: bool)
+{
+    assert_eq!(cpu.flags.contains(ProcessorState::NEGATIVE), negative);
+    assert_eq!(cpu.flags.contains(ProcessorState::ZERO), zero);
+}
+
+#[test]
+fn inc_wraps_and_sets_flags_in_every_addressing_mode()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa2, 0x02,         // LDX #$02
+        0xe6, 0x10,         // INC $10
+        0xf6, 0x10,         // INC $10,X
+        0xee, 0x00, 0x03,   // INC $0300
+        0xfe, 0x00, 0x03,   // INC $0300,X
+    ]);
+    memory.ram[0x10] = 0xff;
+    memory.ram[0x12] = 0x7f;
+    memory.ram[0x300] = 0xff;
+    memory.ram[0x302] = 0x7f;
+    cpu.step(&mut ppu, &mut memory).unwrap();
+
+    // 0xff wraps round to zero...
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x10], 0x00);
+    assert_negative_and_zero(&cpu, false, true);
+
+    // ...and 0x7f goes negative
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x12], 0x80);
+    assert_negative_and_zero(&cpu, true, false);
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x300], 0x00);
+    assert_negative_and_zero(&cpu, false, true);
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x302], 0x80);
+    assert_negative_and_zero(&cpu, true, false);
+
+    // Nothing either side was touched, and the carry never comes into it
+    assert!([0x11, 0x13, 0x301, 0x303].iter().all(|address| memory.ram[*address] == 0));
+    assert!(!cpu.flags.contains(ProcessorState::CARRY));
+}
+
+#[test]
+fn dec_wraps_and_sets_flags_in_every_addressing_mode()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa2, 0x02,         // LDX #$02
+        0xc6, 0x10,         // DEC $10
+        0xd6, 0x10,         // DEC $10,X
+        0xce, 0x00, 0x03,   // DEC $0300
+        0xde, 0x00, 0x03,   // DEC $0300,X
+    ]);
+    memory.ram[0x10] = 0x00;
+    memory.ram[0x12] = 0x01;
+    memory.ram[0x300] = 0x00;
+    memory.ram[0x302] = 0x80;
+    cpu.step(&mut ppu, &mut memory).unwrap();
+
+    // Zero wraps round to 0xff...
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x10], 0xff);
+    assert_negative_and_zero(&cpu, true, false);
+
+    // ...and 1 goes to zero
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x12], 0x00);
+    assert_negative_and_zero(&cpu, false, true);
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x300], 0xff);
+    assert_negative_and_zero(&cpu, true, false);
+
+    // 0x80 goes positive, and isn't zero either
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(memory.ram[0x302], 0x7f);
+    assert_negative_and_zero(&cpu, false, false);
+
+    assert!([0x11, 0x13, 0x301, 0x303].iter().all(|address| memory.ram[*address] == 0));
+    assert!(!cpu.flags.contains(ProcessorState::CARRY));
+}
+
+// INC $0300 (6 cycles), then two NOPs, with the NMI handler at 0x8010 going nowhere
+fn machine_for_nmi_timing() -> (Cpu, Ppu, Memory)
+{
+    let mut rom = build_rom(&[
+        0xee, 0x00, 0x03,   // INC $0300
+        0xea,               // NOP
+        0xea,               // NOP
+        0x4c, 0x05, 0x80,   // JMP $8005
+    ]);
+    rom[16 + 0x10..16 + 0x13].copy_from_slice(&[0x4c, 0x10, 0x80]); // JMP $8010
+    rom[16 + 0x3ffa] = 0x10;
+    rom[16 + 0x3ffb] = 0x80;
+
+    let mut memory = load_rom(&rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    (cpu, ppu, memory)
+}
+
+// Runs PPU cycles up to and including the next one the CPU runs on
+fn run_cpu_cycle(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory)
+{
+    loop
+    {
+        let cycle = ppu.total_cycles;
+        on_emulation_tick(cpu, ppu, memory).unwrap();
+        if ppu.region.cpu_runs_on_cycle(cycle) { return }
+    }
+}
+
+// Raises an NMI once INC's got the given number of cycles left, and returns where it returns to, along with how many
+// CPU cycles it took from being raised to being taken
+fn nmi_with_cycles_left(cycles_left: u32) -> (u16, u32)
+{
+    let (mut cpu, mut ppu, mut memory) = machine_for_nmi_timing();
+    while !(cpu.pc == 0x8003 && cpu.cycles == cycles_left) { run_cpu_cycle(&mut cpu, &mut ppu, &mut memory); }
+    ppu.due_non_maskable_interrupt = true;
+
+    let mut cycles = 0;
+    while cpu.pc < 0x8010
+    {
+        run_cpu_cycle(&mut cpu, &mut ppu, &mut memory);
+        cycles += 1;
+        assert!(cycles < 20, "the NMI never happened");
+    }
+
+    assert_eq!(memory.ram[0x300], 1);
+    let return_address = memory.ram[0x100 + cpu.sp as usize + 2] as u16 | (memory.ram[0x100 + cpu.sp as usize + 3] as u16) << 8;
+    (return_address, cycles)
+}
+
+#[test]
+fn nmis_wait_for_the_instruction_to_finish()
+{
+    // Raised with 5 of INC's 6 cycles to go, all 5 are run first, and then it's straight into the NMI
+    assert_eq!(nmi_with_cycles_left(5), (0x8003, 6));
+
+    // Still in time on the second-to-last cycle...
+    assert_eq!(nmi_with_cycles_left(2), (0x8003, 3));
+}
+
+#[test]
+fn nmis_on_the_last_cycle_wait_for_the_next_instruction_too()
+{
+    // ...but on the last, it's not seen until the first NOP's done as well
+    assert_eq!(nmi_with_cycles_left(1), (0x8004, 4));
+}
+
+#[test]
+fn immediate_unofficial_opcodes_and_then_shift_or_subtract()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0xf0,         // LDA #$f0
+        0x0b, 0xc3,         // ANC #$c3
+        0xa9, 0x0f,         // LDA #$0f
+        0x4b, 0x0b,         // ALR #$0b
+        0x18,               // CLC
+        0xa9, 0xff,         // LDA #$ff
+        0x6b, 0x80,         // ARR #$80
+        0xa9, 0x0f,         // LDA #$0f
+        0xa2, 0x3c,         // LDX #$3c
+        0xcb, 0x0d,         // AXS #$0d
+    ]);
+
+    // ANC: 0xf0 & 0xc3, with bit 7 copied into the carry
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0xc0);
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+    assert_negative_and_zero(&cpu, true, false);
+
+    // ALR: 0x0f & 0x0b, then shifted right, with bit 0 going into the carry
+    for _ in 0..2 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0x05);
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+    assert_negative_and_zero(&cpu, false, false);
+
+    // ARR: 0xff & 0x80, rotated right into 0x40, so carry is bit 6 (set) and overflow bit 6 ^ bit 5 (set)
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.a, 0x40);
+    assert!(cpu.flags.contains(ProcessorState::CARRY));
+    assert!(cpu.flags.contains(ProcessorState::OVERFLOW));
+    assert_negative_and_zero(&cpu, false, false);
+
+    // AXS: (0x0f & 0x3c) - 0x0d into X, which borrows, so carry's clear
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(cpu.x, 0xff);
+    assert_eq!(cpu.a, 0x0f);
+    assert!(!cpu.flags.contains(ProcessorState::CARRY));
+    assert_negative_and_zero(&cpu, true, false);
+}
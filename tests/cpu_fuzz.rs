@@ -0,0 +1,385 @@
+mod common;
+
+use common::machine_from_program;
+use nes_emulator_rust::cpu::{Cpu, ProcessorState};
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::Memory;
+use nes_emulator_rust::opcodes::{AddressingMode, Instruction, Operation, INSTRUCTIONS};
+use nes_emulator_rust::ppu::Ppu;
+
+// nestest only goes down the paths its author thought to write, so this throws every opcode at a few hundred
+// random machines instead (random registers, flags and RAM, with the instruction itself somewhere in RAM), runs
+// it once and checks whatever can be said about the result without a second CPU to compare against: that it
+// doesn't panic, that the PC's moved on by the right amount (or gone where a jump or branch says), that the cycles
+// are sensible, that N and Z match whatever was worked out, and - for the simpler instructions - that the result
+// itself is right. The seed's fixed so that any failure happens again, and the message says exactly what ran.
+
+const SEED: u64 = 0x6502;
+const RUNS_PER_OPCODE: usize = 300;
+
+// SplitMix64, as with "RamInitMode::Random"
+struct Rng(u64);
+
+impl Rng
+{
+    fn next(&mut self) -> u64
+    {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn byte(&mut self) -> u8 { self.next() as u8 }
+}
+
+fn instruction_length(addressing_mode: &AddressingMode) -> u16
+{
+    match addressing_mode
+    {
+        AddressingMode::Implied | AddressingMode::Accumulator => 1,
+        AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::Indirect => 3,
+        _ => 2
+    }
+}
+
+fn negative_and_zero_match(flags: ProcessorState, value: u8) -> bool
+{
+    flags.contains(ProcessorState::NEGATIVE) == (value & 0x80 != 0) && flags.contains(ProcessorState::ZERO) == (value == 0)
+}
+
+// RAM and ROM can be read (and RAM checked afterwards) without side effects; anything else is left alone
+fn peek(ppu: &mut Ppu, memory: &mut Memory, address: u16) -> Option<u8>
+{
+    if address <= 0x1fff || address >= 0x8000 { Some(memory.read_byte(ppu, address, true).unwrap()) } else { None }
+}
+
+fn stack(memory: &Memory, sp: u8, offset: u8) -> u8
+{
+    memory.ram[0x100 + sp.wrapping_add(offset) as usize]
+}
+
+fn stack_word(memory: &Memory, sp: u8, offset: u8) -> u16
+{
+    stack(memory, sp, offset) as u16 | ((stack(memory, sp, offset.wrapping_add(1)) as u16) << 8)
+}
+
+fn branch_taken(operation: &Operation, flags: ProcessorState) -> Option<bool>
+{
+    match operation
+    {
+        Operation::BCC => Some(!flags.contains(ProcessorState::CARRY)),
+        Operation::BCS => Some(flags.contains(ProcessorState::CARRY)),
+        Operation::BNE => Some(!flags.contains(ProcessorState::ZERO)),
+        Operation::BEQ => Some(flags.contains(ProcessorState::ZERO)),
+        Operation::BPL => Some(!flags.contains(ProcessorState::NEGATIVE)),
+        Operation::BMI => Some(flags.contains(ProcessorState::NEGATIVE)),
+        Operation::BVC => Some(!flags.contains(ProcessorState::OVERFLOW)),
+        Operation::BVS => Some(flags.contains(ProcessorState::OVERFLOW)),
+        _ => None
+    }
+}
+
+// The one flag each of the flag instructions sets or clears
+fn flag_instruction(operation: &Operation) -> Option<(ProcessorState, bool)>
+{
+    match operation
+    {
+        Operation::SEC => Some((ProcessorState::CARRY, true)),
+        Operation::CLC => Some((ProcessorState::CARRY, false)),
+        Operation::SEI => Some((ProcessorState::DISABLE_INTERRUPTS, true)),
+        Operation::CLI => Some((ProcessorState::DISABLE_INTERRUPTS, false)),
+        Operation::SED => Some((ProcessorState::DECIMAL, true)),
+        Operation::CLD => Some((ProcessorState::DECIMAL, false)),
+        Operation::CLV => Some((ProcessorState::OVERFLOW, false)),
+        _ => None
+    }
+}
+
+// Where "check_instruction" puts the instruction: somewhere in RAM past the zero page and stack, or right at the
+// top of ROM, so that fetching its operands (and moving the PC past them) wraps round to 0x0000
+#[derive(Clone, Copy)]
+enum Placement
+{
+    Ram,
+    TopOfRom(u16)
+}
+
+fn check_instruction(rng: &mut Rng, cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory, opcode: u8, placement: Placement)
+{
+    let Instruction(name, operation, addressing_mode, base_cycles) = &INSTRUCTIONS[opcode as usize];
+
+    // A random machine with the instruction in it (its operand bytes are just whatever's already there, or random
+    // ones for whatever's still in ROM)
+    for byte in memory.ram.iter_mut() { *byte = rng.byte(); }
+    let pc = match placement
+    {
+        Placement::Ram =>
+        {
+            let pc = 0x200 + (rng.next() % 0x5fd) as u16;
+            memory.ram[pc as usize] = opcode;
+            pc
+        }
+
+        Placement::TopOfRom(pc) =>
+        {
+            for offset in 0..3u16
+            {
+                let address = pc.wrapping_add(offset);
+                if address >= 0x8000
+                {
+                    let index = (address - 0x8000) as usize % memory.pgr_rom.len();
+                    memory.pgr_rom[index] = if offset == 0 { opcode } else { rng.byte() };
+                }
+            }
+            pc
+        }
+    };
+
+    cpu.pc = pc;
+    cpu.a = rng.byte();
+    cpu.x = rng.byte();
+    cpu.y = rng.byte();
+    cpu.sp = rng.byte();
+    cpu.flags = ProcessorState::from_bits_truncate(rng.byte());
+    cpu.cycles = 0;
+    cpu.halted = false;
+    let before = *cpu;
+
+    let context = format!("{} ({:#04x}) at {:#06x} with A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}, operands {:02x} {:02x}",
+        name, opcode, pc, before.a, before.x, before.y, before.flags.bits(), before.sp,
+        peek(ppu, memory, pc.wrapping_add(1)).unwrap(), peek(ppu, memory, pc.wrapping_add(2)).unwrap());
+
+    // Where it'll read or write (or jump to), worked out as the debugger would so as not to disturb anything, then
+    // what it'll read from there, where that can be known beforehand
+    let mut lookahead = before;
+    lookahead.pc = pc.wrapping_add(1);
+    let address = lookahead.fetch_operand(ppu, memory, addressing_mode, true).unwrap().data;
+    let argument = match addressing_mode
+    {
+        AddressingMode::Implied => None,
+        AddressingMode::Accumulator | AddressingMode::Immediate => Some(address as u8),
+        _ => peek(ppu, memory, address)
+    };
+    let irq_vector = memory.read_word(ppu, 0xfffe, true).unwrap();
+    let return_address = stack_word(memory, before.sp, 1);
+    let interrupt_return_address = stack_word(memory, before.sp, 2);
+
+    let cycles = match cpu.step(ppu, memory)
+    {
+        Ok(cycles) => cycles,
+
+        // Only the gaps in the opcode table are allowed to be unknown...
+        Err(EmuError::UnknownOpcode { opcode: unknown, address: at }) =>
+        {
+            assert!(matches!(operation, Operation::XXX), "{}: unexpectedly unknown", context);
+            assert_eq!((unknown, at), (opcode, pc), "{}", context);
+            return
+        }
+
+        // ... and random addresses can land in the unmapped part of the cartridge space (0x4020 - 0x5fff)
+        Err(EmuError::UnmappedRead(_)) | Err(EmuError::UnmappedWrite(_)) => return,
+
+        Err(error) => panic!("{}: {}", context, error)
+    };
+    assert!(!matches!(operation, Operation::XXX), "{}: should be unknown", context);
+
+    // ------------------------------ Where the PC went, and how long it took ------------------------------
+
+    let next_pc = pc.wrapping_add(instruction_length(addressing_mode));
+    if let Some(taken) = branch_taken(operation, before.flags)
+    {
+        // An extra cycle for taking the branch, and another if that's to a different page
+        let target = if taken { address } else { next_pc };
+        let page_crossed = taken && target & 0xff00 != next_pc & 0xff00;
+        assert_eq!(cpu.pc, target, "{}: branched to the wrong place", context);
+        assert_eq!(cycles, 2 + taken as u32 + page_crossed as u32, "{}: wrong cycles", context);
+    }
+    else
+    {
+        let expected_pc = match operation
+        {
+            Operation::JMP | Operation::JSR => address,
+            Operation::RTS => return_address.wrapping_add(1),
+            Operation::RTI => interrupt_return_address,
+            Operation::BRK => irq_vector,
+            Operation::JAM => pc,
+            _ => next_pc
+        };
+        assert_eq!(cpu.pc, expected_pc, "{}: PC is wrong", context);
+
+        // Page crossings can cost one more, but never any more than that
+        let base_cycles = *base_cycles as u32;
+        assert!(cycles == base_cycles || cycles == base_cycles + 1, "{}: took {} cycles", context, cycles);
+    }
+
+    // ----------------------------------------- Flags and results -----------------------------------------
+
+    let flags = cpu.flags;
+    let carry_in = before.flags.contains(ProcessorState::CARRY) as u16;
+    let on_accumulator = addressing_mode == &AddressingMode::Accumulator;
+
+    match operation
+    {
+        // Results in A, X or Y, which N and Z always follow
+        Operation::AND | Operation::EOR | Operation::ORA | Operation::TXA | Operation::TYA | Operation::PLA |
+        Operation::ANC | Operation::ALR | Operation::ARR | Operation::XAA | Operation::LAS =>
+            assert!(negative_and_zero_match(flags, cpu.a), "{}: N/Z don't match A", context),
+        Operation::TAX | Operation::TSX | Operation::AXS =>
+            assert!(negative_and_zero_match(flags, cpu.x), "{}: N/Z don't match X", context),
+        Operation::TAY =>
+            assert!(negative_and_zero_match(flags, cpu.y), "{}: N/Z don't match Y", context),
+
+        Operation::ASL | Operation::LSR | Operation::ROL | Operation::ROR | Operation::INC | Operation::DEC =>
+        {
+            // Writes to ROM go nowhere, so there's only a result to look at if it was in RAM
+            let result = if on_accumulator { Some(cpu.a) } else if address <= 0x1fff { Some(memory.ram[(address & 0x7ff) as usize]) } else { None };
+            if let Some(result) = result {
+                assert!(negative_and_zero_match(flags, result), "{}: N/Z don't match the result", context);
+            }
+        }
+
+        // Loads, which can be checked against what was there beforehand
+        Operation::LDA | Operation::LDX | Operation::LDY | Operation::LAX =>
+        {
+            let loaded = match operation { Operation::LDX => cpu.x, Operation::LDY => cpu.y, _ => cpu.a };
+            assert!(negative_and_zero_match(flags, loaded), "{}: N/Z don't match what was loaded", context);
+            if let Some(argument) = argument {
+                assert_eq!(loaded, argument, "{}: loaded the wrong value", context);
+            }
+            if matches!(operation, Operation::LAX) {
+                assert_eq!(cpu.x, cpu.a, "{}: X and A differ", context);
+            }
+        }
+
+        Operation::INX | Operation::DEX | Operation::INY | Operation::DEY =>
+        {
+            let (result, expected) = match operation
+            {
+                Operation::INX => (cpu.x, before.x.wrapping_add(1)),
+                Operation::DEX => (cpu.x, before.x.wrapping_sub(1)),
+                Operation::INY => (cpu.y, before.y.wrapping_add(1)),
+                _ => (cpu.y, before.y.wrapping_sub(1))
+            };
+            assert_eq!(result, expected, "{}: wrong result", context);
+            assert!(negative_and_zero_match(flags, result), "{}: N/Z don't match the result", context);
+        }
+
+        // Arithmetic, against the textbook definitions (SBC being ADC of the inverted argument)
+        Operation::ADC | Operation::SBC =>
+        {
+            if let Some(argument) = argument
+            {
+                let argument = if matches!(operation, Operation::SBC) { !argument } else { argument };
+                let sum = before.a as u16 + argument as u16 + carry_in;
+                let overflow = (!(before.a ^ argument) & (before.a ^ sum as u8) & 0x80) != 0;
+                assert_eq!(cpu.a, sum as u8, "{}: wrong result", context);
+                assert_eq!(flags.contains(ProcessorState::CARRY), sum > 0xff, "{}: wrong carry", context);
+                assert_eq!(flags.contains(ProcessorState::OVERFLOW), overflow, "{}: wrong overflow", context);
+                assert!(negative_and_zero_match(flags, cpu.a), "{}: N/Z don't match A", context);
+            }
+        }
+
+        Operation::CMP | Operation::CPX | Operation::CPY =>
+        {
+            let register = match operation { Operation::CPX => before.x, Operation::CPY => before.y, _ => before.a };
+            if let Some(argument) = argument
+            {
+                assert_eq!(flags.contains(ProcessorState::CARRY), register >= argument, "{}: wrong carry", context);
+                assert!(negative_and_zero_match(flags, register.wrapping_sub(argument)), "{}: N/Z don't match the difference", context);
+            }
+            assert_eq!((cpu.a, cpu.x, cpu.y), (before.a, before.x, before.y), "{}: registers changed", context);
+        }
+
+        Operation::BIT =>
+        {
+            if let Some(argument) = argument
+            {
+                assert_eq!(flags.contains(ProcessorState::ZERO), before.a & argument == 0, "{}: wrong zero", context);
+                assert_eq!(flags.contains(ProcessorState::NEGATIVE), argument & 0x80 != 0, "{}: wrong negative", context);
+                assert_eq!(flags.contains(ProcessorState::OVERFLOW), argument & 0x40 != 0, "{}: wrong overflow", context);
+            }
+            assert_eq!(cpu.a, before.a, "{}: A changed", context);
+        }
+
+        // Stores, which change nothing but memory
+        Operation::STA | Operation::STX | Operation::STY | Operation::SAX =>
+        {
+            assert_eq!(flags, before.flags, "{}: flags changed", context);
+            let stored = match operation
+            {
+                Operation::STX => before.x,
+                Operation::STY => before.y,
+                Operation::SAX => before.a & before.x,
+                _ => before.a
+            };
+            if address <= 0x1fff {
+                assert_eq!(memory.ram[(address & 0x7ff) as usize], stored, "{}: stored the wrong value", context);
+            }
+        }
+
+        Operation::JSR =>
+        {
+            // The address pushed is that of the JSR's last byte
+            assert_eq!(flags, before.flags, "{}: flags changed", context);
+            assert_eq!(cpu.sp, before.sp.wrapping_sub(2), "{}: wrong stack pointer", context);
+            assert_eq!(stack_word(memory, cpu.sp, 1), pc.wrapping_add(2), "{}: pushed the wrong address", context);
+        }
+
+        Operation::BRK =>
+        {
+            assert!(flags.contains(ProcessorState::DISABLE_INTERRUPTS), "{}: interrupts left enabled", context);
+            assert_eq!(cpu.sp, before.sp.wrapping_sub(3), "{}: wrong stack pointer", context);
+            assert_eq!(stack_word(memory, cpu.sp, 2), pc.wrapping_add(2), "{}: pushed the wrong address", context);
+            assert!(stack(memory, cpu.sp, 1) & ProcessorState::B_FLAG.bits() != 0, "{}: B flag wasn't pushed", context);
+        }
+
+        Operation::TXS => assert_eq!((cpu.sp, flags), (before.x, before.flags), "{}: wrong SP or flags", context),
+
+        Operation::JAM => assert!(cpu.halted, "{}: didn't halt", context),
+
+        // Nothing else about these touches the flags
+        Operation::JMP | Operation::RTS | Operation::NOP | Operation::IGN | Operation::SKB | Operation::PHA |
+        Operation::PHP | Operation::SHY | Operation::SHX | Operation::AHX | Operation::TAS |
+        Operation::BCC | Operation::BCS | Operation::BNE | Operation::BEQ |
+        Operation::BPL | Operation::BMI | Operation::BVC | Operation::BVS =>
+            assert_eq!(flags, before.flags, "{}: flags changed", context),
+
+        _ =>
+        {
+            if let Some((flag, set)) = flag_instruction(operation) {
+                assert_eq!(flags, (before.flags - flag) | if set { flag } else { ProcessorState::empty() }, "{}: wrong flags", context);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_opcode_behaves_on_random_machines()
+{
+    let mut rng = Rng(SEED);
+    for opcode in 0..=255u8
+    {
+        let (mut cpu, mut ppu, mut memory) = machine_from_program(&[]);
+        for _ in 0..RUNS_PER_OPCODE {
+            check_instruction(&mut rng, &mut cpu, &mut ppu, &mut memory, opcode, Placement::Ram);
+        }
+    }
+}
+
+#[test]
+fn every_opcode_behaves_at_the_top_of_the_address_space()
+{
+    let mut rng = Rng(SEED);
+    for opcode in 0..=255u8
+    {
+        let (mut cpu, mut ppu, mut memory) = machine_from_program(&[]);
+        for pc in [0xfffd, 0xfffe, 0xffff]
+        {
+            for _ in 0..RUNS_PER_OPCODE / 10 {
+                check_instruction(&mut rng, &mut cpu, &mut ppu, &mut memory, opcode, Placement::TopOfRom(pc));
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use common::{build_rom, load_rom, load_rom_with_ram_init};
+use nes_emulator_rust::on_emulation_cycle;
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::memory::{Buttons, Memory, RamInitMode};
+use nes_emulator_rust::ppu::Ppu;
+
+use std::path::Path;
+
+// Catches rendering (or CPU) regressions by running games for a while and hashing whatever ends up on screen.
+// The expected hashes live in "tests/frame_hashes.txt", one per line, as so:
+//
+//     rom frames hash [inputs]
+//
+// where "rom" is either a file in "tests/roms" (skipped if it isn't there, as ROMs aren't distributed with the
+// repo) or "builtin:" followed by the name of one of the programs below, and "inputs" is an optional list of
+// "frame:buttons" pairs (comma-separated, with the buttons in hex as in "memory::Buttons") saying what's held on
+// controller one from that frame onwards. If a change is *meant* to alter the picture, the hashes can be
+// regenerated by running with UPDATE_FRAME_HASHES=1 set - but do check the new frames are actually right first!
+
+const HASHES_FILE: &str = "tests/frame_hashes.txt";
+const ROM_DIRECTORY: &str = "tests/roms";
+
+struct Entry
+{
+    rom: String,
+    frames: usize,
+    hash: u64,
+    inputs: Vec<(usize, u8)>
+}
+
+impl Entry
+{
+    fn from_line(line: &str) -> Self
+    {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        assert!(parts.len() == 3 || parts.len() == 4, "invalid line in {}: {}", HASHES_FILE, line);
+
+        let inputs = parts.get(3).map(|inputs| inputs.split(',').map(|input|
+        {
+            let (frame, buttons) = input.split_once(':').expect("inputs should be frame:buttons");
+            (frame.parse().unwrap(), u8::from_str_radix(buttons, 16).unwrap())
+        }).collect()).unwrap_or_default();
+
+        Entry
+        {
+            rom: parts[0].to_string(),
+            frames: parts[1].parse().unwrap(),
+            hash: u64::from_str_radix(parts[2], 16).unwrap(),
+            inputs
+        }
+    }
+
+    fn to_line(&self) -> String
+    {
+        let mut line = format!("{} {} {:016x}", self.rom, self.frames, self.hash);
+        if !self.inputs.is_empty()
+        {
+            let inputs: Vec<String> = self.inputs.iter().map(|(frame, buttons)| format!("{}:{:02x}", frame, buttons)).collect();
+            line += &format!(" {}", inputs.join(","));
+        }
+        line
+    }
+}
+
+// Sets up a backdrop, one colour and a row of solid tiles, then sits there turning greyscale on whilst A's held
+fn builtin_greyscale_on_a() -> Vec<u8>
+{
+    let mut rom = build_rom(&[
+        0xa9, 0x3f, 0x8d, 0x06, 0x20,   // LDA #$3f, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20,   // LDA #$00, STA $2006
+        0xa9, 0x16, 0x8d, 0x07, 0x20,   // LDA #$16, STA $2007
+        0xa9, 0x30, 0x8d, 0x07, 0x20,   // LDA #$30, STA $2007
+        0xa9, 0x20, 0x8d, 0x06, 0x20,   // LDA #$20, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20,   // LDA #$00, STA $2006
+        0xa2, 0x20,                     // LDX #$20
+        0xa9, 0x01,                     // LDA #$01
+        0x8d, 0x07, 0x20,               // STA $2007
+        0xca,                           // DEX
+        0xd0, 0xfa,                     // BNE -6
+        0xa9, 0x00, 0x8d, 0x05, 0x20,   // LDA #$00, STA $2005
+        0x8d, 0x05, 0x20,               // STA $2005
+        0xa9, 0x01, 0x8d, 0x16, 0x40,   // LDA #$01, STA $4016 (at 0x8030)
+        0xa9, 0x00, 0x8d, 0x16, 0x40,   // LDA #$00, STA $4016
+        0xad, 0x16, 0x40,               // LDA $4016
+        0x29, 0x01,                     // AND #$01
+        0x09, 0x0a,                     // ORA #$0a
+        0x8d, 0x01, 0x20,               // STA $2001
+        0x4c, 0x30, 0x80,               // JMP $8030
+    ]);
+
+    // Tile 1 is solid
+    let chr_offset = 16 + 0x4000;
+    rom[chr_offset + 0x10..chr_offset + 0x18].copy_from_slice(&[0xff; 8]);
+    rom
+}
+
+fn load_entry_rom(rom: &str) -> Option<Memory>
+{
+    match rom.strip_prefix("builtin:")
+    {
+        Some("greyscale-on-a") => Some(load_rom(&builtin_greyscale_on_a()).unwrap()),
+        Some(name) => panic!("no builtin program called {}", name),
+        None =>
+        {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIRECTORY).join(rom);
+            if !path.exists() { return None }
+            Some(Memory::from_file(path.to_str().unwrap()).unwrap())
+        }
+    }
+}
+
+fn run_frames(mut memory: Memory, frames: usize, inputs: &[(usize, u8)]) -> (Cpu, Ppu, Memory)
+{
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+
+    for frame in 0..frames
+    {
+        if let Some((_, buttons)) = inputs.iter().rev().find(|(from, _)| *from <= frame) {
+            memory.set_controller(0, Buttons::from_bits_truncate(*buttons));
+        }
+
+        on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    }
+
+    (cpu, ppu, memory)
+}
+
+fn hash_frames(memory: Memory, frames: usize, inputs: &[(usize, u8)]) -> u64
+{
+    let (_, ppu, _) = run_frames(memory, frames, inputs);
+    ppu.get_output_hash()
+}
+
+#[test]
+fn frame_hashes_match()
+{
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(HASHES_FILE);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let update = std::env::var("UPDATE_FRAME_HASHES").is_ok();
+
+    let mut lines = Vec::new();
+    let mut mismatches = Vec::new();
+
+    for line in contents.lines()
+    {
+        if line.trim().is_empty() || line.starts_with('#')
+        {
+            lines.push(line.to_string());
+            continue
+        }
+
+        let mut entry = Entry::from_line(line);
+        let memory = match load_entry_rom(&entry.rom)
+        {
+            Some(memory) => memory,
+            None =>
+            {
+                println!("{} not found; skipping", entry.rom);
+                lines.push(line.to_string());
+                continue
+            }
+        };
+
+        let hash = hash_frames(memory, entry.frames, &entry.inputs);
+        if hash != entry.hash
+        {
+            mismatches.push(format!("{} after {} frames: expected {:016x}, got {:016x}", entry.rom, entry.frames, entry.hash, hash));
+            entry.hash = hash;
+        }
+
+        lines.push(entry.to_line());
+    }
+
+    if update
+    {
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        println!("Updated {}:\n{}", HASHES_FILE, mismatches.join("\n"));
+        return
+    }
+
+    assert!(mismatches.is_empty(), "\n{}\n(if this was intended, rerun with UPDATE_FRAME_HASHES=1)", mismatches.join("\n"));
+}
+
+// Hashes only catch changes to what's on screen, and only for ROMs that have been hashed, so this checks that two
+// machines built separately from the same ROM (and the same seeded RAM) end up exactly the same given the same
+// inputs - anything reading the time, global state or memory that's never been set would soon pull them apart
+#[test]
+fn separate_runs_end_up_identical()
+{
+    let inputs = [(0, 0x00), (5, 0x80), (12, 0x00), (20, 0x81), (27, 0x80)];
+    let run = || run_frames(load_rom_with_ram_init(&builtin_greyscale_on_a(), RamInitMode::Random(1234)).unwrap(), 40, &inputs);
+    let (first_cpu, first_ppu, first_memory) = run();
+    let (second_cpu, second_ppu, second_memory) = run();
+
+    assert!(first_ppu.output == second_ppu.output, "framebuffers differ");
+    assert!(first_memory.ram == second_memory.ram, "RAM differs");
+    assert!(first_memory.prg_ram == second_memory.prg_ram, "PRG RAM differs");
+    assert_eq!(first_ppu.dump_vram(), second_ppu.dump_vram());
+    assert_eq!(first_memory.apu.samples, second_memory.apu.samples);
+
+    assert_eq!(
+        (first_cpu.pc, first_cpu.sp, first_cpu.a, first_cpu.x, first_cpu.y, first_cpu.flags.bits(), first_cpu.total_cycles),
+        (second_cpu.pc, second_cpu.sp, second_cpu.a, second_cpu.x, second_cpu.y, second_cpu.flags.bits(), second_cpu.total_cycles)
+    );
+    assert_eq!(
+        (first_ppu.frame_count, first_ppu.get_scanline(), first_ppu.get_dot(), first_ppu.get_control().bits(), first_ppu.get_mask().bits()),
+        (second_ppu.frame_count, second_ppu.get_scanline(), second_ppu.get_dot(), second_ppu.get_control().bits(), second_ppu.get_mask().bits())
+    );
+
+    // And it did actually get going, rather than both sitting there doing nothing
+    assert!(first_ppu.frame_count >= 40);
+    assert!(first_ppu.get_mask().bits() != 0);
+}
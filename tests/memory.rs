@@ -0,0 +1,379 @@
+mod common;
+
+use common::{build_rom, load_rom, load_rom_with_ram_init, machine_from_program};
+use nes_emulator_rust::{on_emulation_cycle, run_instruction};
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::{Buttons, Memory, RamInitMode};
+use nes_emulator_rust::ppu::Ppu;
+
+#[test]
+fn prg_rom_writes_are_ignored()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x55,         // LDA #$55
+        0x8d, 0x00, 0x80,   // STA $8000
+        0x8d, 0x00, 0xc0,   // STA $c000
+    ]);
+
+    for _ in 0..3 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+
+    // Still the LDA opcode, both at 0x8000 and its mirror
+    assert_eq!(memory.read_byte(&mut ppu, 0x8000, false).unwrap(), 0xa9);
+    assert_eq!(memory.read_byte(&mut ppu, 0xc000, false).unwrap(), 0xa9);
+}
+
+#[test]
+fn rom_loads_from_bytes()
+{
+    let mut rom = build_rom(&[0xea]);
+    rom[16 + 0x4000..].copy_from_slice(&[0x42; 0x2000]);
+
+    let memory = Memory::from_bytes(&rom).unwrap();
+    assert_eq!(memory.pgr_rom.len(), 0x4000);
+    assert_eq!(memory.pgr_rom[0], 0xea);
+    assert_eq!(memory.chr_rom, vec![0x42; 0x2000]);
+
+    // Loading from a file should be no different
+    let path = std::env::temp_dir().join(format!("nes-emulator-rust-test-{}.nes", std::process::id()));
+    std::fs::write(&path, &rom).unwrap();
+    let from_file = Memory::from_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    let from_file = from_file.unwrap();
+    assert_eq!(from_file.pgr_rom, memory.pgr_rom);
+    assert_eq!(from_file.chr_rom, memory.chr_rom);
+
+    // And what's too short to be a ROM is still turned away
+    assert!(Memory::from_bytes(&rom[..8]).is_err());
+}
+
+#[test]
+fn fds_images_are_turned_away()
+{
+    // A headered image of one side (65500 bytes), then the same without the header
+    let mut image = b"FDS\x1a\x01".to_vec();
+    image.resize(16, 0);
+    image.extend(b"\x01*NINTENDO-HVC*");
+    image.resize(16 + 65500, 0);
+
+    assert_eq!(Memory::from_bytes(&image).err(), Some(EmuError::FdsNotSupported));
+    assert_eq!(Memory::from_bytes(&image[16..]).err(), Some(EmuError::FdsNotSupported));
+
+    // Whereas anything else that isn't a ROM is still just a bad one
+    image[..4].copy_from_slice(b"ABC\x1a");
+    assert!(matches!(Memory::from_bytes(&image), Err(EmuError::BadRom(..))));
+}
+
+#[test]
+fn trainer_is_loaded_into_prg_ram()
+{
+    // The trainer goes between the header and PRG ROM, and is signalled by bit 2 of flags 6
+    let mut rom = build_rom(&[]);
+    let trainer: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    rom[6] |= 0x04;
+    rom.splice(16..16, trainer.iter().cloned());
+
+    let memory = load_rom(&rom).unwrap();
+    assert_eq!(&memory.prg_ram[0x1000..0x1200], trainer.as_slice());
+    assert!(memory.prg_ram[..0x1000].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn ram_is_filled_on_power_on()
+{
+    let rom = build_rom(&[]);
+
+    let memory = load_rom(&rom).unwrap();
+    assert!(memory.ram.iter().all(|byte| *byte == 0));
+
+    let memory = load_rom_with_ram_init(&rom, RamInitMode::Filled(0xaa)).unwrap();
+    assert!(memory.ram.iter().all(|byte| *byte == 0xaa));
+
+    let memory = load_rom_with_ram_init(&rom, RamInitMode::HardwarePattern).unwrap();
+    assert_eq!(&memory.ram[0..16], &[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(&memory.ram[2040..2048], &[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+}
+
+#[test]
+fn bus_conflicts_mask_bank_writes()
+{
+    // CNROM (mapper 3) with 4 banks of CHR, each starting with its own number, and 0x01 in ROM at 0x8000
+    let mut rom = build_rom(&[0x01]);
+    rom[5] = 4;
+    rom[6] |= 0x30;
+    for bank in 1..4u8
+    {
+        let mut chr = vec![0u8; 0x2000];
+        chr[0] = bank;
+        rom.extend(chr);
+    }
+
+    let mut memory = load_rom(&rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    assert!(memory.bus_conflicts);
+
+    // Only the bits set in both the value and the ROM byte get through
+    memory.write_byte(&mut ppu, 0x8000, 0x03).unwrap();
+    assert_eq!(memory.read_byte_from_ppu(0x0000), (true, 1));
+
+    // Without conflicts, the value's taken as it is
+    memory.bus_conflicts = false;
+    memory.write_byte(&mut ppu, 0x8000, 0x03).unwrap();
+    assert_eq!(memory.read_byte_from_ppu(0x0000), (true, 3));
+}
+
+#[test]
+fn ram_writes_are_mirrored()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x55,         // LDA #$55
+        0x8d, 0x00, 0x08,   // STA $0800
+        0xa9, 0xaa,         // LDA #$aa
+        0x8d, 0xff, 0x1f,   // STA $1fff
+    ]);
+
+    for _ in 0..4 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+
+    // Both land in the real 2 KB, and so can be read back from any of its mirrors
+    assert_eq!(memory.read_byte(&mut ppu, 0x0000, false).unwrap(), 0x55);
+    assert_eq!(memory.read_byte(&mut ppu, 0x1000, false).unwrap(), 0x55);
+    assert_eq!(memory.read_byte(&mut ppu, 0x07ff, false).unwrap(), 0xaa);
+    assert_eq!(memory.ram[0x7ff], 0xaa);
+}
+
+#[test]
+fn words_are_little_endian()
+{
+    let (_, mut ppu, mut memory) = machine_from_program(&[0x34, 0x12]);
+    assert_eq!(memory.read_word(&mut ppu, 0x8000, false).unwrap(), 0x1234);
+
+    // The zero page version wraps round within the page
+    memory.ram[0xff] = 0x78;
+    memory.ram[0x00] = 0x56;
+    assert_eq!(memory.read_word_from_first_page(&mut ppu, 0xff, false).unwrap(), 0x5678);
+
+    // The high byte's read last, so it's what's left on the bus
+    assert_eq!(memory.last_bus_value, 0x56);
+}
+
+#[test]
+fn gxrom_switches_prg_and_chr_together()
+{
+    // GxROM (mapper 66) with 2 banks of PRG and 4 of CHR, each starting with its own number
+    let mut rom = vec![b'N', b'E', b'S', 0x1a, 4, 4, 0x20, 0x40, 0, 0, 0, 0, 0, 0, 0, 0];
+    for bank in 0..2u8
+    {
+        let mut prg = vec![0u8; 0x8000];
+        prg[0] = bank;
+        prg[1] = 0xff; // So that writes here don't lose any bits to bus conflicts
+        prg[0x7ffd] = 0x80; // Reset vector
+        rom.extend(prg);
+    }
+    for bank in 0..4u8
+    {
+        let mut chr = vec![0u8; 0x2000];
+        chr[0] = bank;
+        rom.extend(chr);
+    }
+
+    let mut memory = load_rom(&rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    assert_eq!(memory.read_byte(&mut ppu, 0x8000, false).unwrap(), 0);
+    assert_eq!(memory.read_byte_from_ppu(0x0000), (true, 0));
+
+    // PRG bank 1 and CHR bank 3 in one go
+    memory.write_byte(&mut ppu, 0x8001, 0x13).unwrap();
+    assert_eq!(memory.read_byte(&mut ppu, 0x8000, false).unwrap(), 1);
+    assert_eq!(memory.read_byte_from_ppu(0x0000), (true, 3));
+}
+
+// Copies the first byte of RAM into the backdrop colour, then loops forever, so what's on screen depends on RAM
+fn frame_hash_with_ram_init(ram_init: RamInitMode, frames: usize) -> (u64, [u8; 2048])
+{
+    let rom = build_rom(&[
+        0xa9, 0x3f, 0x8d, 0x06, 0x20,   // LDA #$3f, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20,   // LDA #$00, STA $2006
+        0xa5, 0x00,                     // LDA $00
+        0x29, 0x3f,                     // AND #$3f
+        0x8d, 0x07, 0x20,               // STA $2007
+        0x4c, 0x11, 0x80,               // JMP $8011
+    ]);
+
+    let mut memory = load_rom_with_ram_init(&rom, ram_init).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    let initial_ram = memory.ram;
+
+    for _ in 0..frames {
+        on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+    }
+    (ppu.get_output_hash(), initial_ram)
+}
+
+#[test]
+fn seeded_ram_is_reproducible()
+{
+    let (hash, ram) = frame_hash_with_ram_init(RamInitMode::Random(1234), 3);
+    assert_eq!(frame_hash_with_ram_init(RamInitMode::Random(1234), 3), (hash, ram));
+
+    // It should at least look random...
+    assert!(ram.iter().any(|byte| *byte != ram[0]));
+
+    // ...and differ from seed to seed
+    let (_, other_ram) = frame_hash_with_ram_init(RamInitMode::Random(5678), 3);
+    assert_ne!(ram, other_ram);
+}
+
+// How many cycles the CPU's stalled for by an OAM DMA from page 2, after running "setup" (and checks the DMA
+// copied the page over whilst it's at it)
+fn dma_stall_after(setup: &[u8]) -> u64
+{
+    let mut program = setup.to_vec();
+    program.extend([0xa9, 0x02, 0x8d, 0x14, 0x40, 0xea]); // LDA #$02, STA $4014, NOP
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&program);
+    for i in 0..256 { memory.ram[0x200 + i] = i as u8 ^ 0x5a; }
+
+    // Up to the end of the STA, then the NOP, which can't start until the DMA's done
+    for _ in 0..setup.len() / 2 + 2 { run_instruction(&mut cpu, &mut ppu, &mut memory).unwrap(); }
+    let before = cpu.total_cycles;
+    run_instruction(&mut cpu, &mut ppu, &mut memory).unwrap();
+
+    assert!(!memory.dma_happening);
+    assert!(ppu.object_attribute_memory.iter().enumerate().all(|(i, byte)| *byte == i as u8 ^ 0x5a));
+    cpu.total_cycles - before - 2
+}
+
+#[test]
+fn oam_dma_stalls_for_513_or_514_cycles()
+{
+    // The reset takes 7 cycles, then 2 for the LDA and 4 for the STA, which leaves the DMA starting on an odd
+    // cycle; another 3 cycle instruction beforehand makes it even
+    assert_eq!(dma_stall_after(&[]), 514);
+    assert_eq!(dma_stall_after(&[0xa5, 0x00]), 513); // LDA $00
+}
+
+#[test]
+fn roms_without_chr_get_chr_ram()
+{
+    // Same as usual, but with no CHR banks, and so nothing after PRG
+    let mut rom = build_rom(&[0x4c, 0x00, 0x80]); // JMP $8000
+    rom[5] = 0;
+    rom.truncate(16 + 0x4000);
+
+    let mut memory = load_rom(&rom).unwrap();
+    assert!(memory.rom_header.has_chr_ram());
+    assert_eq!(memory.chr_rom.len(), 0x2000);
+
+    // Pattern tables can be read and written through the PPU, and a frame can be drawn from them
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x0000), 0);
+    ppu.write_byte_from_ppu(&mut memory, 0x1fff, 0x42);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x1fff), 0x42);
+
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    on_emulation_cycle(&mut cpu, &mut ppu, &mut memory).unwrap();
+}
+
+#[test]
+fn controllers_are_read_back_a_button_at_a_time()
+{
+    let (_, mut ppu, mut memory) = machine_from_program(&[]);
+    memory.set_controller(0, Buttons::A | Buttons::START | Buttons::LEFT);
+    memory.set_controller(1, Buttons::B | Buttons::DOWN);
+    memory.set_controller(2, Buttons::all()); // No such port
+
+    // Strobing latches both controllers, after which each read gives the next button, A first
+    memory.write_byte(&mut ppu, 0x4016, 1).unwrap();
+    memory.write_byte(&mut ppu, 0x4016, 0).unwrap();
+    let mut read = |address: u16| -> Vec<u8> { (0..8).map(|_| memory.read_byte(&mut ppu, address, false).unwrap() & 1).collect() };
+    assert_eq!(read(0x4016), [1, 0, 0, 1, 0, 0, 1, 0]);
+    assert_eq!(read(0x4017), [0, 1, 0, 0, 0, 1, 0, 0]);
+}
+
+#[test]
+fn buttons_are_in_the_order_the_cpu_reads_them()
+{
+    let order = [Buttons::A, Buttons::B, Buttons::SELECT, Buttons::START, Buttons::UP, Buttons::DOWN, Buttons::LEFT, Buttons::RIGHT];
+    for (i, button) in order.iter().enumerate() {
+        assert_eq!(button.bits(), 0x80 >> i);
+    }
+}
+
+#[test]
+fn ppu_registers_are_mirrored_every_eight_bytes()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xa9, 0x21,         // LDA #$21
+        0x8d, 0x0e, 0x20,   // STA $200e (PPU address)
+        0xa9, 0x00,         // LDA #$00
+        0x8d, 0x06, 0x3f,   // STA $3f06 (PPU address)
+        0xa9, 0x42,         // LDA #$42
+        0x8d, 0xff, 0x3f,   // STA $3fff (PPU data)
+        0x8d, 0x0a, 0x30,   // STA $300a (PPU status, which can't be written)
+    ]);
+
+    for _ in 0..7 { cpu.step(&mut ppu, &mut memory).unwrap(); }
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x2100), 0x42);
+    assert_eq!(ppu.get_vram_address(), 0x2101);
+}
+
+#[test]
+fn holding_the_strobe_keeps_returning_button_a()
+{
+    let (_, mut ppu, mut memory) = machine_from_program(&[]);
+    memory.write_byte(&mut ppu, 0x4016, 1).unwrap();
+
+    // Whilst it's held high, the controller keeps reloading, so nothing ever shifts along to B
+    memory.set_controller(0, Buttons::A);
+    for _ in 0..4 { assert_eq!(memory.read_byte(&mut ppu, 0x4016, false).unwrap() & 1, 1); }
+
+    memory.set_controller(0, Buttons::B);
+    for _ in 0..4 { assert_eq!(memory.read_byte(&mut ppu, 0x4016, false).unwrap() & 1, 0); }
+}
+
+#[test]
+fn controller_reads_keep_the_rest_of_the_bus()
+{
+    let (mut cpu, mut ppu, mut memory) = machine_from_program(&[
+        0xad, 0x16, 0x40,   // LDA $4016
+        0xad, 0x16, 0x40,   // LDA $4016
+    ]);
+    memory.set_controller(0, Buttons::A);
+    memory.write_byte(&mut ppu, 0x4016, 1).unwrap();
+    memory.write_byte(&mut ppu, 0x4016, 0).unwrap();
+
+    // The last thing on the bus was the operand's high byte, 0x40, which is where the upper bits come from,
+    // leaving bit 0 as the button (pressed for A, then released for B)
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.a & 0xfe, 0x40);
+    assert_eq!(cpu.a & 1, 1);
+
+    cpu.step(&mut ppu, &mut memory).unwrap();
+    assert_eq!(cpu.a & 0xfe, 0x40);
+    assert_eq!(cpu.a & 1, 0);
+}
+
+#[test]
+fn reads_past_the_eighth_return_one()
+{
+    let (_, mut ppu, mut memory) = machine_from_program(&[]);
+    memory.write_byte(&mut ppu, 0x4016, 1).unwrap();
+    memory.write_byte(&mut ppu, 0x4016, 0).unwrap();
+
+    // Nothing's pressed, so the 8 buttons are all 0, and then the ones shifted in behind them come out
+    let reads: Vec<u8> = (0..16).map(|_| memory.read_byte(&mut ppu, 0x4016, false).unwrap() & 1).collect();
+    assert_eq!(reads[..8], [0; 8]);
+    assert_eq!(reads[8..], [1; 8]);
+}
+
+#[test]
+fn ines_2_headers_load()
+{
+    // Byte 7's 0x08 marks iNES 2.0, which used to be an unknown flag bit (and so a panic)
+    let mut rom = build_rom(&[]);
+    rom[7] = 0x08;
+
+    let memory = load_rom(&rom).unwrap();
+    assert_eq!(memory.rom_header.get_ines_version(), 2);
+}
@@ -0,0 +1,85 @@
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::memory::Memory;
+use nes_emulator_rust::ppu::Ppu;
+
+use std::path::Path;
+
+// nestest has an automated mode, entered by starting at 0xc000 instead of the reset vector, which runs through
+// (nearly) every opcode without needing the PPU. Its log of the CPU's state before each instruction is the
+// "golden" trace to compare against, line by line, as so:
+//
+//     C000  4C F5 C5  JMP $C5F5          A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// As with the other ROMs, neither is distributed with the repo, so place "nestest.nes" and "nestest.log" in
+// "tests/roms" to run this (otherwise there's nothing to do).
+
+const ROM_DIRECTORY: &str = "tests/roms";
+const AUTOMATED_START: u16 = 0xc000;
+
+struct LogLine
+{
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    cycles: u64
+}
+
+impl LogLine
+{
+    fn from_line(line: &str) -> Self
+    {
+        let field = |name: &str| -> &str
+        {
+            let start = line.find(name).unwrap_or_else(|| panic!("no {} in log line: {}", name, line)) + name.len();
+            line[start..].split_whitespace().next().unwrap()
+        };
+        let byte = |name: &str| u8::from_str_radix(field(name), 16).unwrap();
+
+        LogLine
+        {
+            pc: u16::from_str_radix(&line[0..4], 16).unwrap(),
+            a: byte("A:"),
+            x: byte("X:"),
+            y: byte("Y:"),
+            p: byte("P:"),
+            sp: byte("SP:"),
+            cycles: field("CYC:").parse().unwrap()
+        }
+    }
+}
+
+#[test]
+fn nestest_matches_golden_log()
+{
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIRECTORY);
+    let (rom, log) = (directory.join("nestest.nes"), directory.join("nestest.log"));
+    if !rom.exists() || !log.exists()
+    {
+        println!("nestest.nes and nestest.log not found in {}; skipping", ROM_DIRECTORY);
+        return
+    }
+
+    let mut memory = Memory::from_file(rom.to_str().unwrap()).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let mut cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    cpu.set_pc(AUTOMATED_START);
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    for (number, line) in log.lines().enumerate().filter(|(_, line)| !line.trim().is_empty())
+    {
+        let expected = LogLine::from_line(line);
+
+        // The B flag doesn't really exist, so it's down to the log (and us) what to show for it
+        let actual = (cpu.pc, cpu.a, cpu.x, cpu.y, cpu.flags.bits() & !0x10, cpu.sp, cpu.total_cycles);
+        assert_eq!(actual, (expected.pc, expected.a, expected.x, expected.y, expected.p & !0x10, expected.sp, expected.cycles),
+            "\nmismatch at line {} of nestest.log (pc, a, x, y, p, sp, cycles):\n{}", number + 1, line);
+
+        cpu.step(&mut ppu, &mut memory).unwrap();
+    }
+
+    // Its results end up in 0x02 and 0x03 too, where anything other than zero is an error code
+    assert_eq!((memory.ram[2], memory.ram[3]), (0, 0), "nestest reported errors");
+}
@@ -0,0 +1,704 @@
+mod common;
+
+use common::{build_rom, load_rom};
+use nes_emulator_rust::memory::{Memory, Mirroring};
+use nes_emulator_rust::palette_table::{Colour, PALETTE_TABLE};
+use nes_emulator_rust::ppu::{ObjectAttribute, Ppu, PpuControl, PpuMask, Region, SCREEN_WIDTH, SCREEN_HEIGHT, TEST_PATTERN_BARS, TEST_PATTERN_GRID_SIZE};
+
+// Tile 0x02 from pattern table 1 (the low bit of the ID), with the given attributes
+fn double_height_sprite(attributes: u8) -> ObjectAttribute
+{
+    ObjectAttribute::from([0, 0x03, attributes, 0])
+}
+
+#[test]
+fn double_height_sprites_swap_tiles_when_flipped()
+{
+    let unflipped = double_height_sprite(0);
+    let flipped = double_height_sprite(0x80);
+
+    for row in 0..16u8
+    {
+        // Unflipped, the top tile is drawn first and then the one after it
+        let cell = if row < 8 { 0x02 } else { 0x03 };
+        assert_eq!(unflipped.get_pattern_address(row, 16, 0), 0x1000 | (cell << 4) | (row as u16 & 7), "row {}", row);
+
+        // Flipped, it's the other way round, and each tile is drawn upside down
+        let cell = if row < 8 { 0x03 } else { 0x02 };
+        assert_eq!(flipped.get_pattern_address(row, 16, 0), 0x1000 | (cell << 4) | (7 - (row as u16 & 7)), "row {}", row);
+
+        // Which is to say that a flipped sprite is an unflipped one read from the bottom up
+        assert_eq!(flipped.get_pattern_address(row, 16, 0), unflipped.get_pattern_address(15 - row, 16, 0), "row {}", row);
+    }
+}
+
+#[test]
+fn single_height_sprites_flip_within_their_tile()
+{
+    let unflipped = ObjectAttribute::from([0, 0x03, 0, 0]);
+    let flipped = ObjectAttribute::from([0, 0x03, 0x80, 0]);
+
+    for row in 0..8u8
+    {
+        assert_eq!(unflipped.get_pattern_address(row, 8, 1), 0x1030 | row as u16);
+        assert_eq!(flipped.get_pattern_address(row, 8, 1), 0x1030 | (7 - row as u16));
+    }
+}
+
+// Fills the background with an opaque tile (tile zero, which every name table entry points to by default), puts
+// sprite zero - made of that same tile - at the given X, and returns whether a hit was reported during the frame
+fn sprite_zero_hit_at(x: u8) -> bool
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    memory.chr_rom[0..8].copy_from_slice(&[0xff; 8]);
+    ppu.object_attribute_memory[0..4].copy_from_slice(&[100, 0, 0, x]);
+
+    // Show everything, including the leftmost pixels
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+
+    ppu.execute(&mut memory);
+    while !ppu.at_vblank_start() { ppu.execute(&mut memory); }
+    ppu.read_byte_from_cpu(&mut memory, 0x2002, true) & 0x40 != 0
+}
+
+#[test]
+fn sprite_zero_hit_ignores_rightmost_pixel()
+{
+    // At x = 255 only the sprite's leftmost pixel is on screen, and that can't hit
+    assert!(!sprite_zero_hit_at(255));
+
+    // One pixel to the left it can, though
+    assert!(sprite_zero_hit_at(254));
+}
+
+fn run_until(ppu: &mut Ppu, memory: &mut Memory, scanline: i16, dot: i16)
+{
+    while ppu.get_scanline() != scanline || ppu.get_dot() != dot { ppu.execute(memory); }
+}
+
+fn is_lit(ppu: &Ppu, x: usize, y: usize) -> bool
+{
+    ppu.output[(y * SCREEN_WIDTH + x) * 3] != ppu.output[0]
+}
+
+#[test]
+fn mid_frame_address_writes_move_the_tile_fetches()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Tile 1 is solid, and the only place it's used is 0x2109 (coarse X 9, coarse Y 8)
+    memory.chr_rom[0x10..0x18].copy_from_slice(&[0xff; 8]);
+    for (address, value) in [(0x2109u16, 0x01u8), (0x3f01, 0x30)]
+    {
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, (address >> 8) as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, address as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2007, value);
+    }
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x0a);
+
+    // Halfway along scanline 100, point the PPU at 0x2108; coarse X is bumped at dot 104, so the tile fetched at
+    // dot 105 is the one at 0x2109, and it's shown two tiles later
+    run_until(&mut ppu, &mut memory, 100, 100);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x21);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x08);
+    assert_eq!(ppu.get_vram_address(), 0x2108);
+
+    run_until(&mut ppu, &mut memory, 100, 106);
+    assert_eq!(ppu.get_vram_address(), 0x2109);
+
+    run_until(&mut ppu, &mut memory, 102, 0);
+    assert!(!is_lit(&ppu, 119, 100));
+    assert!((120..128).all(|x| is_lit(&ppu, x, 100)));
+    assert!(!is_lit(&ppu, 128, 100));
+
+    // The write sticks for the rest of the frame, too, so the next line starts from coarse X 8
+    assert!(!is_lit(&ppu, 7, 101));
+    assert!((8..16).all(|x| is_lit(&ppu, x, 101)));
+    assert!(!is_lit(&ppu, 16, 101));
+}
+
+#[test]
+fn reading_status_resets_the_address_latch()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Reading 0x2002 halfway through throws away the high byte, so the next write is a high byte again
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x21);
+    ppu.read_byte_from_cpu(&mut memory, 0x2002, false);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x23);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x45);
+    assert_eq!(ppu.get_vram_address(), 0x2345);
+
+    // The debugger peeking at it mustn't, though
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x21);
+    ppu.read_byte_from_cpu(&mut memory, 0x2002, true);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x08);
+    assert_eq!(ppu.get_vram_address(), 0x2108);
+
+    // The latch is shared with 0x2005 too, so a lone scroll write leaves 0x2006 expecting a low byte...
+    ppu.write_byte_from_cpu(&mut memory, 0x2005, 0x00);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x10);
+    assert_eq!(ppu.get_vram_address() & 0xff, 0x10);
+
+    // ...unless the PPU's reset in between
+    ppu.write_byte_from_cpu(&mut memory, 0x2005, 0x00);
+    ppu.reset();
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x23);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x45);
+    assert_eq!(ppu.get_vram_address(), 0x2345);
+}
+
+#[test]
+fn greyscale_is_applied_before_emphasis()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // A red backdrop, with greyscale and red emphasis on (but no rendering, so the backdrop's all there is)
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x3f);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x00);
+    ppu.write_byte_from_cpu(&mut memory, 0x2007, 0x16);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x21);
+
+    ppu.execute(&mut memory);
+    while !ppu.at_vblank_start() { ppu.execute(&mut memory); }
+
+    // Greyscale takes 0x16 to the grey at 0x10, then emphasising red darkens the green and blue of that
+    let Colour(red, green, blue) = PALETTE_TABLE[0x10];
+    assert_eq!(&ppu.output[0..3], &[red, (green as f32 * 0.816) as u8, (blue as f32 * 0.816) as u8]);
+}
+
+// Every dot v-blank was set on over a whole frame, from just after it's cleared, as (scanline, dot) for the first and
+// last of them, along with how many there were
+#[test]
+fn pal_frames_are_longer_than_ntsc_ones()
+{
+    // 341 dots a line, for 262 lines or 312
+    assert_eq!(Region::Ntsc.cycles_per_frame(), 89342);
+    assert_eq!(Region::Pal.cycles_per_frame(), 106392);
+
+    // And that's how long it actually takes to come round again, give or take NTSC's skipped dot
+    let frame_length = |region: Region|
+    {
+        let mut memory = load_rom(&build_rom(&[])).unwrap();
+        let mut ppu = Ppu::from_region(region);
+        run_until(&mut ppu, &mut memory, 1, 0);
+
+        ppu.execute(&mut memory);
+        let mut dots = 1;
+        while (ppu.get_scanline(), ppu.get_dot()) != (1, 0)
+        {
+            ppu.execute(&mut memory);
+            dots += 1;
+        }
+        dots
+    };
+    assert!(Region::Ntsc.cycles_per_frame() - frame_length(Region::Ntsc) <= 1);
+    assert_eq!(frame_length(Region::Pal), Region::Pal.cycles_per_frame());
+}
+
+fn vblank_dots(region: Region) -> ((i16, i16), (i16, i16), usize)
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(region);
+    run_until(&mut ppu, &mut memory, -1, 2);
+
+    // As seen just after each dot's been run, which is when the CPU would next get to read it
+    let mut dots = Vec::new();
+    for _ in 0..region.cycles_per_frame()
+    {
+        let (scanline, dot) = (ppu.get_scanline(), ppu.get_dot());
+        ppu.execute(&mut memory);
+        if ppu.in_vblank() { dots.push((scanline, dot)); }
+    }
+
+    (dots[0], dots[dots.len() - 1], dots.len())
+}
+
+#[test]
+fn vblank_is_set_for_exactly_the_right_dots()
+{
+    // Set on dot 1 of scanline 241 and cleared on dot 1 of the pre-render scanline, so 20 scanlines on NTSC...
+    let (first, last, count) = vblank_dots(Region::Ntsc);
+    assert_eq!(first, (241, 1));
+    assert_eq!(last, (-1, 0));
+    assert_eq!(count, 20 * 341);
+
+    // ...and 70 on PAL, which has all the extra ones
+    let (first, last, count) = vblank_dots(Region::Pal);
+    assert_eq!(first, (241, 1));
+    assert_eq!(last, (-1, 0));
+    assert_eq!(count, 70 * 341);
+}
+
+#[test]
+fn vblank_flag_changes_on_exactly_dot_one()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Not set until dot 1's been run...
+    run_until(&mut ppu, &mut memory, 241, 0);
+    ppu.execute(&mut memory);
+    assert!(!ppu.in_vblank());
+    ppu.execute(&mut memory);
+    assert!(ppu.in_vblank());
+
+    // ...and not cleared until it's been run on the pre-render scanline, either
+    run_until(&mut ppu, &mut memory, -1, 1);
+    assert!(ppu.in_vblank());
+    ppu.execute(&mut memory);
+    assert!(!ppu.in_vblank());
+}
+
+#[test]
+fn reading_status_as_vblank_starts_suppresses_the_nmi()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x80);
+
+    // A dot early, the flag reads clear, and then it's never set
+    run_until(&mut ppu, &mut memory, 241, 1);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2002, false) & 0x80, 0);
+    ppu.execute(&mut memory);
+    assert!(!ppu.due_non_maskable_interrupt);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2002, true) & 0x80, 0);
+
+    // On the dot itself (next frame), it reads as set, but the NMI's still lost
+    run_until(&mut ppu, &mut memory, 241, 0);
+    run_until(&mut ppu, &mut memory, 241, 2);
+    assert!(ppu.due_non_maskable_interrupt);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2002, false) & 0x80, 0x80);
+    assert!(!ppu.due_non_maskable_interrupt);
+
+    // Any later and it's just a normal read
+    run_until(&mut ppu, &mut memory, 241, 0);
+    run_until(&mut ppu, &mut memory, 241, 3);
+    assert!(ppu.due_non_maskable_interrupt);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2002, false) & 0x80, 0x80);
+    assert!(ppu.due_non_maskable_interrupt);
+}
+
+#[test]
+fn enabling_nmi_during_vblank_fires_it()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // No NMI when v-blank begins, as it's disabled...
+    run_until(&mut ppu, &mut memory, 241, 10);
+    assert!(!ppu.due_non_maskable_interrupt);
+
+    // ...but turning it on whilst still in v-blank brings it about
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x80);
+    assert!(ppu.due_non_maskable_interrupt);
+
+    // Writing it again whilst on doesn't, but turning it off and on does
+    ppu.due_non_maskable_interrupt = false;
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x80);
+    assert!(!ppu.due_non_maskable_interrupt);
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x00);
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x80);
+    assert!(ppu.due_non_maskable_interrupt);
+
+    // Once v-blank's been acknowledged, there's nothing to fire
+    ppu.due_non_maskable_interrupt = false;
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x00);
+    ppu.read_byte_from_cpu(&mut memory, 0x2002, false);
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x80);
+    assert!(!ppu.due_non_maskable_interrupt);
+}
+
+#[test]
+fn registers_and_status_can_be_looked_at_without_side_effects()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    ppu.object_attribute_memory = [0xff; 256]; // All off screen, so nothing can overflow
+    ppu.write_byte_from_cpu(&mut memory, 0x2000, 0x90);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+    assert_eq!(ppu.get_control(), PpuControl::GENERATE_NMI | PpuControl::BACKROUND_PATTERN_ADDR);
+    assert!(ppu.get_mask().contains(PpuMask::SHOW_SPRITES | PpuMask::SHOW_BACKGROUND));
+
+    // Looking doesn't clear v-blank, unlike reading the register does
+    run_until(&mut ppu, &mut memory, 241, 2);
+    assert!(ppu.in_vblank());
+    assert!(ppu.in_vblank());
+    assert!(!ppu.sprite_zero_hit());
+    assert!(!ppu.sprite_overflow());
+
+    ppu.read_byte_from_cpu(&mut memory, 0x2002, false);
+    assert!(!ppu.in_vblank());
+}
+
+#[test]
+fn oam_data_writes_move_the_address_along()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    ppu.write_byte_from_cpu(&mut memory, 0x2003, 0x10);
+    for value in [0x11, 0x22, 0x33, 0x44] {
+        ppu.write_byte_from_cpu(&mut memory, 0x2004, value);
+    }
+    assert_eq!(ppu.object_attribute_memory[0x10..0x14], [0x11, 0x22, 0x33, 0x44]);
+
+    // Reads don't, though
+    ppu.write_byte_from_cpu(&mut memory, 0x2003, 0x11);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2004, false), 0x22);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2004, false), 0x22);
+
+    // Whilst rendering, writes are lost (with the address skipping a whole sprite), and reads early on in a line
+    // see secondary OAM being cleared
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x18);
+    run_until(&mut ppu, &mut memory, 10, 30);
+    ppu.write_byte_from_cpu(&mut memory, 0x2003, 0x10);
+    ppu.write_byte_from_cpu(&mut memory, 0x2004, 0x99);
+    assert_eq!(ppu.object_attribute_memory[0x10], 0x11);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2004, false), 0xff);
+
+    run_until(&mut ppu, &mut memory, 10, 100);
+    assert_eq!(ppu.read_byte_from_cpu(&mut memory, 0x2004, false), ppu.object_attribute_memory[0x14]);
+}
+
+fn pixel(ppu: &Ppu, x: usize, y: usize) -> [u8; 3]
+{
+    let i = (y * SCREEN_WIDTH + x) * 3;
+    [ppu.output[i], ppu.output[i + 1], ppu.output[i + 2]]
+}
+
+#[test]
+fn sprites_behind_the_background_only_show_through_where_its_transparent()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Tile 1 is solid, and used for a single background tile at the left of rows 96 and 112, with white for the
+    // background and red for sprites
+    memory.chr_rom[0x10..0x18].copy_from_slice(&[0xff; 8]);
+    for (address, value) in [(0x2180u16, 0x01u8), (0x21c0, 0x01), (0x3f00, 0x0f), (0x3f01, 0x30), (0x3f11, 0x16)]
+    {
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, (address >> 8) as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, address as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2007, value);
+    }
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+
+    // Both sprites half overlap their tile (x = 4), but only the second is in front of it (sprites are drawn
+    // one line below their Y)
+    ppu.object_attribute_memory[0..8].copy_from_slice(&[95, 0x01, 0x20, 4, 111, 0x01, 0x00, 4]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+
+    ppu.execute(&mut memory);
+    while !ppu.at_vblank_start() { ppu.execute(&mut memory); }
+
+    let Colour(red, green, blue) = PALETTE_TABLE[0x30];
+    let background = [red, green, blue];
+    let Colour(red, green, blue) = PALETTE_TABLE[0x16];
+    let sprite = [red, green, blue];
+
+    // Behind: the opaque background wins, but the sprite still shows over the transparent part
+    assert_eq!(pixel(&ppu, 2, 100), background);
+    assert_eq!(pixel(&ppu, 6, 100), background);
+    assert_eq!(pixel(&ppu, 10, 100), sprite);
+
+    // In front: the sprite wins everywhere it's opaque
+    assert_eq!(pixel(&ppu, 2, 116), background);
+    assert_eq!(pixel(&ppu, 6, 116), sprite);
+    assert_eq!(pixel(&ppu, 10, 116), sprite);
+}
+
+#[test]
+fn sprite_backdrop_entries_alias_the_background_ones()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Writing 0x3f10 is writing the universal backdrop at 0x3f00, and reading either gives the same thing
+    ppu.write_byte_from_ppu(&mut memory, 0x3f10, 0x21);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f00), 0x21);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f10), 0x21);
+
+    // And the other way round, along with the other three (whose 0x3f0x entries are never drawn, but are kept)
+    for (address, mirror) in [(0x3f00, 0x3f10), (0x3f04, 0x3f14), (0x3f08, 0x3f18), (0x3f0c, 0x3f1c)]
+    {
+        ppu.write_byte_from_ppu(&mut memory, address, address as u8);
+        assert_eq!(ppu.read_byte_from_ppu(&mut memory, mirror), address as u8);
+    }
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f00), 0x00);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f04), 0x04);
+
+    // Every other sprite entry is its own
+    ppu.write_byte_from_ppu(&mut memory, 0x3f11, 0x16);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f01), 0x00);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3f11), 0x16);
+
+    // All of which repeats up to 0x3fff
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3ff0), 0x00);
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x3ff1), 0x16);
+}
+
+#[test]
+fn tile_usage_counts_what_the_name_tables_refer_to()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Tile 5 in two places, with an attribute byte that would look like tile 7 if it were wrongly counted
+    for (address, value) in [(0x2000u16, 0x05u8), (0x2001, 0x05), (0x23c0, 0x07)]
+    {
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, (address >> 8) as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2006, address as u8);
+        ppu.write_byte_from_cpu(&mut memory, 0x2007, value);
+    }
+
+    // Every name table's looked at, mirrors and all, so the two uses are counted twice
+    let usage = ppu.get_tile_usage(&mut memory);
+    assert_eq!(usage[5], 4);
+    assert_eq!(usage[7], 0);
+    assert_eq!(usage.iter().sum::<u32>(), 0x3c0 * 4);
+
+    // Tile 0 (the top-left of the heatmap) is used all over, and tile 1 (just to its right) not at all
+    let heatmap = ppu.get_tile_heatmap(&mut memory, 0, None);
+    let pixel = |x: usize| &heatmap[x * 3..x * 3 + 3];
+    assert!(pixel(0)[0] >= 0x80);
+    assert!(pixel(8).iter().all(|channel| *channel < 0x80));
+}
+
+fn draw_ten_sprites_in_a_row(sprite_limit: bool) -> (Ppu, Memory)
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    ppu.sprite_limit = sprite_limit;
+
+    // Tile 1 is solid and red, and everything else in OAM is off screen
+    memory.chr_rom[0x10..0x18].copy_from_slice(&[0xff; 8]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x3f);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x11);
+    ppu.write_byte_from_cpu(&mut memory, 0x2007, 0x16);
+    ppu.object_attribute_memory = [0xff; 256];
+    for i in 0..10 {
+        ppu.object_attribute_memory[i * 4..i * 4 + 4].copy_from_slice(&[99, 0x01, 0x00, i as u8 * 16]);
+    }
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+
+    ppu.execute(&mut memory);
+    while !ppu.at_vblank_start() { ppu.execute(&mut memory); }
+    (ppu, memory)
+}
+
+#[test]
+fn only_eight_sprites_are_drawn_per_scanline_unless_the_limit_is_off()
+{
+    let Colour(red, green, blue) = PALETTE_TABLE[0x16];
+    let sprite = [red, green, blue];
+
+    // The last two go missing, and the overflow flag's set
+    let (ppu, _) = draw_ten_sprites_in_a_row(true);
+    assert!((0..8).all(|i| pixel(&ppu, i * 16 + 4, 100) == sprite));
+    assert!((8..10).all(|i| pixel(&ppu, i * 16 + 4, 100) != sprite));
+    assert!(ppu.sprite_overflow());
+
+    // Whereas without the limit, they're all there, and there's nothing to overflow
+    let (ppu, _) = draw_ten_sprites_in_a_row(false);
+    assert!((0..10).all(|i| pixel(&ppu, i * 16 + 4, 100) == sprite));
+    assert!(!ppu.sprite_overflow());
+}
+
+#[test]
+fn sprites_are_drawn_for_all_eight_of_their_columns()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // The same solid red tile as above, once in the middle of the screen and once hanging off its right edge
+    memory.chr_rom[0x10..0x18].copy_from_slice(&[0xff; 8]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x3f);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x11);
+    ppu.write_byte_from_cpu(&mut memory, 0x2007, 0x16);
+    ppu.object_attribute_memory = [0xff; 256];
+    ppu.object_attribute_memory[0..4].copy_from_slice(&[99, 0x01, 0x00, 40]);
+    ppu.object_attribute_memory[4..8].copy_from_slice(&[99, 0x01, 0x00, 252]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+
+    ppu.execute(&mut memory);
+    while !ppu.at_vblank_start() { ppu.execute(&mut memory); }
+
+    let Colour(red, green, blue) = PALETTE_TABLE[0x16];
+    let sprite = [red, green, blue];
+
+    // Every column of every row it covers, without spilling over to either side
+    for y in 100..108
+    {
+        assert!((40..48).all(|x| pixel(&ppu, x, y) == sprite), "row {}", y);
+        assert_ne!(pixel(&ppu, 39, y), sprite);
+        assert_ne!(pixel(&ppu, 48, y), sprite);
+        assert!((252..256).all(|x| pixel(&ppu, x, y) == sprite), "row {}", y);
+        assert_ne!(pixel(&ppu, 251, y), sprite);
+    }
+    assert!((40..48).all(|x| pixel(&ppu, x, 99) != sprite && pixel(&ppu, x, 108) != sprite));
+}
+
+#[test]
+fn skipped_frames_are_not_drawn_but_still_run()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    ppu.frame_skip = 1;
+
+    // Sprite zero is a solid tile, right over a solid background tile, so that it hits every frame
+    memory.chr_rom[0x10..0x18].copy_from_slice(&[0xff; 8]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x21);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x82);
+    ppu.write_byte_from_cpu(&mut memory, 0x2007, 0x01);
+    ppu.object_attribute_memory = [0xff; 256];
+    ppu.object_attribute_memory[0..4].copy_from_slice(&[95, 0x01, 0x00, 16]);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x1e);
+
+    let set_backdrop = |ppu: &mut Ppu, memory: &mut Memory, colour: u8|
+    {
+        ppu.write_byte_from_cpu(memory, 0x2006, 0x3f);
+        ppu.write_byte_from_cpu(memory, 0x2006, 0x00);
+        ppu.write_byte_from_cpu(memory, 0x2007, colour);
+        ppu.write_byte_from_cpu(memory, 0x2006, 0);
+        ppu.write_byte_from_cpu(memory, 0x2006, 0);
+    };
+    let run_frame = |ppu: &mut Ppu, memory: &mut Memory|
+    {
+        // Up to and including the dot that v-blank starts on
+        ppu.output_dirty = false;
+        while !ppu.at_vblank_start() { ppu.execute(memory); }
+        ppu.execute(memory);
+    };
+    let colour = |index: usize| { let Colour(red, green, blue) = PALETTE_TABLE[index]; [red, green, blue] };
+
+    // The first frame's drawn...
+    set_backdrop(&mut ppu, &mut memory, 0x21);
+    run_frame(&mut ppu, &mut memory);
+    assert!(ppu.output_dirty);
+    assert_eq!(pixel(&ppu, 200, 200), colour(0x21));
+
+    // ...the second isn't, though sprite zero still hits...
+    set_backdrop(&mut ppu, &mut memory, 0x16);
+    run_frame(&mut ppu, &mut memory);
+    assert!(!ppu.output_dirty);
+    assert!(ppu.sprite_zero_hit());
+    assert_eq!(pixel(&ppu, 200, 200), colour(0x21));
+
+    // ...and the third is again
+    run_frame(&mut ppu, &mut memory);
+    assert!(ppu.output_dirty);
+    assert_eq!(pixel(&ppu, 200, 200), colour(0x16));
+}
+
+#[test]
+fn mirroring_can_be_overridden()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+    assert_eq!(memory.current_mirroring(), Mirroring::Horizontal);
+
+    // Which of 0x2000, 0x2400, 0x2800 and 0x2c00 see a write to each of the others
+    let mut shared_with = |memory: &mut Memory, mirroring: Mirroring| -> [[bool; 4]; 4]
+    {
+        memory.mirroring_override = Some(mirroring);
+        let mut shared = [[false; 4]; 4];
+        for (from, row) in shared.iter_mut().enumerate()
+        {
+            for table in 0..4 { ppu.write_byte_from_ppu(memory, 0x2000 + table * 0x400, 0); }
+            ppu.write_byte_from_ppu(memory, 0x2000 + from as u16 * 0x400, 0xaa);
+            for (to, shared) in row.iter_mut().enumerate() {
+                *shared = ppu.read_byte_from_ppu(memory, 0x2000 + to as u16 * 0x400) == 0xaa;
+            }
+        }
+        shared
+    };
+
+    let (t, f) = (true, false);
+    assert_eq!(shared_with(&mut memory, Mirroring::Horizontal), [[t, t, f, f], [t, t, f, f], [f, f, t, t], [f, f, t, t]]);
+    assert_eq!(shared_with(&mut memory, Mirroring::Vertical), [[t, f, t, f], [f, t, f, t], [t, f, t, f], [f, t, f, t]]);
+    assert_eq!(shared_with(&mut memory, Mirroring::SingleScreenLower), [[t; 4]; 4]);
+    assert_eq!(shared_with(&mut memory, Mirroring::SingleScreenUpper), [[t; 4]; 4]);
+    assert_eq!(shared_with(&mut memory, Mirroring::FourScreen), [[t, f, f, f], [f, t, f, f], [f, f, t, f], [f, f, f, t]]);
+
+    // Going back to the cartridge's
+    memory.mirroring_override = None;
+    assert_eq!(memory.current_mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn four_screen_cartridges_ignore_the_mirroring_bit()
+{
+    let mut rom = build_rom(&[]);
+    rom[6] |= 0x09;
+    let memory = load_rom(&rom).unwrap();
+    assert_eq!(memory.rom_header.get_mirroring(), Mirroring::FourScreen);
+    assert_eq!(memory.current_mirroring(), Mirroring::FourScreen);
+}
+
+#[test]
+fn the_test_pattern_has_bars_a_grid_and_a_border()
+{
+    let mut memory = Memory::from_blank_cartridge();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    ppu.output_dirty = false;
+    ppu.draw_test_pattern();
+    assert!(ppu.output_dirty);
+
+    let colour = |index: u8| { let Colour(red, green, blue) = PALETTE_TABLE[index as usize]; [red, green, blue] };
+    let bar_width = SCREEN_WIDTH / TEST_PATTERN_BARS.len();
+
+    // Each bar, away from the grid lines
+    for (i, bar) in TEST_PATTERN_BARS.iter().enumerate() {
+        assert_eq!(pixel(&ppu, i * bar_width + bar_width / 2 + 1, 100 + 1), colour(*bar));
+    }
+
+    // The grid, and the border around it
+    assert_eq!(pixel(&ppu, TEST_PATTERN_GRID_SIZE, 100 + 1), colour(0x00));
+    assert_eq!(pixel(&ppu, 100 + 1, TEST_PATTERN_GRID_SIZE), colour(0x00));
+    assert!((0..SCREEN_WIDTH).all(|x| pixel(&ppu, x, 0) == colour(0x30) && pixel(&ppu, x, SCREEN_HEIGHT - 1) == colour(0x30)));
+    assert!((0..SCREEN_HEIGHT).all(|y| pixel(&ppu, 0, y) == colour(0x30) && pixel(&ppu, SCREEN_WIDTH - 1, y) == colour(0x30)));
+
+    // There's nothing on the cartridge to draw over it with
+    assert!(memory.chr_rom.iter().all(|byte| *byte == 0));
+    assert_eq!(ppu.read_byte_from_ppu(&mut memory, 0x0000), 0);
+}
+
+#[test]
+fn scanlines_can_be_rendered_one_at_a_time()
+{
+    let mut memory = load_rom(&build_rom(&[])).unwrap();
+    let mut ppu = Ppu::from_region(Region::Ntsc);
+
+    // Nothing but a red backdrop, so each line's red once it's been drawn
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x3f);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0x00);
+    ppu.write_byte_from_cpu(&mut memory, 0x2007, 0x16);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2006, 0);
+    ppu.write_byte_from_cpu(&mut memory, 0x2001, 0x0a);
+
+    let Colour(red, green, blue) = PALETTE_TABLE[0x16];
+    let backdrop = [red, green, blue];
+
+    run_until(&mut ppu, &mut memory, 10, 0);
+    ppu.render_scanline(&mut memory);
+    assert_eq!((ppu.get_scanline(), ppu.get_dot()), (11, 0));
+    assert!((0..SCREEN_WIDTH).all(|x| pixel(&ppu, x, 10) == backdrop));
+    assert_ne!(pixel(&ppu, 0, 11), backdrop);
+
+    // Starting partway along just finishes the line
+    run_until(&mut ppu, &mut memory, 11, 100);
+    ppu.render_scanline(&mut memory);
+    assert_eq!((ppu.get_scanline(), ppu.get_dot()), (12, 0));
+    assert!((0..SCREEN_WIDTH).all(|x| pixel(&ppu, x, 11) == backdrop));
+}
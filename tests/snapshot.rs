@@ -0,0 +1,152 @@
+mod common;
+
+use common::{build_rom, load_rom};
+use nes_emulator_rust::{on_emulation_cycle, on_emulation_tick};
+use nes_emulator_rust::cheat::Cheat;
+use nes_emulator_rust::cpu::Cpu;
+use nes_emulator_rust::error::EmuError;
+use nes_emulator_rust::memory::{Memory, Mirroring};
+use nes_emulator_rust::ppu::Ppu;
+use nes_emulator_rust::snapshot::Snapshot;
+
+// Counts frames in RAM off the NMI, with the background on, so there's always something changing
+fn busy_rom() -> Vec<u8>
+{
+    let mut rom = build_rom(&[
+        0xa9, 0x80, 0x8d, 0x00, 0x20,   // LDA #$80, STA $2000 (NMI on)
+        0xa9, 0x0a, 0x8d, 0x01, 0x20,   // LDA #$0a, STA $2001 (background on)
+        0xe8,                           // INX (at 0x800a)
+        0x4c, 0x0a, 0x80,               // JMP $800a
+    ]);
+
+    // NMI handler at 0x8020: INC $10, then write the count into the name table and the backdrop
+    rom[16 + 0x20..16 + 0x20 + 26].copy_from_slice(&[
+        0xe6, 0x10,                     // INC $10
+        0xa9, 0x3f, 0x8d, 0x06, 0x20,   // LDA #$3f, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20,   // LDA #$00, STA $2006
+        0xa5, 0x10, 0x29, 0x3f,         // LDA $10, AND #$3f
+        0x8d, 0x07, 0x20,               // STA $2007
+        0x8d, 0x05, 0x20,               // STA $2005
+        0x8d, 0x05, 0x20,               // STA $2005
+        0x40,                           // RTI
+    ]);
+    rom[16 + 0x3ffa] = 0x20;
+    rom[16 + 0x3ffb] = 0x80;
+    rom
+}
+
+fn run_machine(rom: &[u8]) -> (Cpu, Ppu, Memory)
+{
+    let mut memory = load_rom(rom).unwrap();
+    let mut ppu = Ppu::from_region(memory.rom_header.get_region());
+    let cpu = Cpu::from_memory(&mut ppu, &mut memory).unwrap();
+    (cpu, ppu, memory)
+}
+
+fn run_frames(cpu: &mut Cpu, ppu: &mut Ppu, memory: &mut Memory, frames: usize)
+{
+    for _ in 0..frames { on_emulation_cycle(cpu, ppu, memory).unwrap(); }
+}
+
+#[test]
+fn saved_states_carry_on_exactly_where_they_left_off()
+{
+    // Save partway through a frame, so that everything's mid-flow...
+    let rom = busy_rom();
+    let (mut cpu, mut ppu, mut memory) = run_machine(&rom);
+    run_frames(&mut cpu, &mut ppu, &mut memory, 10);
+    for _ in 0..12345 { on_emulation_tick(&mut cpu, &mut ppu, &mut memory).unwrap(); }
+    let bytes = Snapshot::from_machine(&cpu, &ppu, &memory).to_bytes(memory.get_rom_hash());
+
+    // ...then load it into a machine that's only just been turned on, and run both for a while
+    let (mut loaded_cpu, mut loaded_ppu, mut loaded_memory) = run_machine(&rom);
+    Snapshot::from_bytes(&bytes, &loaded_cpu, &loaded_ppu, &loaded_memory).unwrap()
+        .restore(&mut loaded_cpu, &mut loaded_ppu, &mut loaded_memory);
+    assert_eq!(loaded_memory.ram[0x10], memory.ram[0x10]);
+
+    run_frames(&mut cpu, &mut ppu, &mut memory, 5);
+    run_frames(&mut loaded_cpu, &mut loaded_ppu, &mut loaded_memory, 5);
+
+    assert!(memory.ram[0x10] >= 15);
+    assert!(loaded_memory.ram == memory.ram);
+    assert_eq!(loaded_ppu.get_output_hash(), ppu.get_output_hash());
+    assert_eq!(loaded_ppu.dump_vram(), ppu.dump_vram());
+    assert_eq!((loaded_cpu.pc, loaded_cpu.total_cycles), (cpu.pc, cpu.total_cycles));
+    assert_eq!((loaded_ppu.get_scanline(), loaded_ppu.get_dot(), loaded_ppu.frame_count), (ppu.get_scanline(), ppu.get_dot(), ppu.frame_count));
+
+    // Saving it again gives the very same bytes
+    assert_eq!(Snapshot::from_machine(&loaded_cpu, &loaded_ppu, &loaded_memory).to_bytes(loaded_memory.get_rom_hash()),
+        Snapshot::from_machine(&cpu, &ppu, &memory).to_bytes(memory.get_rom_hash()));
+}
+
+#[test]
+fn saved_states_from_another_version_or_rom_are_turned_away()
+{
+    let rom = busy_rom();
+    let (mut cpu, mut ppu, mut memory) = run_machine(&rom);
+    run_frames(&mut cpu, &mut ppu, &mut memory, 3);
+    let bytes = Snapshot::from_machine(&cpu, &ppu, &memory).to_bytes(memory.get_rom_hash());
+    let reason = |result: Result<Snapshot, EmuError>| match result { Err(EmuError::BadState(reason)) => reason, _ => panic!("state should have been turned away") };
+
+    // A newer (or older) version
+    let mut other_version = bytes.clone();
+    other_version[4] += 1;
+    assert_eq!(reason(Snapshot::from_bytes(&other_version, &cpu, &ppu, &memory)), "Save state is version 2, but only version 1 is supported");
+
+    // Another game
+    let mut other_rom = rom.clone();
+    other_rom[16 + 0x1000] = 0xff;
+    let (other_cpu, other_ppu, other_memory) = run_machine(&other_rom);
+    assert_eq!(reason(Snapshot::from_bytes(&bytes, &other_cpu, &other_ppu, &other_memory)), "Save state was made with a different ROM");
+
+    // Not a state at all, or only part of one
+    assert_eq!(reason(Snapshot::from_bytes(&rom, &cpu, &ppu, &memory)), "File is not a save state");
+    assert_eq!(reason(Snapshot::from_bytes(&bytes[..bytes.len() - 1], &cpu, &ppu, &memory)), "Save state is truncated");
+    assert!(Snapshot::from_bytes(&bytes, &cpu, &ppu, &memory).is_ok());
+}
+
+#[test]
+fn restoring_leaves_the_users_ppu_settings_alone()
+{
+    let (cpu, mut ppu, memory) = run_machine(&busy_rom());
+    let snapshot = Snapshot::from_machine(&cpu, &ppu, &memory);
+
+    ppu.sprite_limit = !ppu.sprite_limit;
+    ppu.frame_skip = 2;
+    ppu.restore(&snapshot.ppu);
+    assert_eq!(ppu.sprite_limit, !snapshot.ppu.sprite_limit);
+    assert_eq!(ppu.frame_skip, 2);
+}
+
+#[test]
+fn restoring_leaves_the_users_memory_settings_alone()
+{
+    let (cpu, ppu, mut memory) = run_machine(&busy_rom());
+    let snapshot = Snapshot::from_machine(&cpu, &ppu, &memory);
+
+    memory.bus_conflicts = !memory.bus_conflicts;
+    memory.mirroring_override = Some(Mirroring::SingleScreenLower);
+    memory.apu.filters_enabled = !memory.apu.filters_enabled;
+    memory.restore(&snapshot.memory);
+    assert_eq!(memory.bus_conflicts, !snapshot.memory.bus_conflicts);
+    assert_eq!(memory.mirroring_override, Some(Mirroring::SingleScreenLower));
+    assert_eq!(memory.apu.filters_enabled, !snapshot.memory.apu.filters_enabled);
+}
+
+#[test]
+fn rebuilding_the_machine_can_keep_the_users_memory_settings()
+{
+    let rom = busy_rom();
+    let (_, _, mut memory) = run_machine(&rom);
+    memory.cheats.push(Cheat::from_code("SXIOPO").unwrap());
+    memory.bus_conflicts = !memory.bus_conflicts;
+    memory.mirroring_override = Some(Mirroring::SingleScreenLower);
+    memory.apu.filters_enabled = !memory.apu.filters_enabled;
+
+    let (_, _, mut reloaded) = run_machine(&rom);
+    reloaded.keep_settings_from(&mut memory);
+    assert_eq!(reloaded.cheats.len(), 1);
+    assert_eq!(reloaded.bus_conflicts, memory.bus_conflicts);
+    assert_eq!(reloaded.mirroring_override, Some(Mirroring::SingleScreenLower));
+    assert_eq!(reloaded.apu.filters_enabled, memory.apu.filters_enabled);
+}